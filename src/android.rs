@@ -0,0 +1,44 @@
+//! Android's logcat, through liblog.
+
+use crate::frame;
+use log::{Level, Record};
+use std::io;
+use std::os::raw::{c_char, c_int};
+
+#[link(name = "log")]
+extern "C" {
+    fn __android_log_write(prio: c_int, tag: *const c_char, text: *const c_char) -> c_int;
+}
+
+/// Format a record as a frame, with the target as the tag
+///
+/// # Arguments
+/// * `out` - Buffer to format into
+/// * `record` - Record to write
+pub(crate) fn write_frame(out: &mut Vec<u8>, record: &Record) -> io::Result<()> {
+    // priorities from android/log.h
+    let priority = match record.level() {
+        Level::Error => 6,
+        Level::Warn => 5,
+        Level::Info => 4,
+        Level::Debug => 3,
+        Level::Trace => 2,
+    };
+    frame::write(out, priority, record)
+}
+
+/// Write a frame made by `write_frame` to logcat
+pub(crate) fn send(frame: &[u8]) -> io::Result<()> {
+    let frame = frame::parse(frame)?;
+    let res = unsafe {
+        __android_log_write(
+            c_int::from(frame.level),
+            frame.tag.as_ptr(),
+            frame.text.as_ptr(),
+        )
+    };
+    match res {
+        res if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+        _ => Ok(()),
+    }
+}
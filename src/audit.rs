@@ -0,0 +1,118 @@
+//! Audit trail, written apart from regular records.
+//!
+//! Audit events record who did what to which resource. Unlike regular
+//! records they are never filtered by level, sampled or dropped: each event
+//! is written to its own file as a line of ndjson and synced to disk before
+//! `femme::audit` returns, so an event that was acknowledged survives a crash.
+
+use crate::error::AuditError;
+use crate::stats::SinkCounters;
+use crate::{escape, json, time};
+use log::kv;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+
+/// Fields every audit event must carry
+const REQUIRED: [&str; 3] = ["actor", "action", "resource"];
+
+static AUDIT: OnceLock<Trail> = OnceLock::new();
+
+/// The file audit events are written to
+pub(crate) struct Trail {
+    file: Mutex<File>,
+    counters: Arc<SinkCounters>,
+}
+
+impl Trail {
+    /// Open the audit file, appending to it and creating it if needed
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let file = open_file(path)?;
+        Ok(Trail {
+            file: Mutex::new(file),
+            counters: SinkCounters::register(format!("audit:{}", path.display())),
+        })
+    }
+}
+
+/// Check that the audit file can be opened, without registering a trail
+pub(crate) fn check(path: &Path) -> io::Result<()> {
+    open_file(path).map(drop)
+}
+
+fn open_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new().append(true).create(true).open(path)
+}
+
+/// Make the trail the destination of `femme::audit`
+///
+/// Only the first trail is kept, like only the first logger is.
+pub(crate) fn install(trail: Trail) {
+    let _ = AUDIT.set(trail);
+}
+
+/// Record an audit event
+///
+/// `fields` must contain `actor`, `action` and `resource`, and may carry any
+/// other detail. The event is written to the file configured with
+/// `audit_file`, regardless of the level filters, and synced to disk before
+/// this function returns.
+///
+/// # Examples
+/// ```
+/// # let path = std::env::temp_dir().join("audit-example.log");
+/// femme::pretty().audit_file(&path).finish().unwrap();
+///
+/// femme::audit(
+///     "role granted",
+///     &[
+///         ("actor", "alice".into()),
+///         ("action", "grant".into()),
+///         ("resource", "role:admin".into()),
+///         ("grantee", "bob".into()),
+///     ],
+/// )
+/// .unwrap();
+///
+/// let missing = femme::audit("role granted", &[("actor", "alice".into())]);
+/// assert_eq!(missing.unwrap_err().to_string(), "audit event is missing `action`");
+/// ```
+pub fn audit(event: &str, fields: &[(&str, kv::Value)]) -> Result<(), AuditError> {
+    let trail = AUDIT.get().ok_or(AuditError::NotConfigured)?;
+    if let Some(missing) = REQUIRED
+        .iter()
+        .find(|required| !fields.iter().any(|(key, _)| key == *required))
+    {
+        return Err(AuditError::MissingField(missing));
+    }
+
+    let mut buf = Vec::new();
+    format(&mut buf, event, fields).map_err(AuditError::Io)?;
+
+    let res = {
+        let mut file = trail.file.lock().unwrap_or_else(PoisonError::into_inner);
+        file.write_all(&buf).and_then(|_| file.sync_data())
+    };
+    trail.counters.record(&res);
+    res.map_err(AuditError::Io)
+}
+
+/// Format an audit event as a line of ndjson
+fn format(out: &mut Vec<u8>, event: &str, fields: &[(&str, kv::Value)]) -> io::Result<()> {
+    write!(out, "{{\"level\":30,\"time\":")?;
+    time::write_millis(out, time::now())?;
+    write!(out, ",\"msg\":")?;
+    escape::json_str(out, event)?;
+    write!(out, ",\"audit\":true")?;
+    for (key, val) in fields {
+        write!(out, ",")?;
+        match json::RESERVED.contains(key) || *key == "audit" {
+            true => escape::json_str(out, format_args!("_{}", key))?,
+            false => escape::json_str(out, key)?,
+        }
+        write!(out, ":")?;
+        escape::json_value(out, val)?;
+    }
+    out.write_all(json::TERMINATOR)
+}
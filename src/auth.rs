@@ -0,0 +1,198 @@
+//! Authentication of the requests HTTP sinks make.
+//!
+//! Managed log services each want credentials of some kind with every push:
+//! a bearer token, a user and password, headers of their own, or an AWS
+//! signature. They're added to each request as it's sent, so tokens that
+//! expire can be renewed by a callback.
+
+#[cfg(feature = "aws")]
+use crate::sigv4::{self, Credentials, Scope};
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+/// Makes the headers that authenticate a request
+type HeaderFn = dyn Fn() -> io::Result<Vec<(String, String)>> + Send + Sync;
+
+/// How requests to HTTP sinks authenticate, see `Femme::http_auth`
+///
+/// Credentials sent to an `http://` URL are only as private as the network,
+/// or the proxy, they cross; `https://` needs the `tls` feature.
+#[derive(Clone, Default)]
+pub enum HttpAuth {
+    /// Send requests without credentials
+    #[default]
+    None,
+
+    /// An `Authorization: Bearer` token, such as an API key
+    Bearer(String),
+
+    /// HTTP basic authentication with a user and password
+    Basic {
+        /// Name of the user
+        user: String,
+
+        /// Their password
+        password: String,
+    },
+
+    /// Headers made for each request, see `HttpAuth::headers`
+    Headers(Arc<HeaderFn>),
+
+    /// AWS Signature Version 4, with the credentials in `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`, read anew for each
+    /// request; requires the `aws` feature
+    #[cfg(feature = "aws")]
+    SigV4 {
+        /// Region of the service, such as `eu-west-1`
+        region: String,
+
+        /// Name of the service in signatures, such as `es` or `aps`
+        service: String,
+    },
+}
+
+impl HttpAuth {
+    /// Authenticate with a bearer token
+    pub fn bearer(token: impl Into<String>) -> Self {
+        HttpAuth::Bearer(token.into())
+    }
+
+    /// Authenticate with a user and password
+    pub fn basic(user: impl Into<String>, password: impl Into<String>) -> Self {
+        HttpAuth::Basic {
+            user: user.into(),
+            password: password.into(),
+        }
+    }
+
+    /// Authenticate with headers made for each request
+    ///
+    /// The callback runs on the thread sending batches, before each attempt,
+    /// so it can hand out a token it renews. A request it fails for isn't
+    /// sent, and is retried like a failed one.
+    ///
+    /// # Examples
+    /// ```
+    /// use femme::HttpAuth;
+    ///
+    /// let auth = HttpAuth::headers(|| {
+    ///     let tenant = std::env::var("TENANT").unwrap_or_else(|_| "dev".into());
+    ///     Ok(vec![("X-Scope-OrgID".to_string(), tenant)])
+    /// });
+    /// femme::loki("http://localhost:3100").http_auth(auth);
+    /// ```
+    pub fn headers<F>(headers: F) -> Self
+    where
+        F: Fn() -> io::Result<Vec<(String, String)>> + Send + Sync + 'static,
+    {
+        HttpAuth::Headers(Arc::new(headers))
+    }
+
+    /// Sign requests for an AWS service with Signature Version 4
+    #[cfg(feature = "aws")]
+    pub fn sigv4(region: impl Into<String>, service: impl Into<String>) -> Self {
+        HttpAuth::SigV4 {
+            region: region.into(),
+            service: service.into(),
+        }
+    }
+
+    /// The headers authenticating a POST
+    ///
+    /// # Arguments
+    /// * `host` - Value of the `Host` header
+    /// * `path` - Path and query of the request
+    /// * `headers` - The other headers of the request
+    /// * `body` - The body
+    pub(crate) fn apply(
+        &self,
+        host: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<Vec<(String, String)>> {
+        // only signatures cover the request itself
+        #[cfg(not(feature = "aws"))]
+        let _ = (host, path, headers, body);
+        match self {
+            HttpAuth::None => Ok(Vec::new()),
+            HttpAuth::Bearer(token) => Ok(vec![(
+                "Authorization".to_string(),
+                format!("Bearer {}", token),
+            )]),
+            HttpAuth::Basic { user, password } => {
+                let credentials = crate::bytes::base64(format!("{}:{}", user, password).as_bytes());
+                Ok(vec![(
+                    "Authorization".to_string(),
+                    format!("Basic {}", credentials),
+                )])
+            }
+            HttpAuth::Headers(headers) => headers(),
+            #[cfg(feature = "aws")]
+            HttpAuth::SigV4 { region, service } => {
+                let scope = Scope {
+                    region,
+                    service,
+                    time: crate::time::now(),
+                };
+                let credentials = Credentials::from_env()?;
+                Ok(sigv4::sign(
+                    &credentials,
+                    &scope,
+                    "POST",
+                    host,
+                    path,
+                    headers,
+                    body,
+                ))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for HttpAuth {
+    /// Names the kind of authentication, never the credentials
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpAuth::None => f.write_str("None"),
+            HttpAuth::Bearer(_) => f.write_str("Bearer(..)"),
+            HttpAuth::Basic { user, .. } => f.debug_struct("Basic").field("user", user).finish(),
+            HttpAuth::Headers(_) => f.write_str("Headers(..)"),
+            #[cfg(feature = "aws")]
+            HttpAuth::SigV4 { region, service } => f
+                .debug_struct("SigV4")
+                .field("region", region)
+                .field("service", service)
+                .finish(),
+        }
+    }
+}
+
+impl PartialEq for HttpAuth {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (HttpAuth::None, HttpAuth::None) => true,
+            (HttpAuth::Bearer(a), HttpAuth::Bearer(b)) => a == b,
+            (
+                HttpAuth::Basic { user, password },
+                HttpAuth::Basic {
+                    user: other_user,
+                    password: other_password,
+                },
+            ) => user == other_user && password == other_password,
+            (HttpAuth::Headers(a), HttpAuth::Headers(b)) => Arc::ptr_eq(a, b),
+            #[cfg(feature = "aws")]
+            (
+                HttpAuth::SigV4 { region, service },
+                HttpAuth::SigV4 {
+                    region: other_region,
+                    service: other_service,
+                },
+            ) => region == other_region && service == other_service,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for HttpAuth {}
@@ -0,0 +1,265 @@
+//! Records sent in batches, from a thread of their own.
+//!
+//! Formatted records are queued and joined into the body of a request,
+//! between the prefix and suffix of an envelope and separated by commas,
+//! such as `{"streams":[` and `]}`. A batch is sent once it holds enough
+//! records or bytes, once its oldest record has waited long enough, or on
+//! flush, as its `Limits` say. A batch that fails to send is retried a few
+//! times, then dropped; one that was refused, failing with an error of kind
+//! `InvalidInput`, is dropped right away.
+
+use crate::codec::Compression;
+use crate::diagnostics;
+use crate::{HttpAuth, Proxy};
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Most records in a batch, by default
+pub(crate) const DEFAULT_MAX_RECORDS: usize = 512;
+
+/// Most bytes of records in a batch, by default
+pub(crate) const DEFAULT_MAX_BYTES: usize = 1024 * 1024;
+
+/// Longest a record waits for its batch to be sent, by default
+pub(crate) const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// Records queued while a batch is being sent, beyond which they're dropped
+const QUEUE: usize = 8192;
+
+/// Attempts at sending a batch, and the delay before the first retry, which
+/// doubles with each one
+const ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Sends the body of a batch, with its `Content-Encoding` if it's compressed,
+/// authenticated and through the proxy the sink says
+pub(crate) type Send =
+    Box<dyn FnMut(&[u8], Option<&str>, &HttpAuth, &Proxy) -> io::Result<()> + std::marker::Send>;
+
+/// What surrounds the records of a batch
+pub(crate) struct Envelope {
+    pub(crate) prefix: Vec<u8>,
+    pub(crate) suffix: Vec<u8>,
+}
+
+/// When a batch is sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Limits {
+    /// Most records in a batch
+    pub(crate) max_records: usize,
+
+    /// Most bytes of records in a batch, unless a single record is larger
+    pub(crate) max_bytes: usize,
+
+    /// Longest a record waits for its batch to be sent
+    pub(crate) max_delay: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_records: DEFAULT_MAX_RECORDS,
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_delay: DEFAULT_MAX_DELAY,
+        }
+    }
+}
+
+enum Message {
+    Record(Vec<u8>),
+    Flush(SyncSender<()>),
+    Configure(Limits, Compression, HttpAuth, Proxy),
+}
+
+/// The queue of a batching thread
+pub(crate) struct Batcher {
+    tx: SyncSender<Message>,
+}
+
+impl Batcher {
+    /// Start the thread sending batches
+    ///
+    /// # Arguments
+    /// * `name` - Name of the destination, for reports
+    /// * `envelope` - What surrounds the records of a batch
+    /// * `send` - Sends the body of a batch
+    pub(crate) fn start(name: String, envelope: Envelope, send: Send) -> io::Result<Self> {
+        let (tx, rx) = mpsc::sync_channel(QUEUE);
+        thread::Builder::new()
+            .name("femme-batch".into())
+            .spawn(move || run(rx, &name, &envelope, send))?;
+        Ok(Batcher { tx })
+    }
+
+    /// Replace the default limits, compression, authentication and proxy,
+    /// before any record is queued
+    pub(crate) fn configure(
+        &self,
+        limits: Limits,
+        compression: Compression,
+        auth: HttpAuth,
+        proxy: Proxy,
+    ) {
+        let _ = self
+            .tx
+            .send(Message::Configure(limits, compression, auth, proxy));
+    }
+
+    /// Queue a formatted record, without its trailing newline
+    pub(crate) fn push(&self, record: Vec<u8>) -> io::Result<()> {
+        match self.tx.try_send(Message::Record(record)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(io::Error::other("the batch queue is full")),
+            Err(TrySendError::Disconnected(_)) => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    /// Send the records queued so far, waiting until they're sent or dropped
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        let (ack, done) = mpsc::sync_channel(1);
+        self.tx
+            .send(Message::Flush(ack))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        done.recv()
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+fn run(rx: mpsc::Receiver<Message>, name: &str, envelope: &Envelope, mut send: Send) {
+    let mut limits = Limits::default();
+    let mut compression = Compression::None;
+    let mut auth = HttpAuth::None;
+    let mut proxy = Proxy::Env;
+    let mut records: Vec<Vec<u8>> = Vec::new();
+    let mut bytes = 0;
+    let mut deadline: Option<Instant> = None;
+    loop {
+        let message = match deadline {
+            Some(deadline) => rx.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match message {
+            Ok(Message::Record(record)) => {
+                // a record that would overflow the batch starts the next one
+                if !records.is_empty() && bytes + record.len() + 1 > limits.max_bytes {
+                    deliver(
+                        &mut records,
+                        name,
+                        envelope,
+                        (&compression, &auth, &proxy),
+                        &mut send,
+                    );
+                    bytes = 0;
+                    deadline = None;
+                }
+                deadline.get_or_insert_with(|| Instant::now() + limits.max_delay);
+                bytes += record.len() + 1;
+                records.push(record);
+                if records.len() < limits.max_records && bytes < limits.max_bytes {
+                    continue;
+                }
+            }
+            Ok(Message::Configure(new, codec, credentials, via)) => {
+                limits = new;
+                compression = codec;
+                auth = credentials;
+                proxy = via;
+                continue;
+            }
+            Ok(Message::Flush(ack)) => {
+                deliver(
+                    &mut records,
+                    name,
+                    envelope,
+                    (&compression, &auth, &proxy),
+                    &mut send,
+                );
+                bytes = 0;
+                deadline = None;
+                let _ = ack.send(());
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                deliver(
+                    &mut records,
+                    name,
+                    envelope,
+                    (&compression, &auth, &proxy),
+                    &mut send,
+                );
+                return;
+            }
+        }
+        deliver(
+            &mut records,
+            name,
+            envelope,
+            (&compression, &auth, &proxy),
+            &mut send,
+        );
+        bytes = 0;
+        deadline = None;
+    }
+}
+
+/// Send the batch, retrying a few times before dropping it
+fn deliver(
+    records: &mut Vec<Vec<u8>>,
+    name: &str,
+    envelope: &Envelope,
+    (compression, auth, proxy): (&Compression, &HttpAuth, &Proxy),
+    send: &mut Send,
+) {
+    if records.is_empty() {
+        return;
+    }
+    let len = envelope.prefix.len()
+        + envelope.suffix.len()
+        + records.iter().map(|record| record.len() + 1).sum::<usize>();
+    let mut body = Vec::with_capacity(len);
+    body.extend_from_slice(&envelope.prefix);
+    for (i, record) in records.iter().enumerate() {
+        if i > 0 {
+            body.push(b',');
+        }
+        body.extend_from_slice(record);
+    }
+    body.extend_from_slice(&envelope.suffix);
+    let (body, encoding) = match compression.encode(&body) {
+        Ok(encoded) => encoded,
+        Err(err) => {
+            diagnostics::report(format_args!(
+                "dropped {} records after failing to compress them for {}: {}",
+                records.len(),
+                name,
+                err
+            ));
+            records.clear();
+            return;
+        }
+    };
+
+    let mut delay = RETRY_DELAY;
+    for attempt in 1..=ATTEMPTS {
+        match send(&body, encoding, auth, proxy) {
+            Ok(()) => break,
+            Err(err) if attempt == ATTEMPTS || err.kind() == io::ErrorKind::InvalidInput => {
+                diagnostics::report(format_args!(
+                    "dropped {} records after failing to send them to {}: {}",
+                    records.len(),
+                    name,
+                    err
+                ));
+                break;
+            }
+            Err(_) => {
+                thread::sleep(delay);
+                delay *= 2;
+            }
+        }
+    }
+    records.clear();
+}
@@ -0,0 +1,173 @@
+//! Support bundles: what femme knows about a program, in one archive.
+//!
+//! `femme::support_bundle` writes a gzipped tarball holding:
+//!
+//! * `records.ndjson` - the records kept by the flight recorder
+//! * `config.txt` - the configuration the logger was started with, leaving out
+//!   the credentials and tokens in URLs
+//! * `stats.prom` - the internal counters, as `femme::stats` reports them
+//! * `environment.txt` - the femme version, platform, process and host
+
+use crate::escape;
+use crate::{gzip, recorder, stats, syslog, time};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::UNIX_EPOCH;
+
+/// The configuration of the logger, rendered when it starts
+static CONFIG: OnceLock<String> = OnceLock::new();
+
+/// Name of the directory the files are archived in
+const DIRECTORY: &str = "femme-support";
+
+/// Environment variables that change how programs log or fail
+const VARIABLES: [&str; 3] = ["RUST_LOG", "RUST_BACKTRACE", "RUST_LIB_BACKTRACE"];
+
+/// Write a support bundle to attach to bug reports
+///
+/// The bundle is a `.tar.gz` archive with the records kept by the flight
+/// recorder as ndjson, the configuration the logger was started with, the
+/// internal counters in the Prometheus text format, and the femme version,
+/// platform, hostname, process id, executable and working directory. Only
+/// the logging-related environment variables `RUST_LOG` and
+/// `RUST_BACKTRACE` are included.
+///
+/// The configuration leaves out the user and password, query and fragment
+/// of the URLs it holds, such as the key of a Sentry DSN, and the path of a
+/// webhook, which holds its token.
+///
+/// No records are included without `Femme::flight_recorder`.
+///
+/// # Examples
+/// ```
+/// femme::ndjson().flight_recorder(1000).finish().unwrap();
+/// log::error!("Connection refused");
+///
+/// let path = std::env::temp_dir().join("femme-doc-support.tar.gz");
+/// femme::support_bundle(&path).unwrap();
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn support_bundle(path: impl AsRef<Path>) -> io::Result<()> {
+    let mut tar = Vec::new();
+    let now = time::now() / 1000;
+
+    let mut records = Vec::new();
+    for entry in recorder::recent().entries {
+        write_entry(&mut records, &entry)?;
+    }
+    append(&mut tar, "records.ndjson", &records, now);
+
+    let config = match CONFIG.get() {
+        Some(config) => format!("{}\n", config),
+        None => "femme was not started\n".to_string(),
+    };
+    append(&mut tar, "config.txt", config.as_bytes(), now);
+    append(
+        &mut tar,
+        "stats.prom",
+        stats::stats().to_prometheus().as_bytes(),
+        now,
+    );
+    append(&mut tar, "environment.txt", &environment()?, now);
+
+    // the end of the archive
+    tar.resize(tar.len() + 2 * 512, 0);
+    fs::write(path, gzip::compress(&tar))
+}
+
+/// Keep the configuration of the logger being started
+pub(crate) fn install(config: String) {
+    let _ = CONFIG.set(config);
+}
+
+/// Format a kept record as a line of JSON
+fn write_entry<W: Write>(out: &mut W, entry: &recorder::Entry) -> io::Result<()> {
+    let millis = entry
+        .time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as u64);
+    write!(out, "{{\"time\":\"")?;
+    time::write_rfc3339(out, millis)?;
+    write!(out, "\",\"level\":\"{}\",\"target\":", entry.level)?;
+    escape::json_str(out, &entry.target)?;
+    write!(out, ",\"msg\":")?;
+    escape::json_str(out, &entry.message)?;
+    write!(out, ",\"fields\":{{")?;
+    for (i, (key, value)) in entry.fields.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        escape::json_str(out, key)?;
+        write!(out, ":")?;
+        escape::json_str(out, value)?;
+    }
+    writeln!(out, "}}}}")
+}
+
+/// Describe the femme version, platform, host and process
+fn environment() -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let hostname = syslog::Config::default().hostname().map(str::to_string);
+    let unknown = |value: Option<String>| value.unwrap_or_else(|| "unknown".to_string());
+    writeln!(out, "femme: {}", env!("CARGO_PKG_VERSION"))?;
+    write!(out, "time: ")?;
+    time::write_rfc3339(&mut out, time::now())?;
+    writeln!(out)?;
+    writeln!(out, "os: {}", std::env::consts::OS)?;
+    writeln!(out, "arch: {}", std::env::consts::ARCH)?;
+    writeln!(out, "hostname: {}", unknown(hostname))?;
+    writeln!(out, "pid: {}", std::process::id())?;
+    let exe = std::env::current_exe().ok();
+    writeln!(
+        out,
+        "executable: {}",
+        unknown(exe.map(|exe| exe.display().to_string()))
+    )?;
+    let dir = std::env::current_dir().ok();
+    writeln!(
+        out,
+        "directory: {}",
+        unknown(dir.map(|dir| dir.display().to_string()))
+    )?;
+    for name in &VARIABLES {
+        if let Some(value) = std::env::var_os(name) {
+            writeln!(out, "{}: {}", name, value.to_string_lossy())?;
+        }
+    }
+    Ok(out)
+}
+
+/// Append a regular file to a tar archive in the ustar format
+///
+/// # Arguments
+/// * `tar` - Archive to append to
+/// * `name` - Name of the file in `DIRECTORY`
+/// * `data` - Contents of the file
+/// * `mtime` - Modification time, in seconds since the Unix epoch
+fn append(tar: &mut Vec<u8>, name: &str, data: &[u8], mtime: u64) {
+    let mut header = [0u8; 512];
+    let mut field = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    field(0, format!("{}/{}", DIRECTORY, name).as_bytes());
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", data.len()).as_bytes());
+    field(136, format!("{:011o}\0", mtime).as_bytes());
+    field(148, b"        ");
+    field(156, b"0");
+    field(257, b"ustar\x0000");
+    field(265, b"femme");
+    field(297, b"femme");
+
+    let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+    tar.extend_from_slice(&header);
+    tar.extend_from_slice(data);
+    let padding = (512 - data.len() % 512) % 512;
+    tar.resize(tar.len() + padding, 0);
+}
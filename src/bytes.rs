@@ -0,0 +1,241 @@
+//! Byte buffers logged as Key/Value values.
+//!
+//! A value of `femme::Bytes` formats as a byte string literal, such as
+//! `b"GET \xff\x00"`, which is valid UTF-8 whatever the bytes are. Formats
+//! that know of it take the bytes from the value itself while formatting it,
+//! so text that only looks like a byte string stays text: ndjson writes
+//! bytes that aren't UTF-8 as base64 under the key with a `_b64` suffix, and
+//! pretty output previews them as a line of a hexdump:
+//!
+//! ```text
+//! {"level":30,"time":1566390896789,"msg":"Received frame","payload_b64":"R0VUIP8A"}
+//! ```
+//!
+//! With `Femme::hexdump`, pretty output renders every value of `Bytes` as a
+//! hexdump of its first bytes instead, with rows as `hexdump -C` writes
+//! them:
+//!
+//! ```text
+//! scratch Received frame
+//!     payload (18 bytes)
+//!         00000000  47 45 54 20 2f 20 48 54  54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|
+//!         00000010  0d 0a                                             |..|
+//! ```
+
+use crate::capture::{self, Captured};
+use log::kv;
+use std::fmt;
+
+/// Suffix of the key base64-encoded bytes are written under
+pub(crate) const B64_SUFFIX: &str = "_b64";
+
+/// Most bytes shown by the preview of pretty output, and bytes in each row
+/// of a hexdump
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+const PREVIEW_BYTES: usize = 16;
+
+/// A byte buffer to log as a value, which needn't be UTF-8
+///
+/// # Examples
+/// ```
+/// use kv_log_macro::info;
+///
+/// femme::start();
+///
+/// let frame = [0x47, 0x45, 0x54, 0x20, 0xff, 0x00];
+/// info!("Received frame", { payload: femme::Bytes(&frame) });
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bytes<'a>(pub &'a [u8]);
+
+impl fmt::Display for Bytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        capture::offer(|| Captured::Bytes(self.0.to_vec()));
+        write!(f, "b\"{}\"", self.0.escape_ascii())
+    }
+}
+
+impl kv::ToValue for Bytes<'_> {
+    fn to_value(&self) -> kv::Value<'_> {
+        kv::Value::from_display(self)
+    }
+}
+
+/// Standard base64, with padding
+pub(crate) fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &byte)| n | u32::from(byte) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+/// A line of a hexdump of the first bytes, followed by how many there are
+///
+/// Six bytes preview as `47 45 54 20 ff 00  |GET ..|  (6 bytes)`; longer
+/// buffers show their first 16 bytes and an ellipsis after them.
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+pub(crate) struct Preview<'a>(pub(crate) &'a [u8]);
+
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+impl fmt::Display for Preview<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shown = &self.0[..self.0.len().min(PREVIEW_BYTES)];
+        for (i, byte) in shown.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        if shown.len() < self.0.len() {
+            f.write_str(" …")?;
+        }
+        f.write_str("  ")?;
+        write_printable(f, shown)?;
+        f.write_str("  ")?;
+        write_count(f, self.0.len())
+    }
+}
+
+/// The printable ASCII of some bytes between bars, with others as dots
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+fn write_printable(f: &mut dyn fmt::Write, bytes: &[u8]) -> fmt::Result {
+    f.write_str("|")?;
+    for &byte in bytes {
+        match byte {
+            b' '..=b'~' => f.write_char(char::from(byte))?,
+            _ => f.write_str(".")?,
+        }
+    }
+    f.write_str("|")
+}
+
+/// How many bytes there are, such as `(6 bytes)`
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+fn write_count(f: &mut dyn fmt::Write, len: usize) -> fmt::Result {
+    match len {
+        1 => f.write_str("(1 byte)"),
+        len => write!(f, "({} bytes)", len),
+    }
+}
+
+/// A hexdump of the first bytes, a row of 16 on each line after the first
+///
+/// The first line says how many bytes there are, and the last how many
+/// weren't shown, if any.
+///
+/// # Arguments
+/// * `f` - Writer to format into
+/// * `bytes` - Bytes to dump
+/// * `max` - Most bytes shown
+/// * `indent` - What each row starts with
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+pub(crate) fn write_dump(
+    f: &mut dyn fmt::Write,
+    bytes: &[u8],
+    max: usize,
+    indent: &str,
+) -> fmt::Result {
+    write_count(f, bytes.len())?;
+    let shown = &bytes[..bytes.len().min(max)];
+    for (row, chunk) in shown.chunks(PREVIEW_BYTES).enumerate() {
+        write!(f, "\n{}{:08x}  ", indent, row * PREVIEW_BYTES)?;
+        for i in 0..PREVIEW_BYTES {
+            match chunk.get(i) {
+                Some(byte) => write!(f, "{:02x} ", byte)?,
+                None => f.write_str("   ")?,
+            }
+            if i == PREVIEW_BYTES / 2 - 1 {
+                f.write_str(" ")?;
+            }
+        }
+        f.write_str(" ")?;
+        write_printable(f, chunk)?;
+    }
+    match bytes.len() - shown.len() {
+        0 => Ok(()),
+        1 => write!(f, "\n{}… 1 more byte", indent),
+        rest => write!(f, "\n{}… {} more bytes", indent, rest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use log::kv::ToValue;
+    use log::Level;
+
+    /// Decode standard base64, with padding
+    fn decode(text: &str) -> Vec<u8> {
+        const ALPHABET: &[u8; 64] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut out = Vec::new();
+        for chunk in text.as_bytes().chunks(4) {
+            let digits: Vec<u32> = chunk
+                .iter()
+                .take_while(|&&c| c != b'=')
+                .map(|c| ALPHABET.iter().position(|a| a == c).unwrap() as u32)
+                .collect();
+            let n = digits
+                .iter()
+                .enumerate()
+                .fold(0, |n, (i, digit)| n | digit << (18 - 6 * i));
+            out.extend((0..digits.len() - 1).map(|i| (n >> (16 - 8 * i)) as u8));
+        }
+        out
+    }
+
+    #[test]
+    fn base64_vectors() {
+        // RFC 4648, section 10
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (bytes, text) in vectors {
+            assert_eq!(base64(bytes.as_bytes()), text);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes: Vec<u8> = (0..=255).rev().collect();
+        for len in 0..bytes.len() {
+            assert_eq!(decode(&base64(&bytes[..len])), &bytes[..len]);
+        }
+    }
+
+    #[test]
+    fn ndjson() {
+        if testing::compiled_out() {
+            return;
+        }
+        let frame = [0x47, 0x45, 0x54, 0xff, 0x00];
+        let (payload, text) = (Bytes(&frame), Bytes(b"GET"));
+        let output = testing::log(
+            crate::ndjson(),
+            Level::Info,
+            "Received frame",
+            &[("payload", payload.to_value()), ("text", text.to_value())],
+        );
+        assert!(output.ends_with(",\"payload_b64\":\"R0VU/wA=\",\"text\":\"GET\"}\n"));
+        assert_eq!(decode("R0VU/wA="), frame);
+    }
+}
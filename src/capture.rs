@@ -0,0 +1,85 @@
+//! Values recognised by their type while they're formatted.
+//!
+//! Key/Value values are type-erased, so once a value of `Bytes` or `Report`,
+//! or the keys `max_record_size` cut, is logged, all that's left of it is its
+//! text. Instead, while femme
+//! formats a value, their `Display` hands what they hold to the thread
+//! formatting them.
+
+use log::kv;
+use std::cell::RefCell;
+use std::fmt::{self, Write};
+
+/// What a value of a type femme knows held
+pub(crate) enum Captured {
+    /// The bytes of a `Bytes`
+    Bytes(Vec<u8>),
+
+    /// The error of a `Report`: its message, and those of its sources
+    Report { message: String, chain: Vec<String> },
+
+    /// The keys `max_record_size` cut, formatted as a JSON array
+    #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+    Names(String),
+}
+
+impl Captured {
+    /// The text the value formats as
+    fn text(&self) -> String {
+        match self {
+            Captured::Bytes(bytes) => crate::Bytes(bytes).to_string(),
+            Captured::Report { message, .. } => message.clone(),
+            #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+            Captured::Names(names) => names.clone(),
+        }
+    }
+}
+
+enum State {
+    Idle,
+    Armed,
+    Captured(Captured),
+}
+
+thread_local! {
+    /// Whether `capture` is formatting a value on this thread, and what the
+    /// first value of a known type formatted since held
+    static STATE: RefCell<State> = const { RefCell::new(State::Idle) };
+}
+
+/// Hand what a value holds to `capture`, if it's formatting the value
+pub(crate) fn offer(captured: impl FnOnce() -> Captured) {
+    // gone during thread teardown, and borrowed if formatting a value
+    // formats one, when there's nothing to capture
+    let _ = STATE.try_with(|state| {
+        if let Ok(mut state) = state.try_borrow_mut() {
+            if let State::Armed = *state {
+                *state = State::Captured(captured());
+            }
+        }
+    });
+}
+
+/// Format a value, and return what it held if it's of a type femme knows
+///
+/// Only a value whose whole text is that of the value it captured counts, so
+/// neither a string that reads like one nor text with one inside it is
+/// taken for it.
+///
+/// # Arguments
+/// * `text` - Where the value's text is written
+/// * `value` - Value to format
+pub(crate) fn capture(
+    text: &mut String,
+    value: &kv::Value,
+) -> Result<Option<Captured>, fmt::Error> {
+    let start = text.len();
+    let previous = STATE.try_with(|state| state.replace(State::Armed));
+    let res = write!(text, "{}", value);
+    let state = STATE.try_with(|state| state.replace(previous.unwrap_or(State::Idle)));
+    res?;
+    Ok(match state {
+        Ok(State::Captured(captured)) if text[start..] == captured.text() => Some(captured),
+        _ => None,
+    })
+}
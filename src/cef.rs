@@ -0,0 +1,160 @@
+//! ArcSight Common Event Format (CEF) events, for security tooling.
+//!
+//! A record becomes a line with a header of `|`-separated fields followed by
+//! `key=value` extensions:
+//!
+//! ```text
+//! CEF:0|Acme|Gateway|1.4|app::auth|Login failed|6|rt=1566390896789 user=alice
+//! ```
+//!
+//! The signature id is the record's target and the name its message. Levels
+//! map to severities from 8 for errors down to 0 for traces. Key/Value pairs
+//! become extensions, with keys reduced to letters and digits.
+
+use crate::escape::Scalar;
+use log::{kv, Level, Record};
+use std::io::{self, Write};
+
+/// The device fields of the header, see `Femme::cef_device`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Device {
+    pub(crate) vendor: Option<String>,
+    pub(crate) product: Option<String>,
+    pub(crate) version: Option<String>,
+}
+
+impl Device {
+    /// Whether any field was set
+    pub(crate) fn is_set(&self) -> bool {
+        *self != Device::default()
+    }
+}
+
+/// Format the header, up to the name
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `device` - The device fields
+/// * `product` - Product to name when none is set, the program's name
+pub(crate) fn write_header<W: Write>(
+    out: &mut W,
+    record: &Record,
+    device: &Device,
+    product: Option<&str>,
+) -> io::Result<()> {
+    write!(out, "CEF:0|")?;
+    write_header_field(out, device.vendor.as_deref().unwrap_or("femme"))?;
+    write!(out, "|")?;
+    write_header_field(out, device.product.as_deref().or(product).unwrap_or("-"))?;
+    write!(out, "|")?;
+    write_header_field(out, device.version.as_deref().unwrap_or("0"))?;
+    write!(out, "|")?;
+    write_header_field(out, record.target())?;
+    write!(out, "|")
+}
+
+/// Format the name, the record's message
+pub(crate) fn write_message<W: Write>(out: &mut W, record: &Record) -> io::Result<()> {
+    write_header_field(out, &record.args().to_string())
+}
+
+/// Format the severity and the extensions
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch, written as `rt`
+pub(crate) fn write_extensions<W: Write>(
+    out: &mut W,
+    record: &Record,
+    time: u64,
+) -> io::Result<()> {
+    let severity = match record.level() {
+        Level::Error => 8,
+        Level::Warn => 6,
+        Level::Info => 3,
+        Level::Debug => 1,
+        Level::Trace => 0,
+    };
+    write!(out, "|{}|rt={}", severity, time)?;
+
+    struct Visitor<'a, W> {
+        out: &'a mut W,
+    }
+
+    impl<'kvs, 'a, W: Write> kv::Visitor<'kvs> for Visitor<'a, W> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            let key: String = key
+                .as_str()
+                .chars()
+                .filter(char::is_ascii_alphanumeric)
+                .collect();
+            if key.is_empty() {
+                return Ok(());
+            }
+            write!(self.out, " {}=", key)?;
+
+            let text = val.to_string();
+            let mut unescaped = String::new();
+            let text = match Scalar::parse(&text, &mut unescaped) {
+                Scalar::Str(text) | Scalar::Raw(text) | Scalar::Text(text) => text,
+            };
+            for c in text.chars() {
+                match c {
+                    '\\' => self.out.write_all(b"\\\\")?,
+                    '=' => self.out.write_all(b"\\=")?,
+                    '\n' => self.out.write_all(b"\\n")?,
+                    '\r' => self.out.write_all(b"\\r")?,
+                    c => write!(self.out, "{}", c)?,
+                }
+            }
+            Ok(())
+        }
+    }
+
+    record
+        .key_values()
+        .visit(&mut Visitor { out })
+        .map_err(crate::error::kv_to_io)?;
+    Ok(())
+}
+
+/// Write a header field, with pipes and backslashes escaped and line breaks
+/// turned into spaces
+fn write_header_field<W: Write>(out: &mut W, text: &str) -> io::Result<()> {
+    for c in text.chars() {
+        match c {
+            '|' => out.write_all(b"\\|")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' | '\r' => out.write_all(b" ")?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use log::Level;
+
+    #[test]
+    fn layout() {
+        if testing::compiled_out() {
+            return;
+        }
+        let output = testing::log(
+            crate::cef().cef_device("Acme", "Gateway", "1.4"),
+            Level::Warn,
+            "Login failed",
+            &[("user", "alice".into())],
+        );
+        assert!(output.starts_with("CEF:0|Acme|Gateway|1.4|app|Login failed|6|rt="));
+        assert!(output.ends_with(" user=alice\n"));
+    }
+}
@@ -0,0 +1,216 @@
+//! AWS CloudWatch Logs events, uploaded in batches with `PutLogEvents`.
+//!
+//! Each record becomes a log event whose message is the record as ndjson,
+//! which Logs Insights parses into fields:
+//!
+//! ```text
+//! {"timestamp":1566390896789,"message":"{\"level\":30,\"time\":1566390896789,\"msg\":\"Listening on port 8080\"}"}
+//! ```
+//!
+//! A batch holds at most 10,000 events and 1 MiB of them, as the API allows.
+//! The log stream is created when the first upload finds it missing, and
+//! the sequence token, which older accounts still require, is taken from
+//! each response or the error that expected another one.
+//!
+//! Requests are signed with Signature Version 4, from the credentials and
+//! region in the standard AWS environment variables. They go to
+//! `https://logs.<region>.amazonaws.com`, unless
+//! `AWS_ENDPOINT_URL_CLOUDWATCH_LOGS` or `AWS_ENDPOINT_URL` names another
+//! endpoint, such as a local emulator.
+
+use crate::batch::{self, Batcher, Envelope, Limits};
+use crate::http::{self, Url};
+use crate::sigv4::{self, Credentials};
+use crate::{escape, HttpAuth, Proxy};
+use std::io::{self, Write};
+
+/// Most events in a batch
+const MAX_EVENTS: usize = 10_000;
+
+/// Most bytes of events in a batch
+const MAX_BYTES: usize = 1_048_576;
+
+/// Name of the service in signatures
+const SERVICE: &str = "logs";
+
+/// Format the opening of an event, up to its message
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `time` - Milliseconds since the Unix epoch
+pub(crate) fn write_header<W: Write>(out: &mut W, time: u64) -> io::Result<()> {
+    write!(out, "{{\"timestamp\":{},\"message\":", time)
+}
+
+/// Format the message of an event, a record formatted as ndjson
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `line` - The record as ndjson, with or without its newline
+pub(crate) fn write_message<W: Write>(out: &mut W, line: &[u8]) -> io::Result<()> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    escape::json_str(out, String::from_utf8_lossy(line))
+}
+
+/// Ends an event
+pub(crate) const TERMINATOR: &[u8] = b"}\n";
+
+/// Keep the limits of a batch within those of `PutLogEvents`
+pub(crate) fn clamp(limits: Limits) -> Limits {
+    Limits {
+        max_records: limits.max_records.min(MAX_EVENTS),
+        max_bytes: limits.max_bytes.min(MAX_BYTES),
+        ..limits
+    }
+}
+
+/// Where to upload events, and as whom
+struct Client {
+    url: Url,
+
+    /// Signature Version 4 for the region
+    auth: HttpAuth,
+    group: String,
+    stream: String,
+
+    /// Token the next upload must carry, if the stream asked for one
+    token: Option<String>,
+}
+
+impl Client {
+    /// Call an action of the API
+    ///
+    /// # Arguments
+    /// * `action` - Name of the action, such as `PutLogEvents`
+    /// * `body` - The JSON request
+    /// * `proxy` - Which proxy the request goes through
+    fn call(&self, action: &str, body: &[u8], proxy: &Proxy) -> io::Result<http::Response> {
+        let target = format!("Logs_20140328.{}", action);
+        let headers = [
+            ("Content-Type", "application/x-amz-json-1.1"),
+            ("X-Amz-Target", target.as_str()),
+        ];
+        http::post(&self.url, &headers, body, &self.auth, proxy)
+    }
+
+    /// Create the log stream, unless it exists
+    fn create_stream(&self, proxy: &Proxy) -> io::Result<()> {
+        let mut body = Vec::new();
+        write!(body, "{{\"logGroupName\":")?;
+        escape::json_str(&mut body, &self.group)?;
+        write!(body, ",\"logStreamName\":")?;
+        escape::json_str(&mut body, &self.stream)?;
+        write!(body, "}}")?;
+        let response = self.call("CreateLogStream", &body, proxy)?;
+        match error_type(&response) {
+            Some(kind) if kind.ends_with("ResourceAlreadyExistsException") => Ok(()),
+            _ => response.check(&self.url),
+        }
+    }
+
+    /// Upload a batch, creating the stream or taking the expected sequence
+    /// token if needed
+    fn put(&mut self, batch: &[u8], proxy: &Proxy) -> io::Result<()> {
+        let mut created = false;
+        let mut retoken = false;
+        loop {
+            let body = with_token(batch, self.token.as_deref());
+            let response = self.call("PutLogEvents", &body, proxy)?;
+            if response.is_success() {
+                self.token = string_field(&response.body, "nextSequenceToken");
+                return Ok(());
+            }
+            match error_type(&response) {
+                Some(kind) if kind.ends_with("ResourceNotFoundException") && !created => {
+                    self.create_stream(proxy)?;
+                    self.token = None;
+                    created = true;
+                }
+                Some(kind) if kind.ends_with("InvalidSequenceTokenException") && !retoken => {
+                    self.token = string_field(&response.body, "expectedSequenceToken");
+                    retoken = true;
+                }
+                Some(kind) if kind.ends_with("DataAlreadyAcceptedException") => {
+                    self.token = string_field(&response.body, "expectedSequenceToken");
+                    return Ok(());
+                }
+                _ => return response.check(&self.url),
+            }
+        }
+    }
+}
+
+/// The `__type` of an error response
+fn error_type(response: &http::Response) -> Option<String> {
+    match response.is_success() {
+        true => None,
+        false => string_field(&response.body, "__type"),
+    }
+}
+
+/// The value of a string field of a JSON object, if it has no escapes
+fn string_field(body: &[u8], name: &str) -> Option<String> {
+    let body = std::str::from_utf8(body).ok()?;
+    let key = format!("\"{}\":", name);
+    let rest = body[body.find(&key)? + key.len()..].trim_start();
+    let value = rest.strip_prefix('"')?;
+    Some(value[..value.find('"')?].to_string())
+}
+
+/// The batch with a `sequenceToken` field before its closing brace
+fn with_token(batch: &[u8], token: Option<&str>) -> Vec<u8> {
+    let mut body = batch.to_vec();
+    if let Some(token) = token {
+        body.pop();
+        body.extend_from_slice(b",\"sequenceToken\":");
+        let _ = escape::json_str(&mut body, token);
+        body.push(b'}');
+    }
+    body
+}
+
+/// The endpoint requests go to, from the environment
+fn endpoint(region: &str) -> io::Result<Url> {
+    let configured = ["AWS_ENDPOINT_URL_CLOUDWATCH_LOGS", "AWS_ENDPOINT_URL"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|url| !url.is_empty());
+    match configured {
+        Some(url) => Url::parse(&url),
+        None => Url::parse(&format!("https://logs.{}.amazonaws.com", region)),
+    }
+}
+
+/// Start uploading batches of events to a log stream
+///
+/// # Arguments
+/// * `group` - Name of the log group, which must exist
+/// * `stream` - Name of the log stream, created if it doesn't exist
+/// * `name` - Name of the destination, for reports
+pub(crate) fn start(group: &str, stream: &str, name: String) -> io::Result<Batcher> {
+    // missing credentials fail `finish`, not every upload
+    Credentials::from_env()?;
+    let region = sigv4::region_from_env()?;
+    let url = endpoint(&region)?;
+
+    let mut prefix = Vec::new();
+    write!(prefix, "{{\"logGroupName\":")?;
+    escape::json_str(&mut prefix, group)?;
+    write!(prefix, ",\"logStreamName\":")?;
+    escape::json_str(&mut prefix, stream)?;
+    write!(prefix, ",\"logEvents\":[")?;
+    let envelope = Envelope {
+        prefix,
+        suffix: b"]}".to_vec(),
+    };
+
+    let mut client = Client {
+        url,
+        auth: HttpAuth::sigv4(region, SERVICE),
+        group: group.to_string(),
+        stream: stream.to_string(),
+        token: None,
+    };
+    let send: batch::Send = Box::new(move |body, _, _, proxy| client.put(body, proxy));
+    Batcher::start(name, envelope, send)
+}
@@ -0,0 +1,117 @@
+//! Compression of the batches sent over HTTP.
+//!
+//! A batch is compressed as a whole just before it's sent, and the request
+//! names its encoding in `Content-Encoding`. Gzip is built in, and so are
+//! zstd and lz4 with the features of their names, each with an encoder of
+//! femme's own; other formats plug in through `Codec`.
+
+use crate::gzip;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+/// A compression format for batches, see `Compression::custom`
+///
+/// # Examples
+/// ```
+/// use femme::{Codec, Compression};
+///
+/// /// Sends batches as they are, but says so
+/// struct Identity;
+///
+/// impl Codec for Identity {
+///     fn encoding(&self) -> &str {
+///         "identity"
+///     }
+///
+///     fn encode(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+///         Ok(data.to_vec())
+///     }
+/// }
+///
+/// femme::loki("http://localhost:3100").compression(Compression::custom(Identity));
+/// ```
+pub trait Codec: Send + Sync {
+    /// Name of the format in `Content-Encoding`, such as `zstd`
+    fn encoding(&self) -> &str;
+
+    /// Compress the body of a batch
+    fn encode(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// How batches are compressed, see `Femme::compression`
+#[derive(Clone, Default)]
+pub enum Compression {
+    /// Send batches as they are
+    #[default]
+    None,
+
+    /// Compress batches with gzip, which every collector takes
+    Gzip,
+
+    /// Compress batches with Zstandard, smaller and faster to decode than
+    /// gzip; requires the `zstd` feature
+    #[cfg(feature = "zstd")]
+    Zstd,
+
+    /// Compress batches with LZ4 frames, the fastest to decode; requires the
+    /// `lz4` feature
+    #[cfg(feature = "lz4")]
+    Lz4,
+
+    /// Compress batches with a codec of your own
+    Custom(Arc<dyn Codec>),
+}
+
+impl Compression {
+    /// Compress batches with a codec of your own
+    pub fn custom(codec: impl Codec + 'static) -> Self {
+        Compression::Custom(Arc::new(codec))
+    }
+
+    /// Compress the body of a batch
+    ///
+    /// Returns the body, and its `Content-Encoding` unless it's sent as it is.
+    pub(crate) fn encode<'a>(&'a self, data: &[u8]) -> io::Result<(Vec<u8>, Option<&'a str>)> {
+        match self {
+            Compression::None => Ok((data.to_vec(), None)),
+            Compression::Gzip => Ok((gzip::compress(data), Some("gzip"))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok((crate::zstd::compress(data), Some("zstd"))),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok((crate::lz4::compress(data), Some("lz4"))),
+            Compression::Custom(codec) => Ok((codec.encode(data)?, Some(codec.encoding()))),
+        }
+    }
+}
+
+impl fmt::Debug for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => f.write_str("None"),
+            Compression::Gzip => f.write_str("Gzip"),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => f.write_str("Zstd"),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => f.write_str("Lz4"),
+            Compression::Custom(codec) => f.debug_tuple("Custom").field(&codec.encoding()).finish(),
+        }
+    }
+}
+
+impl PartialEq for Compression {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Compression::None, Compression::None) => true,
+            (Compression::Gzip, Compression::Gzip) => true,
+            #[cfg(feature = "zstd")]
+            (Compression::Zstd, Compression::Zstd) => true,
+            #[cfg(feature = "lz4")]
+            (Compression::Lz4, Compression::Lz4) => true,
+            (Compression::Custom(a), Compression::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Compression {}
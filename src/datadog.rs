@@ -0,0 +1,57 @@
+//! JSON events named by Datadog's reserved attributes.
+//!
+//! A record becomes a line of JSON:
+//!
+//! ```text
+//! {"timestamp":"2019-08-21T12:34:56.789Z","status":"info","logger.name":"app::server","message":"Listening on port 8080","port":8080}
+//! ```
+//!
+//! The Datadog agent and intake read the date, status and message of a log
+//! from these without a pipeline of their own. Key/Value pairs are top-level
+//! attributes, as in ndjson output, and collide with the fields above the
+//! same way.
+
+use crate::{escape, json, time, Femme};
+use log::Record;
+use std::io::{self, Write};
+
+/// Fields femme writes itself, in the order they are written
+pub(crate) const RESERVED: [&str; 4] = ["timestamp", "status", "logger.name", "message"];
+
+/// Format the opening of an event, up to the value of `message`
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+/// * `femme` - Logger configuration
+pub(crate) fn write_header<W: Write>(
+    out: &mut W,
+    record: &Record,
+    time: u64,
+    femme: &Femme,
+) -> io::Result<()> {
+    write!(out, "{{\"timestamp\":")?;
+    match json::overridden(record, femme, "timestamp") {
+        Some(timestamp) => escape::json_value(out, &timestamp)?,
+        None => {
+            write!(out, "\"")?;
+            time::write_rfc3339(out, time)?;
+            write!(out, "\"")?;
+        }
+    }
+    write!(out, ",\"status\":")?;
+    match json::overridden(record, femme, "status") {
+        Some(status) => escape::json_value(out, &status)?,
+        None => {
+            let status = record.level().to_string().to_ascii_lowercase();
+            write!(out, "\"{}\"", status)?;
+        }
+    }
+    write!(out, ",\"logger.name\":")?;
+    match json::overridden(record, femme, "logger.name") {
+        Some(name) => escape::json_value(out, &name)?,
+        None => escape::json_str(out, record.target())?,
+    }
+    write!(out, ",\"message\":")
+}
@@ -0,0 +1,112 @@
+//! Records too large for a UDP datagram.
+//!
+//! A datagram larger than the path allows is dropped by the network, or
+//! refused by the socket outright, so oversized records are cut down to
+//! size first: truncated, or split into chunks that name the record they
+//! belong to.
+
+use crate::{gelf, output};
+use std::io;
+use std::net::UdpSocket;
+
+/// Largest payload of a UDP datagram over IPv4
+pub(crate) const DEFAULT_MAX_SIZE: usize = 65_507;
+
+/// Smallest `max_datagram_size` accepted, which leaves room for a chunk
+/// header and some of the record
+pub(crate) const MIN_MAX_SIZE: usize = 64;
+
+/// What happens to a record too large for a datagram, see
+/// `Femme::oversized_datagrams`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Oversized {
+    /// Cut the record short and end it with `[truncated N bytes]`, followed
+    /// by its newline if it had one
+    #[default]
+    Truncate,
+
+    /// Send the record in several datagrams, each starting with the id of
+    /// the record in hex, its position and the number of datagrams, such as
+    /// `00a3f2c41b9e07d4 2/3 `, so a collector can put it back together
+    Split,
+}
+
+/// How large datagrams may be, and what happens to records that are larger
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Limit {
+    pub(crate) max_size: usize,
+    pub(crate) oversized: Oversized,
+}
+
+impl Default for Limit {
+    fn default() -> Self {
+        Limit {
+            max_size: DEFAULT_MAX_SIZE,
+            oversized: Oversized::default(),
+        }
+    }
+}
+
+/// Send a record, cut down to the size of a datagram if needed
+///
+/// # Arguments
+/// * `socket` - Connected socket
+/// * `record` - The formatted record
+/// * `limit` - How large datagrams may be
+pub(crate) fn send(socket: &UdpSocket, record: &[u8], limit: Limit) -> io::Result<()> {
+    if record.len() <= limit.max_size {
+        return output::send_udp(socket, record);
+    }
+    match limit.oversized {
+        Oversized::Truncate => output::send_udp(socket, &truncate(record, limit.max_size)),
+        Oversized::Split => {
+            let id = u64::from_be_bytes(gelf::message_id());
+            let count = count_chunks(record.len(), limit.max_size);
+            let mut rest = record;
+            for seq in 1..=count {
+                let mut datagram = format!("{:016x} {}/{} ", id, seq, count).into_bytes();
+                let len = rest.len().min(limit.max_size - datagram.len());
+                let (chunk, tail) = rest.split_at(len);
+                datagram.extend_from_slice(chunk);
+                output::send_udp(socket, &datagram)?;
+                rest = tail;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Cut a record down to `max_size` bytes, marker and newline included,
+/// without splitting a character
+fn truncate(record: &[u8], max_size: usize) -> Vec<u8> {
+    let newline = match record.last() {
+        Some(b'\n') => "\n",
+        _ => "",
+    };
+    // the marker's length depends on how much is cut, which depends on the
+    // marker's length; the longest marker the record can need is reserved
+    let longest = format!(" [truncated {} bytes]{}", record.len(), newline).len();
+    let mut end = max_size.saturating_sub(longest);
+    while end > 0 && record[end] & 0xc0 == 0x80 {
+        end -= 1;
+    }
+    let marker = format!(" [truncated {} bytes]{}", record.len() - end, newline);
+    let mut datagram = Vec::with_capacity(end + marker.len());
+    datagram.extend_from_slice(&record[..end]);
+    datagram.extend_from_slice(marker.as_bytes());
+    datagram
+}
+
+/// Number of chunks a record of `len` bytes is split into, each with a
+/// header whose length grows with the number of chunks
+fn count_chunks(len: usize, max_size: usize) -> usize {
+    let mut count = 1;
+    loop {
+        let header = format!("{:016x} {}/{} ", 0, count, count).len();
+        let needed = len.div_ceil(max_size - header);
+        if needed <= count {
+            return count;
+        }
+        count = needed;
+    }
+}
@@ -0,0 +1,102 @@
+//! Reporting of femme's own failures.
+//!
+//! A logger can't log its own errors through itself, and it must never
+//! crash the application or flood its output because a disk filled up. Errors
+//! are passed to a handler at most once per interval, together with the number
+//! of errors suppressed since the previous report.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// An error femme ran into while logging
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InternalError {
+    /// Description of what failed
+    pub message: String,
+
+    /// Number of errors that occurred since the previous report without being
+    /// reported themselves
+    pub suppressed: u64,
+}
+
+impl fmt::Display for InternalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "femme: {}", self.message)?;
+        if self.suppressed > 0 {
+            write!(f, " ({} similar errors suppressed)", self.suppressed)?;
+        }
+        Ok(())
+    }
+}
+
+/// Callback receiving internal errors
+pub(crate) type Handler = Arc<dyn Fn(&InternalError) + Send + Sync>;
+
+/// How often an internal error is reported by default
+pub(crate) const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Reporter {
+    handler: Handler,
+    interval: Duration,
+    last: Mutex<Option<Instant>>,
+    suppressed: AtomicU64,
+}
+
+static REPORTER: Mutex<Option<Arc<Reporter>>> = Mutex::new(None);
+
+/// The handler used unless one is configured
+pub(crate) fn default_handler() -> Handler {
+    #[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
+    return Arc::new(|err: &InternalError| {
+        web_sys::console::error_1(&err.to_string().into());
+    });
+
+    #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+    return Arc::new(|err: &InternalError| eprintln!("{}", err));
+}
+
+/// Install the handler that receives internal errors
+///
+/// # Arguments
+/// * `handler` - Callback receiving the errors
+/// * `interval` - Minimum time between two reports
+pub(crate) fn install(handler: Handler, interval: Duration) {
+    *REPORTER.lock().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(Reporter {
+        handler,
+        interval,
+        last: Mutex::new(None),
+        suppressed: AtomicU64::new(0),
+    }));
+}
+
+/// Report an internal error, unless one was reported too recently
+///
+/// # Arguments
+/// * `message` - Description of what failed
+pub(crate) fn report(message: impl fmt::Display) {
+    let reporter = match REPORTER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+    {
+        Some(reporter) => reporter,
+        None => return,
+    };
+
+    {
+        let mut last = reporter.last.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        if last.is_some_and(|last| now.duration_since(last) < reporter.interval) {
+            reporter.suppressed.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        *last = Some(now);
+    }
+
+    (reporter.handler)(&InternalError {
+        message: message.to_string(),
+        suppressed: reporter.suppressed.swap(0, Ordering::Relaxed),
+    });
+}
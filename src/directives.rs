@@ -0,0 +1,73 @@
+//! Filter directives such as `info,hyper=warn`.
+//!
+//! A directive string is a comma separated list. A bare level sets the default
+//! level, `target=level` sets the level for a module or crate.
+
+use crate::ConfigError;
+use log::LevelFilter;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::hash::BuildHasher;
+
+/// A parsed directive string
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Directives {
+    /// The default level, if the string contained a bare level
+    pub(crate) level: Option<LevelFilter>,
+
+    /// Levels for individual targets, in the order they appeared
+    pub(crate) targets: Vec<(String, LevelFilter)>,
+}
+
+/// Parse a directive string
+///
+/// # Arguments
+/// * `spec` - Directives such as `info,hyper=warn`
+pub(crate) fn parse(spec: &str) -> Result<Directives, ConfigError> {
+    let mut directives = Directives::default();
+    let invalid = |directive: &str| ConfigError::InvalidDirective {
+        directive: directive.to_string(),
+    };
+
+    for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        match directive.split_once('=') {
+            None => {
+                let level = directive.parse().map_err(|_| invalid(directive))?;
+                directives.level = Some(level);
+            }
+            Some((target, level)) => {
+                let target = target.trim();
+                let level = level.trim().parse().map_err(|_| invalid(directive))?;
+                if target.is_empty() {
+                    return Err(invalid(directive));
+                }
+                directives.targets.push((target.to_string(), level));
+            }
+        }
+    }
+
+    Ok(directives)
+}
+
+/// Render levels back into a directive string
+///
+/// Targets are sorted so that the same configuration always renders the same
+/// way.
+///
+/// # Arguments
+/// * `level` - The default level
+/// * `targets` - Levels for individual targets
+pub(crate) fn format<K, S>(level: LevelFilter, targets: &HashMap<K, LevelFilter, S>) -> String
+where
+    K: AsRef<str>,
+    S: BuildHasher,
+{
+    let mut targets: Vec<_> = targets.iter().map(|(t, l)| (t.as_ref(), l)).collect();
+    targets.sort();
+
+    let mut out = level.to_string().to_lowercase();
+    for (target, level) in targets {
+        let _ = write!(out, ",{}={}", target, level.to_string().to_lowercase());
+    }
+    out
+}
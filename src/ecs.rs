@@ -0,0 +1,111 @@
+//! JSON documents laid out by the Elastic Common Schema, for Elasticsearch.
+//!
+//! A record becomes a line of JSON:
+//!
+//! ```text
+//! {"@timestamp":"2019-08-21T12:34:56.789Z","log.level":"info","log.logger":"app::server","ecs.version":"1.6.0","message":"Listening on port 8080","labels":{"port":"8080"}}
+//! ```
+//!
+//! Key/Value pairs become `labels`, which the schema maps as keywords: values
+//! are written as strings, and dots in keys, which the schema doesn't allow
+//! in label names, become underscores.
+
+use crate::escape::{self, Scalar};
+use crate::time;
+use log::{kv, Record};
+use std::io::{self, Write};
+
+/// Version of the schema the documents follow
+const ECS_VERSION: &str = "1.6.0";
+
+/// Format the opening of a document, up to the value of `message`
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+pub(crate) fn write_header<W: Write>(out: &mut W, record: &Record, time: u64) -> io::Result<()> {
+    write!(out, "{{\"@timestamp\":\"")?;
+    time::write_rfc3339(out, time)?;
+    let level = record.level().to_string().to_ascii_lowercase();
+    write!(out, "\",\"log.level\":\"{}\",\"log.logger\":", level)?;
+    escape::json_str(out, record.target())?;
+    if let Some(file) = record.file() {
+        write!(out, ",\"log.origin.file.name\":")?;
+        escape::json_str(out, file)?;
+    }
+    if let Some(line) = record.line() {
+        write!(out, ",\"log.origin.file.line\":{}", line)?;
+    }
+    write!(out, ",\"ecs.version\":\"{}\",\"message\":", ECS_VERSION)
+}
+
+/// Format Key/Value pairs as `labels`, or nothing without any
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+pub(crate) fn write_labels<W: Write>(out: &mut W, record: &Record) -> io::Result<()> {
+    struct Visitor<'a, W> {
+        out: &'a mut W,
+        empty: bool,
+    }
+
+    impl<'kvs, 'a, W: Write> kv::Visitor<'kvs> for Visitor<'a, W> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            match self.empty {
+                true => write!(self.out, ",\"labels\":{{")?,
+                false => write!(self.out, ",")?,
+            }
+            self.empty = false;
+            escape::json_str(self.out, key.as_str().replace('.', "_"))?;
+            write!(self.out, ":")?;
+
+            let text = val.to_string();
+            let mut unescaped = String::new();
+            let text = match Scalar::parse(&text, &mut unescaped) {
+                Scalar::Str(text) | Scalar::Raw(text) | Scalar::Text(text) => text,
+            };
+            escape::json_str(self.out, text)?;
+            Ok(())
+        }
+    }
+
+    let mut visitor = Visitor { out, empty: true };
+    record
+        .key_values()
+        .visit(&mut visitor)
+        .map_err(crate::error::kv_to_io)?;
+    if !visitor.empty {
+        write!(visitor.out, "}}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use log::Level;
+
+    #[test]
+    fn layout() {
+        if testing::compiled_out() {
+            return;
+        }
+        let output = testing::log(
+            crate::ecs(),
+            Level::Info,
+            "Listening on port 8080",
+            &[("port", 8080.into()), ("http.method", "GET".into())],
+        );
+        assert!(output.starts_with("{\"@timestamp\":\""));
+        assert!(output.contains(",\"log.level\":\"info\",\"log.logger\":\"app\","));
+        assert!(output.ends_with(
+            ",\"message\":\"Listening on port 8080\",\"labels\":{\"port\":\"8080\",\"http_method\":\"GET\"}}\n"
+        ));
+    }
+}
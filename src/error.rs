@@ -0,0 +1,366 @@
+//! Errors returned while starting the logger.
+
+use std::{error, fmt, io};
+
+/// Error returned when the logger can't be started
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration is inconsistent
+    Config(ConfigError),
+
+    /// Another logger has already been installed
+    SetLogger(log::SetLoggerError),
+
+    /// An output couldn't be opened
+    Output {
+        /// Name of the output, such as the path of a file
+        name: String,
+
+        /// Why it couldn't be opened
+        source: io::Error,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(err) => write!(f, "invalid logger configuration: {}", err),
+            Error::SetLogger(err) => err.fmt(f),
+            Error::Output { name, source } => write!(f, "failed to open {}: {}", name, source),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Config(err) => Some(err),
+            Error::SetLogger(err) => Some(err),
+            Error::Output { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        Error::Config(err)
+    }
+}
+
+impl From<log::SetLoggerError> for Error {
+    fn from(err: log::SetLoggerError) -> Self {
+        Error::SetLogger(err)
+    }
+}
+
+/// Error returned when an audit event can't be recorded
+#[derive(Debug)]
+pub enum AuditError {
+    /// No `audit_file` was configured when the logger started
+    NotConfigured,
+
+    /// A required field is missing from the event
+    MissingField(&'static str),
+
+    /// The event couldn't be written or synced to disk
+    Io(io::Error),
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditError::NotConfigured => write!(f, "no audit file is configured"),
+            AuditError::MissingField(field) => write!(f, "audit event is missing `{}`", field),
+            AuditError::Io(err) => write!(f, "failed to write audit event: {}", err),
+        }
+    }
+}
+
+impl error::Error for AuditError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            AuditError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// A setting that is invalid or contradicts another one
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// `level_for` was given an empty module name
+    EmptyTarget,
+
+    /// A directive passed to `directives` isn't `level` or `target=level`
+    InvalidDirective {
+        /// The offending directive
+        directive: String,
+    },
+
+    /// `background` was given a queue capacity of zero
+    ZeroQueueCapacity,
+
+    /// `fetch` was given a batch size of zero
+    ZeroBatchSize,
+
+    /// `console_limit` was set to zero, which would hide every info line
+    ZeroConsoleLimit,
+
+    /// `max_value_width` was set to zero, which would hide every value
+    ZeroValueWidth,
+
+    /// `hexdump` was set to zero bytes, which would dump none of them
+    ZeroHexdump,
+
+    /// A logger is `Logger::Custom`, but no layout was given with
+    /// `Femme::format`
+    MissingFormat,
+
+    /// `rotate_size` was set to zero, which would rotate on every write
+    ZeroRotateSize,
+
+    /// An option that only affects pretty-printed output was set for a logger
+    /// that doesn't pretty-print
+    PrettyOnly {
+        /// Name of the builder method
+        option: &'static str,
+    },
+
+    /// A log file can't be opened where it is configured
+    UnusablePath {
+        /// Path of the file
+        path: std::path::PathBuf,
+
+        /// Why it can't be opened
+        reason: String,
+    },
+
+    /// `max_record_size` was set below 256 bytes, too few for the header of
+    /// a record
+    SmallRecordSize,
+
+    /// `max_datagram_size` was set below 64 bytes, too few for a chunk of a
+    /// record
+    SmallDatagram,
+
+    /// An option for Loki streams was set without a Loki logger
+    LokiOnly {
+        /// Name of the builder method
+        option: &'static str,
+    },
+
+    /// An option for Kafka records was set without a Kafka logger
+    #[cfg(all(
+        feature = "kafka",
+        any(not(target_arch = "wasm32"), target_os = "wasi")
+    ))]
+    KafkaOnly {
+        /// Name of the builder method
+        option: &'static str,
+    },
+
+    /// An option for MQTT messages was set without an MQTT logger
+    #[cfg(all(feature = "mqtt", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+    MqttOnly {
+        /// Name of the builder method
+        option: &'static str,
+    },
+
+    /// `max_batch_records` or `max_batch_bytes` was set to 0, which leaves
+    /// no room for a record
+    EmptyBatch,
+
+    /// An option that only applies to batched output was set without a
+    /// destination sending records in batches
+    BatchOnly {
+        /// Name of the builder method
+        option: &'static str,
+    },
+
+    /// `http_proxy` was given a URL that doesn't name an `http://` proxy
+    InvalidProxy {
+        /// The URL, without its user and password
+        url: String,
+
+        /// Why it can't be used
+        reason: String,
+    },
+
+    /// An option that only applies to file output was set without `file`
+    FileOnly {
+        /// Name of the builder method
+        option: &'static str,
+    },
+
+    /// An option that only applies to stream output was set without a TCP,
+    /// Unix socket or `Connector` destination
+    StreamOnly {
+        /// Name of the builder method
+        option: &'static str,
+    },
+
+    /// An option that only applies to TCP output was set without a TCP
+    /// destination
+    TcpOnly {
+        /// Name of the builder method
+        option: &'static str,
+    },
+
+    /// `dns_refresh` was set to zero, which would look a host up on every
+    /// write
+    ZeroDnsRefresh,
+
+    /// `webhook_rate_limit` was set to zero, which would hold back every
+    /// alert
+    ZeroWebhookRate,
+
+    /// A destination was set on the logger itself next to sinks added with
+    /// `sink`, which replace it
+    WithSinks {
+        /// Name of the builder method
+        option: &'static str,
+    },
+
+    /// An option that only affects JSON output was set for a logger that
+    /// doesn't write JSON
+    NDJsonOnly {
+        /// Name of the builder method
+        option: &'static str,
+    },
+
+    /// An option that only affects CEF output was set for a logger that
+    /// doesn't write CEF
+    CefOnly {
+        /// Name of the builder method
+        option: &'static str,
+    },
+
+    /// An option that only affects syslog and journald output was set for a
+    /// logger that writes neither
+    SyslogOnly {
+        /// Name of the builder method
+        option: &'static str,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::EmptyTarget => write!(f, "`level_for` needs a module name"),
+            ConfigError::InvalidDirective { directive } => {
+                write!(f, "invalid directive `{}`", directive)
+            }
+            ConfigError::ZeroQueueCapacity => {
+                write!(f, "`background` needs a queue capacity of at least 1")
+            }
+            ConfigError::ZeroBatchSize => write!(f, "`fetch` needs a batch size of at least 1"),
+            ConfigError::ZeroConsoleLimit => write!(
+                f,
+                "`console_limit` needs at least 1 line per second; raise the level instead"
+            ),
+            ConfigError::ZeroValueWidth => {
+                write!(f, "`max_value_width` needs a width of at least 1")
+            }
+            ConfigError::ZeroHexdump => write!(f, "`hexdump` needs at least 1 byte to show"),
+            ConfigError::MissingFormat => write!(
+                f,
+                "`Logger::Custom` writes the layout given with `format`, but none was given"
+            ),
+            ConfigError::ZeroRotateSize => {
+                write!(f, "`rotate_size` needs a size of at least 1 byte")
+            }
+            ConfigError::PrettyOnly { option } => write!(
+                f,
+                "`{}` only affects pretty-printed output, but the logger doesn't pretty-print",
+                option
+            ),
+            ConfigError::UnusablePath { path, reason } => {
+                write!(f, "can't log to {}: {}", path.display(), reason)
+            }
+            ConfigError::SmallRecordSize => {
+                write!(f, "`max_record_size` needs at least 256 bytes")
+            }
+            ConfigError::SmallDatagram => {
+                write!(f, "`max_datagram_size` needs at least 64 bytes")
+            }
+            ConfigError::LokiOnly { option } => write!(
+                f,
+                "`{}` only applies to Loki streams, but no logger is `Logger::Loki`",
+                option
+            ),
+            #[cfg(all(feature = "kafka", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+            ConfigError::KafkaOnly { option } => write!(
+                f,
+                "`{}` only applies to Kafka records, but no logger is `Logger::Kafka`",
+                option
+            ),
+            #[cfg(all(feature = "mqtt", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+            ConfigError::MqttOnly { option } => write!(
+                f,
+                "`{}` only applies to MQTT messages, but no logger is `Logger::Mqtt`",
+                option
+            ),
+            ConfigError::EmptyBatch => write!(
+                f,
+                "`max_batch_records` and `max_batch_bytes` need to leave room for a record"
+            ),
+            ConfigError::BatchOnly { option } => write!(
+                f,
+                "`{}` only applies to batched output, but no destination sending batches is set",
+                option
+            ),
+            ConfigError::InvalidProxy { url, reason } => {
+                write!(f, "`http_proxy` can't use {}: {}", url, reason)
+            }
+            ConfigError::FileOnly { option } => {
+                write!(
+                    f,
+                    "`{}` only applies to file output, but no `file` is set",
+                    option
+                )
+            }
+            ConfigError::StreamOnly { option } => write!(
+                f,
+                "`{}` only applies to stream output, but no TCP, Unix socket or connector destination is set",
+                option
+            ),
+            ConfigError::TcpOnly { option } => write!(
+                f,
+                "`{}` only applies to TCP output, but no TCP destination is set",
+                option
+            ),
+            ConfigError::ZeroDnsRefresh => write!(f, "`dns_refresh` needs an interval above zero"),
+            ConfigError::ZeroWebhookRate => {
+                write!(f, "`webhook_rate_limit` needs a rate above zero")
+            }
+            ConfigError::WithSinks { option } => write!(
+                f,
+                "`{}` doesn't apply once sinks are added with `sink`; set a sink's destination instead",
+                option
+            ),
+            ConfigError::NDJsonOnly { option } => write!(
+                f,
+                "`{}` only affects ndjson output, but the logger doesn't write ndjson",
+                option
+            ),
+            ConfigError::CefOnly { option } => write!(
+                f,
+                "`{}` only affects CEF output, but the logger doesn't write CEF",
+                option
+            ),
+            ConfigError::SyslogOnly { option } => write!(
+                f,
+                "`{}` only affects syslog and journald output, but the logger writes neither",
+                option
+            ),
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+/// A Key/Value error as an `io::Error`, which newer versions of `log` no
+/// longer convert to with `?`
+pub(crate) fn kv_to_io(err: log::kv::Error) -> io::Error {
+    io::Error::other(err.to_string())
+}
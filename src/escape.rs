@@ -0,0 +1,447 @@
+//! Escape untrusted text before it reaches the output.
+//!
+//! Messages and values frequently carry raw user input. Written verbatim, a
+//! `\r` or an ANSI escape sequence can rewrite what a terminal shows and a
+//! stray quote or newline breaks the ndjson framing. These adapters sit
+//! between `write!` and the output so text is escaped as it is formatted.
+
+use crate::bytes;
+use crate::capture::{self, Captured};
+use log::kv;
+use std::cell::RefCell;
+use std::fmt::{self, Write as _};
+use std::io;
+
+/// Whether a character has to be escaped before it reaches a terminal
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+fn is_unsafe(c: char) -> bool {
+    c.is_control() && c != '\t' && c != '\n'
+}
+
+/// Writes text for display on a terminal, with newlines written as the
+/// given bytes
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+struct Visible<'a, W: io::Write>(Adapter<'a, W>, &'static [u8]);
+
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+impl<'a, W: io::Write> fmt::Write for Visible<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let newline = self.1;
+        self.0.capture(|out| {
+            let mut start = 0;
+            for (idx, c) in s.char_indices() {
+                if c != '\n' && !is_unsafe(c) {
+                    continue;
+                }
+                out.write_all(&s.as_bytes()[start..idx])?;
+                start = idx + c.len_utf8();
+
+                match c as u32 {
+                    0x0a => out.write_all(newline)?,
+                    b @ 0x00..=0x1f => out.write_all(&[b'^', b as u8 + 0x40])?,
+                    0x7f => out.write_all(b"^?")?,
+                    other => write!(out, "\\u{{{:x}}}", other)?,
+                }
+            }
+            out.write_all(&s.as_bytes()[start..])
+        })
+    }
+}
+
+/// Writes text as the contents of a JSON string, without the quotes
+struct Json<'a, W: io::Write>(Adapter<'a, W>);
+
+impl<'a, W: io::Write> fmt::Write for Json<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.capture(|out| {
+            let mut start = 0;
+            for (idx, c) in s.char_indices() {
+                let escaped: &[u8] = match c {
+                    '"' => b"\\\"",
+                    '\\' => b"\\\\",
+                    '\n' => b"\\n",
+                    '\r' => b"\\r",
+                    '\t' => b"\\t",
+                    c if c.is_control() || c == '\u{2028}' || c == '\u{2029}' => b"",
+                    _ => continue,
+                };
+                out.write_all(&s.as_bytes()[start..idx])?;
+                start = idx + c.len_utf8();
+
+                if escaped.is_empty() {
+                    write!(out, "\\u{:04x}", c as u32)?;
+                } else {
+                    out.write_all(escaped)?;
+                }
+            }
+            out.write_all(&s.as_bytes()[start..])
+        })
+    }
+}
+
+/// Bridges `fmt::Write` to an `io::Write`, keeping the io error around
+///
+/// `fmt::Error` carries no information, so the underlying error is stashed
+/// and handed back once formatting has been aborted.
+struct Adapter<'a, W: io::Write> {
+    out: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<'a, W: io::Write> Adapter<'a, W> {
+    fn new(out: &'a mut W) -> Self {
+        Adapter { out, error: None }
+    }
+
+    fn capture(&mut self, f: impl FnOnce(&mut W) -> io::Result<()>) -> fmt::Result {
+        f(self.out).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+
+    fn finish(self, res: fmt::Result) -> io::Result<()> {
+        match (res, self.error) {
+            (Ok(()), _) => Ok(()),
+            (Err(_), Some(err)) => Err(err),
+            (Err(_), None) => Err(io::Error::other("formatting failed")),
+        }
+    }
+}
+
+/// Writes `text` for display on a terminal
+///
+/// C0 control characters are shown in caret notation (`ESC` becomes `^[`),
+/// other control characters as `\u{..}` escapes. Newlines are kept, but the
+/// following line is indented so it can't pass for a record of its own.
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+pub(crate) fn visible<W: io::Write>(out: &mut W, text: impl fmt::Display) -> io::Result<()> {
+    let mut visible = Visible(Adapter::new(out), b"\n    ");
+    let res = write!(visible, "{}", text);
+    visible.0.finish(res)
+}
+
+/// Writes `text` like `visible`, on a single line
+///
+/// Newlines are shown as `\n`, for outputs where a newline ends a message.
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+pub(crate) fn single_line<W: io::Write>(out: &mut W, text: impl fmt::Display) -> io::Result<()> {
+    let mut visible = Visible(Adapter::new(out), b"\\n");
+    let res = write!(visible, "{}", text);
+    visible.0.finish(res)
+}
+
+/// Writes `text` as a quoted JSON string
+pub(crate) fn json_str<W: io::Write>(out: &mut W, text: impl fmt::Display) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    let mut json = Json(Adapter::new(out));
+    let res = write!(json, "{}", text);
+    json.0.finish(res)?;
+    out.write_all(b"\"")
+}
+
+/// Undo the escaping `str`'s `Debug` implementation applies
+///
+/// `log` renders string values through `Debug`, so `"a\nb"` arrives as the
+/// characters `"a\nb"` including the quotes.
+fn unescape_debug(text: &str, out: &mut String) {
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('0') => out.push('\0'),
+            Some('u') => {
+                let mut code = 0u32;
+                for digit in chars
+                    .by_ref()
+                    .skip_while(|&c| c == '{')
+                    .take_while(|&c| c != '}')
+                {
+                    code = code
+                        .saturating_mul(16)
+                        .saturating_add(digit.to_digit(16).unwrap_or(0));
+                }
+                out.extend(char::from_u32(code));
+            }
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+}
+
+/// The shape of a key/value value, recovered from its formatted text
+pub(crate) enum Scalar<'a> {
+    /// A string, with `Debug` escapes already removed
+    Str(&'a str),
+
+    /// A number, bool or null that is valid JSON as-is
+    Raw(&'a str),
+
+    /// Anything else, formatted through `Display` or `Debug`
+    Text(&'a str),
+}
+
+impl<'a> Scalar<'a> {
+    /// Classify a formatted value
+    ///
+    /// # Arguments
+    /// * `text` - The value as formatted by its `Display` implementation
+    /// * `unescaped` - Buffer receiving strings that need unescaping
+    pub(crate) fn parse(text: &'a str, unescaped: &'a mut String) -> Self {
+        let quoted = |open: char, close: char| {
+            text.len() >= 2 && text.starts_with(open) && text.ends_with(close)
+        };
+
+        if quoted('"', '"') || quoted('\'', '\'') {
+            let inner = &text[1..text.len() - 1];
+            if !inner.contains('\\') {
+                return Scalar::Str(inner);
+            }
+            unescape_debug(inner, unescaped);
+            Scalar::Str(unescaped)
+        } else if text == "true" || text == "false" {
+            Scalar::Raw(text)
+        } else if text == "None" {
+            Scalar::Raw("null")
+        } else if is_json_number(text) {
+            Scalar::Raw(text)
+        } else {
+            Scalar::Text(text)
+        }
+    }
+}
+
+/// Whether `text` is a number in JSON's grammar
+fn is_json_number(text: &str) -> bool {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    let (int, rest) = digits.split_at(
+        digits
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(digits.len()),
+    );
+    if int.is_empty() || (int.len() > 1 && int.starts_with('0')) {
+        return false;
+    }
+
+    let rest = match rest.strip_prefix('.') {
+        Some(frac) => {
+            let end = frac
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(frac.len());
+            if end == 0 {
+                return false;
+            }
+            &frac[end..]
+        }
+        None => rest,
+    };
+
+    match rest.strip_prefix(['e', 'E']) {
+        Some(exp) => {
+            let exp = exp.strip_prefix(['+', '-']).unwrap_or(exp);
+            !exp.is_empty() && exp.chars().all(|c| c.is_ascii_digit())
+        }
+        None => rest.is_empty(),
+    }
+}
+
+/// Buffers for formatting values, reused across records
+#[derive(Default)]
+struct Scratch {
+    text: String,
+    unescaped: String,
+}
+
+/// Capacity past which a buffer is shrunk again after an unusually large value
+const SCRATCH_CAPACITY: usize = 4096;
+
+thread_local! {
+    static SCRATCH: RefCell<Scratch> = RefCell::new(Scratch::default());
+}
+
+/// Writes a key/value value as JSON
+///
+/// Strings, numbers and bools keep their JSON type, everything else becomes
+/// a string. The value is formatted into a buffer kept per thread, so this
+/// doesn't allocate once the buffer has grown to fit.
+pub(crate) fn json_value<W: io::Write>(out: &mut W, value: &kv::Value) -> io::Result<()> {
+    write_scratch(out, None, value)
+}
+
+/// Writes a key/value pair as a JSON object member
+///
+/// Like `json_value`, and values of `femme::Bytes` are decoded: bytes that
+/// are UTF-8 become a string, and others are written as base64 under the key
+/// with a `_b64` suffix.
+///
+/// Values of `femme::Report` become an object with the error's `message` and
+/// the `chain` of its sources.
+pub(crate) fn json_member<W: io::Write>(
+    out: &mut W,
+    key: &str,
+    value: &kv::Value,
+) -> io::Result<()> {
+    write_scratch(out, Some(key), value)
+}
+
+fn write_scratch<W: io::Write>(
+    out: &mut W,
+    key: Option<&str>,
+    value: &kv::Value,
+) -> io::Result<()> {
+    // the buffers are taken when a value's `Display` logs, and gone during
+    // thread teardown; fall back to fresh ones then
+    SCRATCH
+        .try_with(|scratch| match scratch.try_borrow_mut() {
+            Ok(mut scratch) => write_value(out, key, value, &mut scratch),
+            Err(_) => write_value(out, key, value, &mut Scratch::default()),
+        })
+        .unwrap_or_else(|_| write_value(out, key, value, &mut Scratch::default()))
+}
+
+fn write_value<W: io::Write>(
+    out: &mut W,
+    key: Option<&str>,
+    value: &kv::Value,
+    scratch: &mut Scratch,
+) -> io::Result<()> {
+    scratch.text.clear();
+    scratch.unescaped.clear();
+
+    // writing into a `String` only fails if the value's `Display` does
+    let res = match capture::capture(&mut scratch.text, value) {
+        Ok(captured) => match (key, captured) {
+            (Some(key), Some(Captured::Bytes(bytes))) => write_bytes(out, key, &bytes),
+            (Some(key), Some(Captured::Report { message, chain })) => {
+                write_report(out, key, &message, &chain)
+            }
+            #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+            (Some(key), Some(Captured::Names(names))) => {
+                json_str(out, key)?;
+                out.write_all(b":")?;
+                out.write_all(names.as_bytes())
+            }
+            (Some(key), None) => {
+                json_str(out, key)?;
+                out.write_all(b":")?;
+                write_text(out, &scratch.text, &mut scratch.unescaped)
+            }
+            (None, _) => write_text(out, &scratch.text, &mut scratch.unescaped),
+        },
+        Err(_) => Err(io::Error::other("failed to format value")),
+    };
+
+    for buf in [&mut scratch.text, &mut scratch.unescaped] {
+        if buf.capacity() > SCRATCH_CAPACITY {
+            buf.clear();
+            buf.shrink_to(SCRATCH_CAPACITY);
+        }
+    }
+    res
+}
+
+/// Writes the formatted text of a value as JSON
+fn write_text<W: io::Write>(out: &mut W, text: &str, unescaped: &mut String) -> io::Result<()> {
+    match Scalar::parse(text, unescaped) {
+        Scalar::Str(s) => json_str(out, s),
+        Scalar::Raw(raw) => out.write_all(raw.as_bytes()),
+        Scalar::Text(text) => json_str(out, text),
+    }
+}
+
+/// Writes a member holding an error, as an object with its message and the
+/// chain of its sources, if it has any
+fn write_report<W: io::Write>(
+    out: &mut W,
+    key: &str,
+    message: &str,
+    chain: &[String],
+) -> io::Result<()> {
+    json_str(out, key)?;
+    out.write_all(b":{\"message\":")?;
+    json_str(out, message)?;
+    if !chain.is_empty() {
+        out.write_all(b",\"chain\":")?;
+        json_str(out, chain.join(": "))?;
+    }
+    out.write_all(b"}")
+}
+
+/// Writes a member holding bytes, as a string if they are UTF-8 and as
+/// base64 otherwise
+fn write_bytes<W: io::Write>(out: &mut W, key: &str, bytes: &[u8]) -> io::Result<()> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => {
+            json_str(out, key)?;
+            out.write_all(b":")?;
+            json_str(out, text)
+        }
+        Err(_) => {
+            json_str(out, format_args!("{}{}", key, bytes::B64_SUFFIX))?;
+            out.write_all(b":")?;
+            json_str(out, bytes::base64(bytes))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(text: &str) -> String {
+        let mut out = Vec::new();
+        json_str(&mut out, text).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn value(value: kv::Value) -> String {
+        let mut out = Vec::new();
+        json_value(&mut out, &value).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_strings() {
+        assert_eq!(json("plain"), "\"plain\"");
+        assert_eq!(json("a \"b\" \\ c"), "\"a \\\"b\\\" \\\\ c\"");
+        assert_eq!(json("1\n2\r3\t4"), "\"1\\n2\\r3\\t4\"");
+        assert_eq!(json("\u{1b}[31m\u{7f}"), "\"\\u001b[31m\\u007f\"");
+        assert_eq!(json("\u{2028}\u{2029}é"), "\"\\u2028\\u2029é\"");
+    }
+
+    #[test]
+    fn json_values() {
+        assert_eq!(value(42.into()), "42");
+        assert_eq!(value((-1.5).into()), "-1.5");
+        assert_eq!(value(true.into()), "true");
+        assert_eq!(value("a\n\"b\"".into()), "\"a\\n\\\"b\\\"\"");
+        assert_eq!(value('x'.into()), "\"x\"");
+        assert_eq!(value(kv::Value::from_display(&"007")), "\"007\"");
+        assert_eq!(value(kv::Value::from_display(&"1e5")), "1e5");
+        assert_eq!(value(kv::Value::from_display(&"1.")), "\"1.\"");
+    }
+
+    #[test]
+    fn terminal_text() {
+        let mut out = Vec::new();
+        visible(&mut out, "a\u{1b}[2Jb\u{7f}\u{9b}\nc").unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a^[[2Jb^?\\u{9b}\n    c");
+
+        let mut out = Vec::new();
+        single_line(&mut out, "a\nb\tc").unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a\\nb\tc");
+    }
+
+    #[test]
+    fn debug_escapes() {
+        let mut out = String::new();
+        unescape_debug("a\\n\\\"b\\\"\\u{e9}\\\\", &mut out);
+        assert_eq!(out, "a\n\"b\"é\\");
+    }
+}
@@ -0,0 +1,204 @@
+//! A destination that falls back to another one while it fails.
+//!
+//! Records go to the primary destination while it's healthy. A record the
+//! primary fails to take goes to the fallback instead, and after a few
+//! failures in a row every record does, while the primary is tried again
+//! every few seconds. An internal record announces each switch.
+
+use crate::diagnostics;
+use crate::output::{Destination, Output};
+use crate::rotate;
+use log::{kv, Level, Record};
+use std::io::{self, IoSlice};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Failures in a row after which records go to the fallback
+const FAIL_AFTER: u32 = 3;
+
+/// How often the primary is tried again while failed over
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// An opened `Destination::Failover`
+pub(crate) struct Failover {
+    /// Where the primary is, to open it again if it couldn't be at first
+    destination: Destination,
+    primary_name: String,
+    fallback_name: String,
+    fallback: Output,
+    state: Mutex<State>,
+
+    /// How files are opened, as for any destination
+    truncate: bool,
+    create_dirs: bool,
+    rotation: rotate::Policy,
+}
+
+struct State {
+    /// The primary, unless it couldn't be opened yet
+    primary: Option<Output>,
+
+    /// Failures of the primary in a row
+    failures: u32,
+
+    /// Whether records go to the fallback
+    failed_over: bool,
+
+    /// When to try the primary again while failed over
+    retry_at: Instant,
+}
+
+/// A switch between the primary and the fallback
+enum Switch {
+    /// Records go to the fallback, after the last error of the primary
+    FailedOver(String),
+
+    /// Records go to the primary again
+    FailedBack,
+}
+
+impl Failover {
+    /// Open both destinations; a primary that can't be opened yet only
+    /// starts the logger failed over
+    ///
+    /// # Arguments
+    /// * `primary` - Where records go while it's healthy
+    /// * `fallback` - Where records go while the primary fails
+    /// * `truncate` - Whether to empty a file instead of appending to it
+    /// * `create_dirs` - Whether to create a file's missing parent directories
+    /// * `rotation` - When to rotate a file
+    pub(crate) fn open(
+        primary: &Destination,
+        fallback: &Destination,
+        truncate: bool,
+        create_dirs: bool,
+        rotation: rotate::Policy,
+    ) -> io::Result<Self> {
+        let fallback_output = fallback.open(truncate, create_dirs, rotation)?;
+        let opened = match primary.open(truncate, create_dirs, rotation) {
+            Ok(output) => Some(output),
+            Err(err) => {
+                // the logger isn't installed yet, so no record can say so
+                diagnostics::report(format_args!(
+                    "failed to open {}, writing to {} until it opens: {}",
+                    primary.name(),
+                    fallback.name(),
+                    err
+                ));
+                None
+            }
+        };
+        Ok(Failover {
+            destination: primary.clone(),
+            primary_name: primary.name(),
+            fallback_name: fallback.name(),
+            fallback: fallback_output,
+            state: Mutex::new(State {
+                failed_over: opened.is_none(),
+                primary: opened,
+                failures: 0,
+                retry_at: Instant::now() + RETRY_INTERVAL,
+            }),
+            truncate,
+            create_dirs,
+            rotation,
+        })
+    }
+
+    /// Whether each record must be written on its own, to either destination
+    pub(crate) fn is_datagram(&self) -> bool {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        self.fallback.is_datagram() || state.primary.as_ref().is_some_and(Output::is_datagram)
+    }
+
+    /// Write a record to the primary, or to the fallback if it fails
+    pub(crate) fn write_all_vectored(&self, slices: &mut [IoSlice<'_>]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        let mut switch = None;
+
+        let try_primary = !state.failed_over || now >= state.retry_at;
+        if state.failed_over && try_primary {
+            state.retry_at = now + RETRY_INTERVAL;
+            if state.primary.is_none() {
+                state.primary = self
+                    .destination
+                    .open(self.truncate, self.create_dirs, self.rotation)
+                    .ok();
+            }
+        }
+        let written = match (&state.primary, try_primary) {
+            (Some(primary), true) => match primary.write_all_vectored(slices) {
+                // a connection that broke holds records instead of failing
+                Ok(()) if !primary.is_connected() => Err("disconnected".to_string()),
+                res => res.map_err(|err| err.to_string()),
+            },
+            (None, true) => Err("not open".to_string()),
+            (_, false) => Err(String::new()),
+        };
+
+        match (&written, state.failed_over) {
+            (Ok(()), false) => state.failures = 0,
+            (Ok(()), true) => {
+                state.failed_over = false;
+                state.failures = 0;
+                switch = Some(Switch::FailedBack);
+            }
+            (Err(err), false) => {
+                state.failures += 1;
+                if state.failures >= FAIL_AFTER {
+                    state.failed_over = true;
+                    state.retry_at = now + RETRY_INTERVAL;
+                    switch = Some(Switch::FailedOver(err.clone()));
+                }
+            }
+            (Err(_), true) => {}
+        }
+        let res = match written {
+            Ok(()) => Ok(()),
+            Err(_) => self.fallback.write_all_vectored(slices),
+        };
+        drop(state);
+
+        if let Some(switch) = switch {
+            self.announce(switch);
+        }
+        res
+    }
+
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let primary = state.primary.as_ref().map_or(Ok(()), Output::flush);
+        let fallback = self.fallback.flush();
+        primary.and(fallback)
+    }
+
+    /// Log that records switched destinations
+    fn announce(&self, switch: Switch) {
+        let (level, event, message, error) = match &switch {
+            Switch::FailedOver(error) => {
+                (Level::Warn, "failed_over", "failed over to", Some(error))
+            }
+            Switch::FailedBack => (Level::Info, "failed_back", "failed back from", None),
+        };
+        let fields: &[(&str, kv::Value)] = &[
+            ("femme.sink", self.primary_name.as_str().into()),
+            ("fallback", self.fallback_name.as_str().into()),
+            ("event", event.into()),
+        ];
+        let error = error.map(|error| ("error", kv::Value::from(error.as_str())));
+        let sources: &[&dyn kv::Source] = &[&fields, &error];
+
+        log::logger().log(
+            &Record::builder()
+                .level(level)
+                .target("femme")
+                .args(format_args!(
+                    "sink {} {} {}",
+                    self.primary_name, message, self.fallback_name
+                ))
+                .key_values(&sources)
+                .build(),
+        );
+    }
+}
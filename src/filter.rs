@@ -0,0 +1,72 @@
+//! Cache of the level each module is logged at.
+//!
+//! Filtering a record means splitting its module path and hashing the crate
+//! name into the targets map. For chatty modules that are turned off, that is
+//! most of what a disabled call costs. Module paths logged through the `log`
+//! macros are `'static`, so their address identifies them: the cache maps it
+//! to the resolved level with a multiplication and a single atomic load.
+
+use log::LevelFilter;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Number of slots, a power of two
+const SLOTS: usize = 64;
+
+/// Bits of an entry holding the level, plus one so zero means empty
+const LEVEL_BITS: u64 = 0b111;
+
+/// A direct-mapped, lock-free cache from module path to level
+///
+/// Each slot packs a tag derived from the path with its level, so a slot is
+/// read and written with a single atomic operation and never seen half
+/// written. Two paths mapping to the same slot simply evict each other.
+pub(crate) struct LevelCache([AtomicU64; SLOTS]);
+
+impl LevelCache {
+    pub(crate) fn new() -> Self {
+        LevelCache(std::array::from_fn(|_| AtomicU64::new(0)))
+    }
+
+    /// The level for a module path, resolving and remembering it on a miss
+    ///
+    /// # Arguments
+    /// * `module` - A module path that lives for the rest of the program
+    /// * `resolve` - Computes the level when it isn't cached
+    pub(crate) fn get(
+        &self,
+        module: &'static str,
+        resolve: impl FnOnce() -> LevelFilter,
+    ) -> LevelFilter {
+        let hash = hash(module);
+        let slot = &self.0[(hash >> (64 - SLOTS.trailing_zeros())) as usize];
+        let tag = hash & !LEVEL_BITS;
+
+        let entry = slot.load(Ordering::Relaxed);
+        if entry & !LEVEL_BITS == tag && entry & LEVEL_BITS != 0 {
+            return level_from_bits(entry & LEVEL_BITS);
+        }
+
+        let level = resolve();
+        slot.store(tag | (level as u64 + 1), Ordering::Relaxed);
+        level
+    }
+}
+
+/// Mix the address and length of a path into 64 well distributed bits
+fn hash(module: &'static str) -> u64 {
+    let mut x = (module.as_ptr() as u64) ^ (module.len() as u64).rotate_left(48);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn level_from_bits(bits: u64) -> LevelFilter {
+    match bits - 1 {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
@@ -0,0 +1,100 @@
+//! Layouts of records of your own, see `Femme::format`.
+
+use crate::{x86, Femme, Logger};
+use log::Record;
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+/// A layout of records, written by `Femme::format` in place of a built-in
+/// one
+///
+/// `write` is called from the logging threads and from background writer
+/// threads, once per record and sink. Records are written as it leaves
+/// them, so each should end with a newline; writing too little is only
+/// noticed by whoever reads the output. It must not log.
+///
+/// The built-in layouts are formats too, `Pretty` and `NDJson`, so a layout
+/// can wrap one.
+///
+/// # Examples
+/// ```
+/// use std::io::{self, Write};
+///
+/// struct Tagged;
+///
+/// impl femme::Format for Tagged {
+///     fn write(&self, w: &mut dyn Write, record: &log::Record) -> io::Result<()> {
+///         write!(w, "[{}] ", record.level())?;
+///         femme::NDJson.write(w, record)
+///     }
+/// }
+///
+/// femme::Femme::default().format(Tagged).finish().unwrap();
+/// log::info!("Listening on port 8080");
+/// ```
+pub trait Format: Send + Sync {
+    /// Write a record, with the newline it ends in
+    fn write(&self, w: &mut dyn Write, record: &Record) -> io::Result<()>;
+}
+
+/// The pretty-printed layout of `femme::pretty`, uncolored and with the
+/// options of `Femme::default`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Pretty;
+
+impl Format for Pretty {
+    fn write(&self, w: &mut dyn Write, record: &Record) -> io::Result<()> {
+        write_default(w, record, Logger::Pretty)
+    }
+}
+
+/// The ndjson layout of `femme::ndjson`, with the options of
+/// `Femme::default`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NDJson;
+
+impl Format for NDJson {
+    fn write(&self, w: &mut dyn Write, record: &Record) -> io::Result<()> {
+        write_default(w, record, Logger::NDJson)
+    }
+}
+
+/// Write a record in a built-in layout, with the default options
+fn write_default(w: &mut dyn Write, record: &Record, logger: Logger) -> io::Result<()> {
+    static DEFAULT: OnceLock<Femme> = OnceLock::new();
+    let femme = DEFAULT.get_or_init(Femme::default);
+    let mut buf = Vec::new();
+    x86::format_plain(&mut buf, record, femme, logger)?;
+    w.write_all(&buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use log::Level;
+
+    struct Compact;
+
+    impl Format for Compact {
+        fn write(&self, w: &mut dyn Write, record: &Record) -> io::Result<()> {
+            writeln!(
+                w,
+                "{:<5} {}: {}",
+                record.level(),
+                record.target(),
+                record.args()
+            )
+        }
+    }
+
+    #[test]
+    fn custom() {
+        if testing::compiled_out() {
+            return;
+        }
+        let femme = Femme::default().format(Compact);
+        let output = testing::log(femme, Level::Warn, "Slow query", &[]);
+        assert_eq!(output, "WARN  app: Slow query\n");
+    }
+}
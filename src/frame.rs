@@ -0,0 +1,83 @@
+//! Records packed for loggers of the operating system.
+//!
+//! Native loggers such as logcat and os_log take a record's level, tag and
+//! text as separate arguments. A record is formatted into a frame holding
+//! them: the level, mapped to the logger's own value, as a single byte, then
+//! the tag and the text, each ending with a NUL byte. Frames go through a
+//! sink's queue like any formatted record and are unpacked again when
+//! written.
+
+use log::{kv, Record};
+use std::ffi::CStr;
+use std::io::{self, Write};
+
+/// A frame, unpacked
+pub(crate) struct Frame<'a> {
+    /// The logger's value for the record's level
+    pub(crate) level: u8,
+
+    /// The record's target
+    pub(crate) tag: &'a CStr,
+
+    /// The message, followed by the Key/Value pairs as `key=value`
+    pub(crate) text: &'a CStr,
+}
+
+/// Format a record as a frame, with the target as the tag
+///
+/// # Arguments
+/// * `out` - Buffer to format into
+/// * `level` - The logger's value for the record's level
+/// * `record` - Record to write
+pub(crate) fn write(out: &mut Vec<u8>, level: u8, record: &Record) -> io::Result<()> {
+    out.push(level);
+    write!(NoNul(out), "{}", record.target())?;
+    out.push(0);
+
+    struct Visitor<'a> {
+        out: NoNul<'a>,
+    }
+
+    impl<'kvs, 'a> kv::Visitor<'kvs> for Visitor<'a> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            write!(self.out, " {}={}", key, val)?;
+            Ok(())
+        }
+    }
+
+    write!(NoNul(out), "{}", record.args())?;
+    record
+        .key_values()
+        .visit(&mut Visitor { out: NoNul(out) })
+        .map_err(crate::error::kv_to_io)?;
+    out.push(0);
+    Ok(())
+}
+
+/// Unpack a frame made by `write`
+pub(crate) fn parse(frame: &[u8]) -> io::Result<Frame<'_>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed frame");
+    let (&level, rest) = frame.split_first().ok_or_else(invalid)?;
+    let tag = CStr::from_bytes_until_nul(rest).map_err(|_| invalid())?;
+    let text = CStr::from_bytes_until_nul(&rest[tag.to_bytes_with_nul().len()..])
+        .map_err(|_| invalid())?;
+    Ok(Frame { level, tag, text })
+}
+
+/// Drops NUL bytes, which would cut a tag or text short
+struct NoNul<'a>(&'a mut Vec<u8>);
+
+impl<'a> Write for NoNul<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend(buf.iter().filter(|&&byte| byte != 0));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//! GELF messages, version 1.1, as Graylog accepts them.
+//!
+//! A record becomes a JSON object:
+//!
+//! ```text
+//! {"version":"1.1","host":"web-1","short_message":"Listening on port 8080","timestamp":1566390896.789,"level":6,"_target":"app::server","_user":"alice"}
+//! ```
+//!
+//! The first line of the message is the `short_message`; a message of
+//! several lines is sent whole as `full_message` too. Key/Value pairs become
+//! additional fields, prefixed with `_`. Over a stream each message ends with
+//! a NUL byte; over UDP each one is a datagram of its own, split into chunks
+//! when it's too large for one.
+
+use crate::escape::{self, Scalar};
+use crate::{output, syslog};
+use log::{kv, Level, Record};
+use std::io::{self, Write};
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Additional fields written for every record; pairs with the same name get
+/// a `kv_` prefix so they can't be mistaken for them. `_id` is reserved by
+/// Graylog.
+const OWN_FIELDS: [&str; 4] = ["id", "target", "file", "line"];
+
+/// Ends a message sent over a stream
+pub(crate) const TERMINATOR: &[u8] = b"\0";
+
+/// Largest datagram sent, as Graylog recommends for local networks
+const MAX_DATAGRAM: usize = 8192;
+
+/// Bytes before the payload of a chunk: magic bytes, message id, sequence
+/// number and count
+const CHUNK_HEADER: usize = 12;
+
+/// Most chunks a message may be split into
+const MAX_CHUNKS: usize = 128;
+
+/// Format a record as a GELF message, without its terminator
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+/// * `config` - Syslog fields, of which the hostname is shared
+pub(crate) fn write_message<W: Write>(
+    out: &mut W,
+    record: &Record,
+    time: u64,
+    config: &syslog::Config,
+) -> io::Result<()> {
+    let level = match record.level() {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    };
+    let message = record.args().to_string();
+    let short_message = message.lines().next().unwrap_or("");
+
+    write!(out, "{{\"version\":\"1.1\",\"host\":")?;
+    escape::json_str(out, config.hostname().unwrap_or("localhost"))?;
+    write!(out, ",\"short_message\":")?;
+    escape::json_str(out, short_message)?;
+    if short_message.len() != message.len() {
+        write!(out, ",\"full_message\":")?;
+        escape::json_str(out, &message)?;
+    }
+    write!(
+        out,
+        ",\"timestamp\":{}.{:03},\"level\":{}",
+        time / 1000,
+        time % 1000,
+        level
+    )?;
+    write!(out, ",\"_target\":")?;
+    escape::json_str(out, record.target())?;
+    if let Some(file) = record.file() {
+        write!(out, ",\"_file\":")?;
+        escape::json_str(out, file)?;
+    }
+    if let Some(line) = record.line() {
+        write!(out, ",\"_line\":{}", line)?;
+    }
+
+    struct Visitor<'a, W> {
+        out: &'a mut W,
+    }
+
+    impl<'kvs, 'a, W: Write> kv::Visitor<'kvs> for Visitor<'a, W> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            // names hold word characters, dots and dashes
+            let name: String = key
+                .as_str()
+                .chars()
+                .map(|c| match c {
+                    c if c.is_alphanumeric() || matches!(c, '_' | '.' | '-') => c,
+                    _ => '_',
+                })
+                .collect();
+            let prefix = match OWN_FIELDS.contains(&name.as_str()) {
+                true => "_kv_",
+                false => "_",
+            };
+            write!(self.out, ",")?;
+            escape::json_str(self.out, format_args!("{}{}", prefix, name))?;
+            write!(self.out, ":")?;
+
+            // values are strings or numbers; bools and nulls become strings
+            let text = val.to_string();
+            let mut unescaped = String::new();
+            match Scalar::parse(&text, &mut unescaped) {
+                Scalar::Raw(number) if !matches!(number, "true" | "false" | "null") => {
+                    write!(self.out, "{}", number)?
+                }
+                Scalar::Str(text) | Scalar::Raw(text) | Scalar::Text(text) => {
+                    escape::json_str(self.out, text)?
+                }
+            }
+            Ok(())
+        }
+    }
+
+    record
+        .key_values()
+        .visit(&mut Visitor { out })
+        .map_err(crate::error::kv_to_io)?;
+    write!(out, "}}")
+}
+
+/// Send a message as a single datagram, or in chunks if it's too large
+///
+/// # Arguments
+/// * `socket` - Socket connected to Graylog
+/// * `message` - Message made by `write_message`, without its terminator
+pub(crate) fn send(socket: &UdpSocket, message: &[u8]) -> io::Result<()> {
+    if message.len() <= MAX_DATAGRAM {
+        return output::send_udp(socket, message);
+    }
+
+    let chunks: Vec<_> = message.chunks(MAX_DATAGRAM - CHUNK_HEADER).collect();
+    if chunks.len() > MAX_CHUNKS {
+        return Err(io::Error::other(format!(
+            "a message of {} bytes doesn't fit in {} chunks",
+            message.len(),
+            MAX_CHUNKS
+        )));
+    }
+
+    let id = message_id();
+    let mut datagram = Vec::with_capacity(MAX_DATAGRAM);
+    for (seq, chunk) in chunks.iter().enumerate() {
+        datagram.clear();
+        datagram.extend_from_slice(&[0x1e, 0x0f]);
+        datagram.extend_from_slice(&id);
+        datagram.extend_from_slice(&[seq as u8, chunks.len() as u8]);
+        datagram.extend_from_slice(chunk);
+        output::send_udp(socket, &datagram)?;
+    }
+    Ok(())
+}
+
+/// An id for the chunks of a message, unique to the process and unlikely to
+/// be reused by another one
+pub(crate) fn message_id() -> [u8; 8] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let seed = (crate::time::now() << 20) ^ u64::from(std::process::id());
+    (seed ^ count.rotate_right(16)).to_be_bytes()
+}
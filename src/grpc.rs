@@ -0,0 +1,400 @@
+//! A small gRPC client over HTTP/2, for sinks that call a unary method per
+//! batch.
+//!
+//! Each call is made over a connection of its own, like the requests of
+//! `http`: the connection preface and settings, the request as stream 1 in
+//! as many DATA frames as the server's flow control lets through, and the
+//! response read up to its trailers, whose `grpc-status` says how the call
+//! went. `http://` URLs are spoken to in cleartext (h2c, with prior
+//! knowledge), and with the `tls` feature `https://` URLs over TLS, which
+//! must agree to `h2`. A proxy tunnels either with `CONNECT`.
+
+use crate::http::{self, Connection, Url};
+use crate::{hpack, HttpAuth, Proxy};
+use std::io::{self, Read, Write};
+
+/// What a client opens a connection with, before its settings
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Frame types, from RFC 9113
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+/// Frame flags
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+/// Settings this client sends or heeds
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+
+/// The stream of the call, the first a client opens
+const STREAM: u32 = 1;
+
+/// Largest frame either side may send until told otherwise, which this
+/// client never raises
+const MAX_FRAME_SIZE: usize = 16_384;
+
+/// Flow-control window of a connection and its streams when it opens
+const INITIAL_WINDOW: i64 = 65_535;
+
+/// Status codes of calls that may succeed if made again, as OTLP lists them:
+/// cancelled, deadline exceeded, resource exhausted, aborted, out of range,
+/// unavailable and data loss
+const RETRYABLE: [u32; 7] = [1, 4, 8, 10, 11, 14, 15];
+
+/// Call a unary method
+///
+/// Returns the response message.
+///
+/// # Arguments
+/// * `url` - The server, whose path, if any, the method's is appended to
+/// * `method` - Path of the method, such as `/package.Service/Method`
+/// * `message` - The encoded request message
+/// * `auth` - How the call authenticates
+/// * `proxy` - Which proxy the call goes through
+pub(crate) fn call(
+    url: &Url,
+    method: &str,
+    message: &[u8],
+    auth: &HttpAuth,
+    proxy: &Proxy,
+) -> io::Result<Vec<u8>> {
+    let authority = format!("{}:{}", url.host, url.port);
+    let path = format!("{}{}", url.path.trim_end_matches('/'), method);
+
+    // a message is framed behind a flag saying it isn't compressed, and its
+    // length
+    let mut body = Vec::with_capacity(message.len() + 5);
+    body.push(0);
+    body.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    body.extend_from_slice(message);
+
+    let headers = [("content-type", "application/grpc"), ("te", "trailers")];
+    let credentials = auth.apply(&authority, &path, &headers, &body)?;
+    let scheme = if url.tls { "https" } else { "http" };
+    let mut fields = vec![
+        (":method", "POST"),
+        (":scheme", scheme),
+        (":path", path.as_str()),
+        (":authority", authority.as_str()),
+    ];
+    fields.extend(headers);
+    fields.extend(
+        credentials
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+    );
+    let mut block = Vec::new();
+    hpack::encode(&mut block, &fields);
+    if block.len() > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the headers of the call are too large",
+        ));
+    }
+
+    let mut connection = Http2 {
+        stream: http::open(url, proxy, "h2")?,
+        url,
+        decoder: hpack::Decoder::new(),
+        connection_window: INITIAL_WINDOW,
+        stream_window: INITIAL_WINDOW,
+        initial_window: INITIAL_WINDOW,
+        response: Response::default(),
+    };
+    connection.stream.write_all(PREFACE)?;
+    let mut settings = Vec::new();
+    settings.extend_from_slice(&SETTINGS_ENABLE_PUSH.to_be_bytes());
+    settings.extend_from_slice(&0u32.to_be_bytes());
+    connection.write_frame(SETTINGS, 0, 0, &settings)?;
+    connection.write_frame(HEADERS, END_HEADERS, STREAM, &block)?;
+
+    let mut rest = &body[..];
+    while !rest.is_empty() {
+        let window = connection.connection_window.min(connection.stream_window);
+        if window <= 0 {
+            connection.read_frame()?;
+            if connection.response.ended {
+                break;
+            }
+            continue;
+        }
+        let len = rest.len().min(MAX_FRAME_SIZE).min(window as usize);
+        let (chunk, after) = rest.split_at(len);
+        let flags = if after.is_empty() { END_STREAM } else { 0 };
+        connection.write_frame(DATA, flags, STREAM, chunk)?;
+        connection.connection_window -= len as i64;
+        connection.stream_window -= len as i64;
+        rest = after;
+    }
+    while !connection.response.ended {
+        connection.read_frame()?;
+    }
+    let mut goaway = Vec::new();
+    goaway.extend_from_slice(&0u32.to_be_bytes());
+    goaway.extend_from_slice(&0u32.to_be_bytes());
+    let _ = connection.write_frame(GOAWAY, 0, 0, &goaway);
+    connection.response.check(url)
+}
+
+/// A connection a call is made over
+struct Http2<'a> {
+    stream: Connection,
+    url: &'a Url,
+    decoder: hpack::Decoder,
+
+    /// How many bytes of DATA the server takes before telling us to send
+    /// more, on the connection and on the stream
+    connection_window: i64,
+    stream_window: i64,
+
+    /// The server's `SETTINGS_INITIAL_WINDOW_SIZE`
+    initial_window: i64,
+
+    response: Response,
+}
+
+impl Http2<'_> {
+    fn write_frame(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 9);
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        frame.extend_from_slice(&[kind, flags]);
+        frame.extend_from_slice(&stream.to_be_bytes());
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame)
+    }
+
+    /// Read a frame and do as it says
+    fn read_frame(&mut self) -> io::Result<()> {
+        let mut head = [0; 9];
+        self.stream.read_exact(&mut head)?;
+        let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+        let (kind, flags) = (head[3], head[4]);
+        let stream = u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff;
+        if len > MAX_FRAME_SIZE {
+            return Err(self.invalid());
+        }
+        let mut payload = vec![0; len];
+        self.stream.read_exact(&mut payload)?;
+
+        match kind {
+            SETTINGS if flags & ACK == 0 => {
+                if !len.is_multiple_of(6) {
+                    return Err(self.invalid());
+                }
+                for setting in payload.chunks(6) {
+                    let id = u16::from_be_bytes([setting[0], setting[1]]);
+                    let value = word(setting, 2).ok_or_else(|| self.invalid())?;
+                    // a new initial window moves that of the open stream by
+                    // as much
+                    if id == SETTINGS_INITIAL_WINDOW_SIZE {
+                        self.stream_window += i64::from(value) - self.initial_window;
+                        self.initial_window = i64::from(value);
+                    }
+                }
+                self.write_frame(SETTINGS, ACK, 0, &[])
+            }
+            PING if flags & ACK == 0 => self.write_frame(PING, ACK, 0, &payload),
+            WINDOW_UPDATE => {
+                let increment = word(&payload, 0).ok_or_else(|| self.invalid())?;
+                let increment = i64::from(increment & 0x7fff_ffff);
+                match stream {
+                    0 => self.connection_window += increment,
+                    STREAM => self.stream_window += increment,
+                    _ => {}
+                }
+                Ok(())
+            }
+            GOAWAY => {
+                let (last, code) = match (word(&payload, 0), word(&payload, 4)) {
+                    (Some(last), Some(code)) => (last & 0x7fff_ffff, code),
+                    _ => return Err(self.invalid()),
+                };
+                // a server shutting down gracefully may still answer the call
+                match last >= STREAM && code == 0 {
+                    true => Ok(()),
+                    false => Err(io::Error::other(format!(
+                        "{} closed the connection: error code {}",
+                        self.name(),
+                        code
+                    ))),
+                }
+            }
+            RST_STREAM if stream == STREAM => {
+                let code = word(&payload, 0).ok_or_else(|| self.invalid())?;
+                Err(io::Error::other(format!(
+                    "{} reset the call: error code {}",
+                    self.name(),
+                    code
+                )))
+            }
+            HEADERS if stream == STREAM => {
+                let mut fragment = self.unpad(flags, payload)?;
+                if flags & PRIORITY != 0 {
+                    fragment = fragment.get(5..).ok_or_else(|| self.invalid())?.to_vec();
+                }
+                let mut end = flags & END_HEADERS != 0;
+                while !end {
+                    let mut head = [0; 9];
+                    self.stream.read_exact(&mut head)?;
+                    let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+                    if head[3] != CONTINUATION || len > MAX_FRAME_SIZE {
+                        return Err(self.invalid());
+                    }
+                    let start = fragment.len();
+                    fragment.resize(start + len, 0);
+                    self.stream.read_exact(&mut fragment[start..])?;
+                    end = head[4] & END_HEADERS != 0;
+                }
+                let headers = self.decoder.decode(&fragment)?;
+                self.response.headers.extend(headers);
+                self.response.ended |= flags & END_STREAM != 0;
+                Ok(())
+            }
+            DATA if stream == STREAM => {
+                self.response.body.extend(self.unpad(flags, payload)?);
+                self.response.ended |= flags & END_STREAM != 0;
+                // what was read is made room for again, in case the response
+                // is larger than the window
+                if len > 0 && !self.response.ended {
+                    let increment = (len as u32).to_be_bytes();
+                    self.write_frame(WINDOW_UPDATE, 0, 0, &increment)?;
+                    self.write_frame(WINDOW_UPDATE, 0, STREAM, &increment)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The payload of a frame without its padding, if it's padded
+    fn unpad(&self, flags: u8, mut payload: Vec<u8>) -> io::Result<Vec<u8>> {
+        if flags & PADDED == 0 {
+            return Ok(payload);
+        }
+        let padding = usize::from(*payload.first().ok_or_else(|| self.invalid())?);
+        if padding + 1 > payload.len() {
+            return Err(self.invalid());
+        }
+        payload.truncate(payload.len() - padding);
+        payload.remove(0);
+        Ok(payload)
+    }
+
+    fn name(&self) -> String {
+        http::redact(&self.url.to_string(), false)
+    }
+
+    fn invalid(&self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid HTTP/2 frame from {}", self.name()),
+        )
+    }
+}
+
+/// The response to a call so far
+#[derive(Default)]
+struct Response {
+    /// Headers and then trailers, with lowercase names
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+
+    /// Whether the server is done with the stream
+    ended: bool,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The response message, unless the call failed
+    ///
+    /// Calls failing with a status OTLP says may pass, or whose HTTP status
+    /// says the server is busy or failing, may be made again. Any other
+    /// failure means the call was refused, and is of kind `InvalidInput`, so
+    /// it isn't retried.
+    fn check(self, url: &Url) -> io::Result<Vec<u8>> {
+        let name = http::redact(&url.to_string(), false);
+        let status = self.header(":status").unwrap_or("");
+        if status != "200" {
+            let message = format!("{} answered {}", name, status);
+            return match status {
+                "429" | "502" | "503" | "504" => Err(io::Error::other(message)),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidInput, message)),
+            };
+        }
+        let code: u32 = self
+            .header("grpc-status")
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} answered without a gRPC status", name),
+                )
+            })?;
+        if code != 0 {
+            let message = match self.header("grpc-message") {
+                Some(message) if !message.is_empty() => format!(
+                    "{} answered gRPC status {}: {}",
+                    name,
+                    code,
+                    percent_decode(message)
+                ),
+                _ => format!("{} answered gRPC status {}", name, code),
+            };
+            return match RETRYABLE.contains(&code) {
+                true => Err(io::Error::other(message)),
+                false => Err(io::Error::new(io::ErrorKind::InvalidInput, message)),
+            };
+        }
+        // the message behind its compression flag and length
+        let len = word(&self.body, 1).unwrap_or(0) as usize;
+        Ok(self.body.get(5..5 + len).unwrap_or_default().to_vec())
+    }
+}
+
+/// The big-endian word at an offset of a payload
+fn word(payload: &[u8], at: usize) -> Option<u32> {
+    let bytes = payload.get(at..at + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// A `grpc-message`, whose bytes other than printable ASCII are
+/// percent-encoded
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut at = 0;
+    while at < bytes.len() {
+        let hex = bytes
+            .get(at + 1..at + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[at], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                at += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                at += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
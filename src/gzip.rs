@@ -0,0 +1,188 @@
+//! Gzip compression, with a small DEFLATE encoder of its own.
+//!
+//! Repeated strings are found with the hash chains of `lz77` and written as
+//! fixed Huffman codes, in a single block. That gets most of the size of logs, which repeat
+//! a lot, without a dependency or the cost of dynamic Huffman tables.
+
+use crate::lz77::Matcher;
+
+/// Largest distance a match may reach back
+const WINDOW: usize = 32 * 1024;
+
+/// Longest match DEFLATE can encode
+const MAX_MATCH: usize = 258;
+
+/// Smallest length of each length code, from 257, and its extra bits
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Smallest distance of each distance code, and its extra bits
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// CRC-32 of each byte, for the gzip trailer
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => 0xedb8_8320 ^ (crc >> 1),
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Compress `data` into a gzip member
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    // magic bytes, DEFLATE, no flags, no modification time, fastest, unknown OS
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 4, 255];
+    deflate(data, &mut out);
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Bits written least significant first, as DEFLATE packs them
+struct Bits<'a> {
+    out: &'a mut Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl Bits<'_> {
+    fn put(&mut self, value: u32, len: u32) {
+        self.acc |= u64::from(value) << self.len;
+        self.len += len;
+        while self.len >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which is packed most significant bit first
+    fn code(&mut self, code: u32, len: u32) {
+        self.put(code.reverse_bits() >> (32 - len), len);
+    }
+
+    /// Write a literal byte, a length code or the end of the block
+    fn symbol(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    /// Write a match of `len` bytes starting `distance` bytes back
+    fn reference(&mut self, len: usize, distance: usize) {
+        let code = LENGTH_BASE
+            .iter()
+            .rposition(|&base| usize::from(base) <= len);
+        let code = code.unwrap_or(0);
+        self.symbol(257 + code as u32);
+        let extra = len - usize::from(LENGTH_BASE[code]);
+        self.put(extra as u32, u32::from(LENGTH_EXTRA[code]));
+
+        let code = DISTANCE_BASE
+            .iter()
+            .rposition(|&base| usize::from(base) <= distance);
+        let code = code.unwrap_or(0);
+        self.code(code as u32, 5);
+        let extra = distance - usize::from(DISTANCE_BASE[code]);
+        self.put(extra as u32, u32::from(DISTANCE_EXTRA[code]));
+    }
+
+    fn finish(self) {
+        if self.len > 0 {
+            self.out.push(self.acc as u8);
+        }
+    }
+}
+
+/// Compress `data` into a single final DEFLATE block with fixed codes
+fn deflate(data: &[u8], out: &mut Vec<u8>) {
+    let mut bits = Bits {
+        out,
+        acc: 0,
+        len: 0,
+    };
+    bits.put(1, 1);
+    bits.put(1, 2);
+
+    let mut matcher = Matcher::new(data, WINDOW);
+    let mut at = 0;
+    while at < data.len() {
+        let (len, distance) = matcher.longest(at, MAX_MATCH);
+        if len > 0 {
+            bits.reference(len, distance);
+            for at in at..at + len {
+                matcher.insert(at);
+            }
+            at += len;
+        } else {
+            bits.symbol(u32::from(data[at]));
+            matcher.insert(at);
+            at += 1;
+        }
+    }
+
+    bits.symbol(256);
+    bits.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hex;
+
+    #[test]
+    fn crc32_check() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    // checked with `gzip -d`
+    #[test]
+    fn vectors() {
+        assert_eq!(
+            hex(&compress(b"")),
+            "1f8b08000000000004ff03000000000000000000"
+        );
+        assert_eq!(
+            hex(&compress(
+                b"GET /users 200\nGET /users 200\nGET /users/7 404\n"
+            )),
+            "1f8b08000000000004ff73770d51d02f2d4e2d2a56303230e0c2c9d53757\
+             303130e10200df3ac5302f000000"
+        );
+    }
+}
@@ -0,0 +1,399 @@
+//! Header blocks of HTTP/2, compressed with HPACK.
+//!
+//! Requests are encoded with literals only, never added to the table the
+//! server keeps, so the encoder keeps no state; `authorization` is marked
+//! never to be indexed by anything relaying it. Responses are decoded with
+//! the static table, the dynamic table the server fills, and the Huffman
+//! code of string literals, as RFC 7541 describes them.
+
+use std::collections::VecDeque;
+use std::io;
+
+/// Size of the dynamic table, which the server may not grow past since it's
+/// the `SETTINGS_HEADER_TABLE_SIZE` HTTP/2 starts with
+const MAX_TABLE_SIZE: usize = 4096;
+
+/// What each entry adds to the size of the dynamic table besides its name
+/// and value
+const ENTRY_OVERHEAD: usize = 32;
+
+/// The static table, whose entries have the indices 1 to 61
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Bits of the Huffman code of each byte, and of the end of the string
+/// last; the code is canonical, so its lengths are all it takes to decode it
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+
+/// The symbol ending a string, which mustn't appear in one
+const EOS: usize = 256;
+
+/// Longest code of the Huffman code
+const MAX_HUFFMAN_LENGTH: usize = 30;
+
+/// Encode headers as a header block
+///
+/// Names are lowercased, as HTTP/2 wants them.
+pub(crate) fn encode(out: &mut Vec<u8>, headers: &[(&str, &str)]) {
+    for (name, value) in headers {
+        let name = name.to_ascii_lowercase();
+        // a literal with a new name, never indexed for credentials
+        let kind = match name.as_str() {
+            "authorization" => 0x10,
+            _ => 0x00,
+        };
+        out.push(kind);
+        for text in [name.as_str(), value] {
+            write_integer(out, text.len(), 7, 0x00);
+            out.extend_from_slice(text.as_bytes());
+        }
+    }
+}
+
+/// Write an integer after the flags in the high bits of its first byte
+///
+/// # Arguments
+/// * `out` - Buffer to write to
+/// * `value` - The integer
+/// * `prefix` - Bits of the first byte it starts in
+/// * `flags` - The other bits of the first byte
+fn write_integer(out: &mut Vec<u8>, mut value: usize, prefix: u32, flags: u8) {
+    let max = (1 << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    value -= max;
+    while value >= 128 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Decodes the header blocks of a connection, which share a dynamic table
+pub(crate) struct Decoder {
+    /// Entries, the most recent first
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Decoder {
+    pub(crate) fn new() -> Self {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: MAX_TABLE_SIZE,
+        }
+    }
+
+    /// Decode a header block into its headers, in order
+    pub(crate) fn decode(&mut self, mut block: &[u8]) -> io::Result<Vec<(String, String)>> {
+        let mut headers = Vec::new();
+        while let Some(&first) = block.first() {
+            match first {
+                // an entry of a table
+                0x80..=0xff => {
+                    let index = read_integer(&mut block, 7)?;
+                    headers.push(self.entry(index)?);
+                }
+                // a literal added to the dynamic table
+                0x40..=0x7f => {
+                    let header = self.literal(&mut block, 6)?;
+                    self.insert(header.clone());
+                    headers.push(header);
+                }
+                // a new size of the dynamic table
+                0x20..=0x3f => {
+                    let size = read_integer(&mut block, 5)?;
+                    if size > MAX_TABLE_SIZE {
+                        return Err(invalid());
+                    }
+                    self.max_size = size;
+                    self.evict(0);
+                }
+                // a literal left out of the table, maybe never to be indexed
+                _ => headers.push(self.literal(&mut block, 4)?),
+            }
+        }
+        Ok(headers)
+    }
+
+    /// The entry of an index, of the static table or else the dynamic one
+    fn entry(&self, index: usize) -> io::Result<(String, String)> {
+        match index {
+            0 => Err(invalid()),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.to_string(), value.to_string()))
+            }
+            _ => self.table.get(index - 62).cloned().ok_or_else(invalid),
+        }
+    }
+
+    /// A literal header, whose name is a literal too or that of an entry
+    fn literal(&self, block: &mut &[u8], prefix: u32) -> io::Result<(String, String)> {
+        let name = match read_integer(block, prefix)? {
+            0 => read_string(block)?,
+            index => self.entry(index)?.0,
+        };
+        Ok((name, read_string(block)?))
+    }
+
+    fn insert(&mut self, header: (String, String)) {
+        let size = header.0.len() + header.1.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        // an entry larger than the table empties it, and isn't added
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front(header);
+        }
+    }
+
+    /// Drop the oldest entries until there's room for `size` bytes more
+    fn evict(&mut self, size: usize) {
+        while self.size + size > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + ENTRY_OVERHEAD,
+                None => break,
+            }
+        }
+    }
+}
+
+/// Read an integer starting in the low `prefix` bits of the next byte
+fn read_integer(block: &mut &[u8], prefix: u32) -> io::Result<usize> {
+    let max = (1 << prefix) - 1;
+    let (&first, rest) = block.split_first().ok_or_else(invalid)?;
+    *block = rest;
+    let mut value = usize::from(first) & max;
+    if value < max {
+        return Ok(value);
+    }
+    for shift in (0..28).step_by(7) {
+        let (&byte, rest) = block.split_first().ok_or_else(invalid)?;
+        *block = rest;
+        value += usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid())
+}
+
+/// Read a string literal, Huffman-coded if its high bit says so
+fn read_string(block: &mut &[u8]) -> io::Result<String> {
+    let huffman = block.first().is_some_and(|&first| first & 0x80 != 0);
+    let len = read_integer(block, 7)?;
+    let bytes = block.get(..len).ok_or_else(invalid)?;
+    *block = &block[len..];
+    let bytes = match huffman {
+        true => decode_huffman(bytes)?,
+        false => bytes.to_vec(),
+    };
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// Decode a string of the Huffman code, walking the codes of each length
+/// in turn since those of a length are consecutive
+fn decode_huffman(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut counts = [0usize; MAX_HUFFMAN_LENGTH + 1];
+    for &len in &HUFFMAN_LENGTHS {
+        counts[usize::from(len)] += 1;
+    }
+    let mut symbols: Vec<usize> = (0..HUFFMAN_LENGTHS.len()).collect();
+    symbols.sort_by_key(|&symbol| HUFFMAN_LENGTHS[symbol]);
+
+    let mut out = Vec::with_capacity(bytes.len() * 8 / 5);
+    // the bits of the code read so far, and the first code, and the index
+    // of its symbol, of the length they've reached
+    let (mut code, mut len, mut first, mut index) = (0, 0, 0, 0);
+    for byte in bytes {
+        for bit in (0..8).rev() {
+            code = code << 1 | usize::from(byte >> bit & 1);
+            len += 1;
+            let count = counts[len];
+            if code < first + count {
+                match symbols[index + code - first] {
+                    EOS => return Err(invalid()),
+                    symbol => out.push(symbol as u8),
+                }
+                (code, len, first, index) = (0, 0, 0, 0);
+            } else if len == MAX_HUFFMAN_LENGTH {
+                return Err(invalid());
+            } else {
+                index += count;
+                first = (first + count) << 1;
+            }
+        }
+    }
+    // what's left is padding: fewer than 8 bits, all of them set
+    match len < 8 && code == (1 << len) - 1 {
+        true => Ok(out),
+        false => Err(invalid()),
+    }
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid HPACK header block")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        let hex: String = hex.split_whitespace().collect();
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// The requests of RFC 7541 C.3 and C.4, which decode to the same
+    /// headers with and without the Huffman code
+    fn requests(blocks: [&str; 3]) {
+        let first = [
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+        ];
+        let mut second = first.to_vec();
+        second.push(("cache-control", "no-cache"));
+        let third = [
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":path", "/index.html"),
+            (":authority", "www.example.com"),
+            ("custom-key", "custom-value"),
+        ];
+
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.decode(&bytes(blocks[0])).unwrap(), headers(&first));
+        assert_eq!(decoder.decode(&bytes(blocks[1])).unwrap(), headers(&second));
+        assert_eq!(decoder.decode(&bytes(blocks[2])).unwrap(), headers(&third));
+        assert_eq!(decoder.size, 164);
+    }
+
+    #[test]
+    fn literals() {
+        requests([
+            "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+            "8286 84be 5808 6e6f 2d63 6163 6865",
+            "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+        ]);
+    }
+
+    #[test]
+    fn huffman() {
+        requests([
+            "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+            "8286 84be 5886 a8eb 1064 9cbf",
+            "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+        ]);
+    }
+
+    #[test]
+    fn invalid_blocks() {
+        // padding of a zero bit, an index past the tables, a table too large
+        for block in ["8241 8cf1 e3c2 e5f2 3a6b a0ab 90f4 fe", "ff00", "3fe2 1f"] {
+            assert!(Decoder::new().decode(&bytes(block)).is_err(), "{}", block);
+        }
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let mut block = Vec::new();
+        encode(
+            &mut block,
+            &[("Authorization", "Bearer x"), ("te", "trailers")],
+        );
+        assert_eq!(block[0], 0x10);
+        assert_eq!(
+            Decoder::new().decode(&block).unwrap(),
+            headers(&[("authorization", "Bearer x"), ("te", "trailers")])
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Human friendly rendering of durations and byte counts.
+//!
+//! `log`'s key/value values don't carry their type, but a `Duration` logged
+//! through its `Debug` leaves text of its own, such as `1.240000001s` or
+//! `250ms`, which a string reading the same doesn't since strings are
+//! quoted in their `Debug`. Plain numbers are recognized by how their key is
+//! named instead: `elapsed_ms=1240` is a duration in milliseconds and
+//! `body_bytes=3355443` a size in bytes.
+
+use log::kv;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// Key suffixes naming a duration, with the number of nanoseconds per unit
+const DURATION_SUFFIXES: &[(&str, f64)] =
+    &[("_ns", 1.0), ("_us", 1e3), ("_ms", 1e6), ("_secs", 1e9)];
+
+/// The unit a key's value is measured in
+enum Unit {
+    /// Nanoseconds per unit of the value
+    Duration(f64),
+
+    /// A number of bytes
+    Bytes,
+}
+
+fn unit(key: &str) -> Option<Unit> {
+    if key == "bytes" || key.ends_with("_bytes") {
+        return Some(Unit::Bytes);
+    }
+    DURATION_SUFFIXES
+        .iter()
+        .find(|(suffix, _)| key.ends_with(suffix))
+        .map(|&(_, nanos)| Unit::Duration(nanos))
+}
+
+/// Format hundredths of a unit, dropping trailing zeros
+fn hundredths(value: u128) -> String {
+    let text = format!("{}.{:02}", value / 100, value % 100);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// Render a duration like `1.24s` or `3m 12s`
+///
+/// The duration is rounded to what each unit shows before the unit is
+/// picked, so 59.999s renders as `1m 0s` rather than `60s`.
+pub(crate) fn duration(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    let rounded = |step: u128| (nanos + step / 2) / step;
+    if nanos < 1_000 {
+        return format!("{}ns", nanos);
+    }
+    match (rounded(10), rounded(10_000), rounded(10_000_000)) {
+        (micros, _, _) if micros < 100_000 => format!("{}µs", hundredths(micros)),
+        (_, millis, _) if millis < 100_000 => format!("{}ms", hundredths(millis)),
+        (_, _, secs) if secs < 6_000 => format!("{}s", hundredths(secs)),
+        _ => match rounded(1_000_000_000) {
+            secs if secs < 3_600 => format!("{}m {}s", secs / 60, secs % 60),
+            _ => {
+                let minutes = rounded(60_000_000_000);
+                format!("{}h {}m", minutes / 60, minutes % 60)
+            }
+        },
+    }
+}
+
+/// The duration `Duration`'s `Debug` wrote some text for, such as `1.5s`
+fn parse_duration(text: &str) -> Option<Duration> {
+    let (number, nanos_per_unit) = [
+        ("ns", 1),
+        ("µs", 1_000),
+        ("ms", 1_000_000),
+        ("s", 1_000_000_000),
+    ]
+    .iter()
+    .find_map(|&(unit, nanos)| Some((text.strip_suffix(unit)?, nanos)))?;
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let digits = |text: &str| text.bytes().all(|byte| byte.is_ascii_digit());
+    if whole.is_empty() || !digits(whole) || !digits(fraction) || fraction.len() > 9 {
+        return None;
+    }
+    let whole: u128 = whole.parse().ok()?;
+    let fraction = match fraction {
+        "" => 0,
+        fraction => {
+            fraction.parse::<u128>().ok()? * nanos_per_unit / 10u128.pow(fraction.len() as u32)
+        }
+    };
+    let nanos = whole.checked_mul(nanos_per_unit)? + fraction;
+    let secs = u64::try_from(nanos / 1_000_000_000).ok()?;
+    Some(Duration::new(secs, (nanos % 1_000_000_000) as u32))
+}
+
+/// Render a byte count in binary units like `3.2 MiB`
+pub(crate) fn bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Humanize a formatted value if it's a duration, or its key names a
+/// duration or size
+///
+/// Returns `None` when neither the value nor the key is recognized, or the
+/// value isn't a non-negative number.
+///
+/// # Arguments
+/// * `key` - The key the value was logged under
+/// * `val` - The value
+/// * `text` - The value as formatted by its `Display` implementation
+pub(crate) fn humanize(key: &str, val: &kv::Value, text: &str) -> Option<String> {
+    if let Some(value) = parse_duration(text) {
+        if format!("{:?}", val) == text {
+            return Some(duration(value));
+        }
+    }
+    match unit(key)? {
+        Unit::Bytes => text.parse::<u64>().ok().map(bytes),
+        Unit::Duration(nanos) => {
+            let value = text.parse::<f64>().ok().filter(|v| *v >= 0.0)?;
+            let nanos = value * nanos;
+            if !nanos.is_finite() || nanos > u64::MAX as f64 {
+                return None;
+            }
+            Some(duration(Duration::from_nanos(nanos as u64)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        let cases = [
+            (Duration::from_nanos(999), "999ns"),
+            (Duration::from_nanos(1_500), "1.5µs"),
+            (Duration::from_nanos(999_996), "1ms"),
+            (Duration::from_micros(12_340), "12.34ms"),
+            (Duration::from_millis(1_240), "1.24s"),
+            (Duration::from_micros(59_999_999), "1m 0s"),
+            (Duration::from_secs(192), "3m 12s"),
+            (Duration::from_millis(3_599_600), "1h 0m"),
+            (Duration::from_secs(7_290), "2h 2m"),
+        ];
+        for (value, text) in cases {
+            assert_eq!(duration(value), text, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn byte_counts() {
+        assert_eq!(bytes(512), "512 B");
+        assert_eq!(bytes(3_355_443), "3.2 MiB");
+    }
+
+    #[test]
+    fn values() {
+        let humanize = |key: &str, val: kv::Value| humanize(key, &val, &val.to_string());
+        let elapsed = Duration::from_millis(1_240);
+        assert_eq!(
+            humanize("elapsed", kv::Value::from_debug(&elapsed)),
+            Some("1.24s".to_string())
+        );
+        assert_eq!(humanize("elapsed", "1.24s".into()), None);
+        assert_eq!(
+            humanize("elapsed_ms", 1_240.into()),
+            Some("1.24s".to_string())
+        );
+        assert_eq!(humanize("elapsed_ms", (-1).into()), None);
+        assert_eq!(
+            humanize("body_bytes", 512.into()),
+            Some("512 B".to_string())
+        );
+        assert_eq!(humanize("body", 512.into()), None);
+    }
+}
@@ -0,0 +1,63 @@
+//! Indentation of pretty-printed records, for nested operations.
+//!
+//! Each thread keeps its own depth, raised by the guards `femme::indent`
+//! returns and lowered again when they drop, so the records of nested
+//! builds or recursive tasks render as a tree.
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+/// Columns each level of depth indents by
+const STEP: usize = 2;
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Indent the pretty-printed records of this thread until the guard drops
+///
+/// Guards nest: each one indents records by another level. The guard can't
+/// be sent to another thread, since the depth belongs to the thread that
+/// raised it; a future holding one across an `.await` isn't `Send` either,
+/// which keeps it from resuming on a thread with a depth of its own.
+///
+/// # Examples
+/// ```
+/// femme::pretty().finish().unwrap();
+///
+/// log::info!("Building workspace");
+/// {
+///     let _indent = femme::indent();
+///     log::info!("Building core");
+///     {
+///         let _indent = femme::indent();
+///         log::info!("Compiling 12 files");
+///     }
+///     log::info!("Building cli");
+/// }
+/// log::info!("Done");
+/// ```
+pub fn indent() -> Indent {
+    DEPTH.with(|depth| depth.set(depth.get() + 1));
+    Indent {
+        _thread: PhantomData,
+    }
+}
+
+/// Guard returned by `femme::indent`, which ends the indentation on drop
+#[must_use = "records are only indented while the guard is alive"]
+#[derive(Debug)]
+pub struct Indent {
+    _thread: PhantomData<*const ()>,
+}
+
+impl Drop for Indent {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get().saturating_sub(1)));
+    }
+}
+
+/// Columns the records of this thread are indented by
+pub(crate) fn width() -> usize {
+    DEPTH.try_with(Cell::get).unwrap_or(0) * STEP
+}
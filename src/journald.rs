@@ -0,0 +1,139 @@
+//! Entries for the systemd journal, in its native protocol.
+//!
+//! A record becomes one datagram of `FIELD=value` lines:
+//!
+//! ```text
+//! PRIORITY=6
+//! SYSLOG_IDENTIFIER=app
+//! TARGET=app::server
+//! MESSAGE=Listening on port 8080
+//! USER=alice
+//! ```
+//!
+//! Values holding a newline are written in the protocol's binary form
+//! instead: the field name and a newline, the value's length as a
+//! little-endian 64-bit integer, the value, and a newline.
+
+use crate::escape::Scalar;
+use crate::syslog;
+use log::{kv, Level, Record};
+use std::io::{self, Write};
+
+/// Longest field name the journal accepts
+const MAX_FIELD_NAME: usize = 64;
+
+/// Fields written for every record; pairs with the same name get a `KV_`
+/// prefix so they can't be mistaken for them
+const OWN_FIELDS: [&str; 8] = [
+    "PRIORITY",
+    "SYSLOG_FACILITY",
+    "SYSLOG_IDENTIFIER",
+    "TARGET",
+    "CODE_FILE",
+    "CODE_LINE",
+    "CODE_MODULE",
+    "MESSAGE",
+];
+
+/// Socket the journal daemon receives entries on
+pub(crate) const SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Format a record as a journal entry
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `config` - Syslog fields, shared with the syslog format
+pub(crate) fn write_entry<W: Write>(
+    out: &mut W,
+    record: &Record,
+    config: &syslog::Config,
+) -> io::Result<()> {
+    let priority = match record.level() {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    };
+    writeln!(out, "PRIORITY={}", priority)?;
+    writeln!(out, "SYSLOG_FACILITY={}", config.facility.code())?;
+    if let Some(app_name) = config.app_name() {
+        write_field(out, "SYSLOG_IDENTIFIER", app_name)?;
+    }
+    write_field(out, "TARGET", record.target())?;
+    if let Some(file) = record.file() {
+        write_field(out, "CODE_FILE", file)?;
+    }
+    if let Some(line) = record.line() {
+        writeln!(out, "CODE_LINE={}", line)?;
+    }
+    if let Some(module_path) = record.module_path() {
+        write_field(out, "CODE_MODULE", module_path)?;
+    }
+    write_field(out, "MESSAGE", &record.args().to_string())?;
+
+    struct Visitor<'a, W> {
+        out: &'a mut W,
+    }
+
+    impl<'kvs, 'a, W: Write> kv::Visitor<'kvs> for Visitor<'a, W> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            let name = match field_name(key.as_str()) {
+                Some(name) if OWN_FIELDS.contains(&name.as_str()) => format!("KV_{}", name),
+                Some(name) => name,
+                None => return Ok(()),
+            };
+
+            // strings are written without the quotes of their `Debug` output
+            let text = val.to_string();
+            let mut unescaped = String::new();
+            let text = match Scalar::parse(&text, &mut unescaped) {
+                Scalar::Str(text) | Scalar::Raw(text) | Scalar::Text(text) => text,
+            };
+            write_field(self.out, &name, text)?;
+            Ok(())
+        }
+    }
+
+    record
+        .key_values()
+        .visit(&mut Visitor { out })
+        .map_err(crate::error::kv_to_io)?;
+    Ok(())
+}
+
+/// Write a field, in the binary form if its value holds a newline
+fn write_field<W: Write>(out: &mut W, name: &str, value: &str) -> io::Result<()> {
+    match value.contains('\n') {
+        false => writeln!(out, "{}={}", name, value),
+        true => {
+            writeln!(out, "{}", name)?;
+            out.write_all(&(value.len() as u64).to_le_bytes())?;
+            writeln!(out, "{}", value)
+        }
+    }
+}
+
+/// Turn a key into a journal field name, such as `http.method` into
+/// `HTTP_METHOD`
+///
+/// Field names hold uppercase letters, digits and underscores, and start
+/// with a letter: names starting with an underscore are reserved for fields
+/// the journal adds itself. Returns `None` for a key without any letter to
+/// start with.
+fn field_name(key: &str) -> Option<String> {
+    let start = key.find(|c: char| c.is_ascii_alphabetic())?;
+    let name = key[start..]
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .take(MAX_FIELD_NAME)
+        .collect();
+    Some(name)
+}
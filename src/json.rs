@@ -0,0 +1,541 @@
+//! Newline Delimited JSON output, shared by the native and wasm backends.
+
+use crate::{escape, time, Femme, KeyCollision, LevelValues, Multiline};
+use log::{kv, Record};
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+/// Members femme writes itself, in the order they are written
+pub(crate) const RESERVED: [&str; 3] = ["level", "time", "msg"];
+
+/// Members written with `Femme::bunyan`, in the order they are written
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+const BUNYAN_RESERVED: [&str; 7] = ["v", "level", "name", "hostname", "pid", "time", "msg"];
+
+/// Members with `Femme::fmt_version`, in the order they are written
+const VERSIONED_RESERVED: [&str; 4] = ["fmt_version", "level", "time", "msg"];
+
+/// Members with `Femme::fmt_version` and `Femme::bunyan`, in the order they
+/// are written
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+const VERSIONED_BUNYAN_RESERVED: [&str; 8] = [
+    "fmt_version",
+    "v",
+    "level",
+    "name",
+    "hostname",
+    "pid",
+    "time",
+    "msg",
+];
+
+/// Members femme writes itself with a configuration
+pub(crate) fn reserved(femme: &Femme) -> &'static [&'static str] {
+    #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+    if femme.bunyan {
+        return match femme.fmt_version {
+            true => &VERSIONED_BUNYAN_RESERVED,
+            false => &BUNYAN_RESERVED,
+        };
+    }
+    match femme.fmt_version {
+        true => &VERSIONED_RESERVED,
+        false => &RESERVED,
+    }
+}
+
+/// What to write in place of a user key
+///
+/// # Arguments
+/// * `key` - The key as it was logged
+/// * `reserved` - Members the format writes itself
+/// * `collision` - What to do with keys that collide with a reserved member
+pub(crate) fn rename<'a>(
+    key: &'a str,
+    reserved: &[&str],
+    collision: KeyCollision,
+) -> Option<Cow<'a, str>> {
+    if !reserved.contains(&key) {
+        return Some(Cow::Borrowed(key));
+    }
+    match collision {
+        KeyCollision::Prefix => Some(Cow::Owned(format!("_{}", key))),
+        KeyCollision::Drop | KeyCollision::Override => None,
+    }
+}
+
+/// Ends a record: closes the object and the line
+pub(crate) const TERMINATOR: &[u8] = b"}\n";
+
+/// Format a record as a line of Newline Delimited JSON
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+/// * `femme` - Logger configuration
+#[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
+pub(crate) fn write_ndjson<W: Write>(
+    out: &mut W,
+    record: &Record,
+    time: u64,
+    femme: &Femme,
+) -> io::Result<()> {
+    write_header(out, record, time, femme)?;
+    write_message(out, record, femme)?;
+    write_pairs(out, record, femme, reserved(femme))?;
+    out.write_all(TERMINATOR)
+}
+
+/// The value logged for a reserved member, if it overrides femme's own
+pub(crate) fn overridden<'a>(
+    record: &'a Record,
+    femme: &Femme,
+    name: &str,
+) -> Option<kv::Value<'a>> {
+    match femme.key_collision.unwrap_or_default() {
+        KeyCollision::Override => record.key_values().get(kv::Key::from_str(name)),
+        _ => None,
+    }
+}
+
+/// Format the opening of a record, up to the value of `msg`
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+/// * `femme` - Logger configuration
+pub(crate) fn write_header<W: Write>(
+    out: &mut W,
+    record: &Record,
+    time: u64,
+    femme: &Femme,
+) -> io::Result<()> {
+    #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+    let bunyan = femme.bunyan;
+    #[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
+    let bunyan = false;
+
+    write!(out, "{{")?;
+    if femme.fmt_version {
+        write!(out, "\"fmt_version\":{},", crate::FMT_VERSION)?;
+    }
+    match bunyan {
+        true => write!(out, "\"v\":0,\"level\":")?,
+        false => write!(out, "\"level\":")?,
+    }
+    match overridden(record, femme, "level") {
+        Some(level) => escape::json_value(out, &level)?,
+        None => match femme.level_values {
+            LevelValues::Numbers(numbers) => {
+                write!(out, "{}", numbers[record.level() as usize - 1])?
+            }
+            LevelValues::Names(names) => escape::json_str(out, names[record.level() as usize - 1])?,
+        },
+    }
+    #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+    if bunyan {
+        write!(out, ",\"name\":")?;
+        match overridden(record, femme, "name") {
+            Some(name) => escape::json_value(out, &name)?,
+            None => escape::json_str(out, femme.syslog.app_name().unwrap_or("-"))?,
+        }
+        write!(out, ",\"hostname\":")?;
+        match overridden(record, femme, "hostname") {
+            Some(hostname) => escape::json_value(out, &hostname)?,
+            None => escape::json_str(out, femme.syslog.hostname().unwrap_or("localhost"))?,
+        }
+        write!(out, ",\"pid\":")?;
+        match overridden(record, femme, "pid") {
+            Some(pid) => escape::json_value(out, &pid)?,
+            None => write!(out, "{}", std::process::id())?,
+        }
+    }
+    write!(out, ",\"time\":")?;
+    match overridden(record, femme, "time") {
+        Some(time) => escape::json_value(out, &time)?,
+        None if bunyan => {
+            write!(out, "\"")?;
+            time::write_rfc3339(out, time)?;
+            write!(out, "\"")?;
+        }
+        None => time::write_millis(out, time)?,
+    }
+    write!(out, ",\"msg\":")
+}
+
+/// Format the value of `msg`
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `femme` - Logger configuration
+pub(crate) fn write_message<W: Write>(
+    out: &mut W,
+    record: &Record,
+    femme: &Femme,
+) -> io::Result<()> {
+    match overridden(record, femme, "msg") {
+        Some(msg) => escape::json_value(out, &msg),
+        None if femme.multiline == Multiline::Array => {
+            let message = record.args().to_string();
+            let mut lines = message.lines();
+            escape::json_str(out, lines.next().unwrap_or_default())?;
+            if message.contains('\n') {
+                write!(out, ",\"msg_lines\":[")?;
+                for (i, line) in message.lines().enumerate() {
+                    if i > 0 {
+                        write!(out, ",")?;
+                    }
+                    escape::json_str(out, line)?;
+                }
+                write!(out, "]")?;
+            }
+            Ok(())
+        }
+        None => escape::json_str(out, record.args()),
+    }
+}
+
+/// Call `f` with the record, or under `Multiline::Split` with a record for
+/// each line of its message, those after the first with a `continuation`
+/// pair counting the lines from 1
+///
+/// # Arguments
+/// * `record` - Record to write
+/// * `femme` - Logger configuration
+/// * `f` - Callback writing a record
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+pub(crate) fn split_lines(record: &Record, femme: &Femme, f: impl Fn(&Record)) {
+    if femme.multiline != Multiline::Split || overridden(record, femme, "msg").is_some() {
+        return f(record);
+    }
+    let message = record.args().to_string();
+    if !message.contains('\n') {
+        return f(record);
+    }
+    for (idx, line) in message.lines().enumerate() {
+        let continuation = Some(("continuation", idx)).filter(|_| idx > 0);
+        let sources: &[&dyn kv::Source] = &[record.key_values(), &continuation];
+        f(&record
+            .to_builder()
+            .args(format_args!("{}", line))
+            .key_values(&sources)
+            .build());
+    }
+}
+
+/// Format Key/Value pairs as additional members, flat or nested
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `femme` - Logger configuration
+/// * `reserved` - Members the format writes itself, such as `RESERVED`
+pub(crate) fn write_pairs<W: Write>(
+    out: &mut W,
+    record: &Record,
+    femme: &Femme,
+    reserved: &[&str],
+) -> io::Result<()> {
+    let collision = femme.key_collision.unwrap_or_default();
+    match femme.nest_keys {
+        true => format_nested_kv_pairs(out, record, reserved, collision),
+        false => format_json_kv_pairs(out, record, reserved, collision),
+    }
+}
+
+/// Format Key/Value pairs as additional JSON object members
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `reserved` - Members the format writes itself
+/// * `collision` - What to do with keys that collide with a reserved member
+fn format_json_kv_pairs<W: Write>(
+    out: &mut W,
+    record: &Record,
+    reserved: &[&str],
+    collision: KeyCollision,
+) -> io::Result<()> {
+    struct Visitor<'a, 'r, W> {
+        out: &'a mut W,
+        reserved: &'r [&'r str],
+        collision: KeyCollision,
+    }
+
+    impl<'kvs, 'a, 'r, W: Write> kv::Visitor<'kvs> for Visitor<'a, 'r, W> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            let key = match rename(key.as_str(), self.reserved, self.collision) {
+                Some(key) => key,
+                None => return Ok(()),
+            };
+            write!(self.out, ",")?;
+            escape::json_member(self.out, &key, &val)?;
+            Ok(())
+        }
+    }
+
+    let mut visitor = Visitor {
+        out,
+        reserved,
+        collision,
+    };
+    record
+        .key_values()
+        .visit(&mut visitor)
+        .map_err(crate::error::kv_to_io)?;
+    Ok(())
+}
+
+/// A member of a JSON object being assembled from dotted keys
+enum Node<'a, 'kvs> {
+    Value(&'a kv::Value<'kvs>),
+    Object(Vec<(&'a str, Node<'a, 'kvs>)>),
+}
+
+/// Format Key/Value pairs as additional JSON object members, expanding dotted
+/// keys into nested objects
+///
+/// `http.method` and `http.status` become `"http":{"method":..,"status":..}`,
+/// placed where the first of them appeared. A key is written as is, dots and
+/// all, when expanding it would be ambiguous:
+/// - it has an empty segment, such as `.a`, `a.` or `a..b`
+/// - it is both a value and the prefix of another key, such as `http` next to
+///   `http.method`; then the other key is written as is too
+/// - its first segment is a reserved member, such as `msg.id`
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `reserved` - Members the format writes itself
+/// * `collision` - What to do with keys that collide with a reserved member
+fn format_nested_kv_pairs<W: Write>(
+    out: &mut W,
+    record: &Record,
+    reserved: &[&str],
+    collision: KeyCollision,
+) -> io::Result<()> {
+    struct Pairs<'kvs>(Vec<(kv::Key<'kvs>, kv::Value<'kvs>)>);
+
+    impl<'kvs> kv::Visitor<'kvs> for Pairs<'kvs> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            self.0.push((key, val));
+            Ok(())
+        }
+    }
+
+    fn write_members<W: Write>(out: &mut W, members: &[(&str, Node)]) -> io::Result<()> {
+        for (idx, (name, node)) in members.iter().enumerate() {
+            if idx > 0 {
+                write!(out, ",")?;
+            }
+            match node {
+                Node::Value(val) => escape::json_member(out, name, val)?,
+                Node::Object(members) => {
+                    escape::json_str(out, name)?;
+                    write!(out, ":{{")?;
+                    write_members(out, members)?;
+                    write!(out, "}}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    let mut visited = Pairs(Vec::new());
+    record
+        .key_values()
+        .visit(&mut visited)
+        .map_err(crate::error::kv_to_io)?;
+    let pairs: Vec<_> = visited
+        .0
+        .iter()
+        .filter_map(|(key, val)| Some((rename(key.as_str(), reserved, collision)?, val)))
+        .collect();
+
+    // `a.b.c` conflicts with a key `a` or `a.b`, and with any key below it
+    let is_prefix = |prefix: &str, key: &str| {
+        key.len() > prefix.len() && key.starts_with(prefix) && key.as_bytes()[prefix.len()] == b'.'
+    };
+    let expands = |key: &str| {
+        key.split('.').count() > 1
+            && key.split('.').all(|segment| !segment.is_empty())
+            && !reserved.iter().any(|reserved| is_prefix(reserved, key))
+            && !pairs
+                .iter()
+                .any(|(other, _)| is_prefix(other, key) || is_prefix(key, other))
+    };
+
+    let mut root: Vec<(&str, Node)> = Vec::new();
+    for (key, val) in &pairs {
+        let key: &str = key;
+        if !expands(key) {
+            root.push((key, Node::Value(val)));
+            continue;
+        }
+
+        let mut segments = key.split('.').peekable();
+        let mut members = &mut root;
+        while let Some(segment) = segments.next() {
+            if segments.peek().is_none() {
+                members.push((segment, Node::Value(val)));
+                break;
+            }
+            let idx = members
+                .iter()
+                .position(|(name, node)| *name == segment && matches!(node, Node::Object(_)));
+            let idx = idx.unwrap_or_else(|| {
+                members.push((segment, Node::Object(Vec::new())));
+                members.len() - 1
+            });
+            members = match &mut members[idx].1 {
+                Node::Object(members) => members,
+                Node::Value(_) => unreachable!("only objects are descended into"),
+            };
+        }
+    }
+
+    if !root.is_empty() {
+        write!(out, ",")?;
+        write_members(out, &root)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    /// Time of the records of the fixtures
+    const TIME: u64 = 1_566_390_896_789;
+
+    /// How a record is written by each version of the layout, which must
+    /// never change once released; bumping `FMT_VERSION` adds one
+    const FIXTURES: &[(u32, &str)] = &[(
+        1,
+        "{\"fmt_version\":1,\"level\":40,\"time\":1566390896789,\"msg\":\"Disk almost full\",\"free\":\"2%\",\"mounts\":3}\n",
+    )];
+
+    fn write(femme: &Femme) -> String {
+        let pairs: &[(&str, kv::Value)] = &[("free", "2%".into()), ("mounts", 3.into())];
+        let record = Record::builder()
+            .level(Level::Warn)
+            .target("app")
+            .args(format_args!("Disk almost full"))
+            .key_values(&pairs)
+            .build();
+        let mut out = Vec::new();
+        write_header(&mut out, &record, TIME, femme).unwrap();
+        write_message(&mut out, &record, femme).unwrap();
+        write_pairs(&mut out, &record, femme, reserved(femme)).unwrap();
+        out.extend_from_slice(TERMINATOR);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn fmt_version_layout() {
+        let (_, fixture) = FIXTURES
+            .iter()
+            .find(|(version, _)| *version == crate::FMT_VERSION)
+            .expect("a fixture for the current version");
+        assert_eq!(write(&crate::ndjson().fmt_version(true)), *fixture);
+    }
+
+    #[test]
+    fn fmt_version_layout_bunyan() {
+        let femme = crate::ndjson().fmt_version(true).bunyan(true);
+        let expected = format!(
+            "{{\"fmt_version\":1,\"v\":0,\"level\":40,\"name\":{:?},\"hostname\":{:?},\"pid\":{},\"time\":\"2019-08-21T12:34:56.789Z\",\"msg\":\"Disk almost full\",\"free\":\"2%\",\"mounts\":3}}\n",
+            femme.syslog.app_name().unwrap_or("-"),
+            femme.syslog.hostname().unwrap_or("localhost"),
+            std::process::id(),
+        );
+        assert_eq!(write(&femme), expected);
+    }
+
+    #[test]
+    fn bunyan() {
+        if crate::testing::compiled_out() {
+            return;
+        }
+        let femme = crate::ndjson().bunyan(true).syslog_app_name("server");
+        let output = crate::testing::log(femme, Level::Info, "Listening on port 8080", &[]);
+        assert!(output.starts_with("{\"v\":0,\"level\":30,\"name\":\"server\",\"hostname\":"));
+        assert!(output.contains(&format!(",\"pid\":{},\"time\":\"", std::process::id())));
+    }
+
+    #[test]
+    fn multiline_split() {
+        if crate::testing::compiled_out() {
+            return;
+        }
+        let output = crate::testing::log(
+            crate::ndjson().multiline(Multiline::Split),
+            Level::Error,
+            "Query failed:\nsyntax error at \"FORM\"",
+            &[("table", "users".into())],
+        );
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(",\"msg\":\"Query failed:\",\"table\":\"users\"}"));
+        assert!(lines[1].ends_with(
+            ",\"msg\":\"syntax error at \\\"FORM\\\"\",\"table\":\"users\",\"continuation\":1}"
+        ));
+    }
+
+    #[test]
+    fn key_collisions() {
+        if crate::testing::compiled_out() {
+            return;
+        }
+        let pairs: &[(&str, kv::Value)] = &[("msg", "dup".into()), ("level", 1.into())];
+        let line = |collision| {
+            let femme = crate::ndjson().key_collision(collision);
+            crate::testing::log(femme, Level::Info, "x", pairs)
+        };
+        assert!(
+            line(KeyCollision::Prefix).ends_with(",\"msg\":\"x\",\"_msg\":\"dup\",\"_level\":1}\n")
+        );
+        assert!(line(KeyCollision::Drop).ends_with(",\"msg\":\"x\"}\n"));
+        let overridden = line(KeyCollision::Override);
+        assert!(overridden.starts_with("{\"level\":1,\"time\":"));
+        assert!(overridden.ends_with(",\"msg\":\"dup\"}\n"));
+    }
+
+    #[test]
+    fn nested_keys() {
+        if crate::testing::compiled_out() {
+            return;
+        }
+        let pairs: &[(&str, kv::Value)] = &[
+            ("http.method", "GET".into()),
+            ("user", "alice".into()),
+            ("http.status", 200.into()),
+            ("a..b", 1.into()),
+            ("msg.id", 2.into()),
+        ];
+        let output = crate::testing::log(crate::ndjson().nest_keys(true), Level::Info, "x", pairs);
+        let msg = output.find(",\"msg\":").unwrap();
+        assert_eq!(
+            &output[msg..],
+            ",\"msg\":\"x\",\"http\":{\"method\":\"GET\",\"status\":200},\"user\":\"alice\",\"a..b\":1,\"msg.id\":2}\n"
+        );
+    }
+
+    #[test]
+    fn without_fmt_version() {
+        let fixture = FIXTURES[0].1.replacen("\"fmt_version\":1,", "", 1);
+        assert_eq!(write(&crate::ndjson()), fixture);
+    }
+}
@@ -0,0 +1,707 @@
+//! Kafka topics, records are produced to in batches.
+//!
+//! Each record is produced as a Kafka record whose value is the record as
+//! ndjson and whose key is its target, a Key/Value pair of it or nothing, as
+//! `KafkaKey` says. Keyed records go to a partition picked from the murmur2
+//! hash of the key, as Kafka's own clients pick it, so records with the same
+//! key keep their order; records without one go to a partition taken in
+//! turn for each batch.
+//!
+//! Batches are produced with the Produce API, version 3, as record batches
+//! of magic 2, to the leaders the Metadata API names, waiting for all
+//! in-sync replicas. A partition failing with an error Kafka says may pass,
+//! such as a leader moving, has the metadata looked up again and is retried
+//! on its own, so partitions already written aren't written twice; one
+//! failing otherwise, such as for a record too large, drops the batch.
+//!
+//! Brokers are spoken to over plaintext, without SASL, and batches aren't
+//! compressed.
+
+use crate::batch::{self, Batcher, Envelope, Limits};
+use log::{kv, Record};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Most bytes of records in a batch, under the `message.max.bytes` brokers
+/// allow by default
+const MAX_BYTES: usize = 1_000_000;
+
+/// How long connecting, a request or its response may take
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long brokers wait for the replicas to take a batch, in milliseconds
+const REPLICA_TIMEOUT: i32 = 10_000;
+
+/// Name of the client in requests
+const CLIENT_ID: &str = "femme";
+
+/// Keys and versions of the APIs used
+const PRODUCE: (i16, i16) = (0, 3);
+const METADATA: (i16, i16) = (3, 1);
+
+/// Error codes a request may succeed after, once the metadata is looked up
+/// again: unknown topic or partition, leader not available, not leader,
+/// request timed out, network exception, not enough replicas (after
+/// append) and storage error
+const RETRIABLE: [i16; 8] = [3, 5, 6, 7, 13, 19, 20, 56];
+
+/// What the key of each Kafka record is, see `Femme::kafka_key`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum KafkaKey {
+    /// The target of the record, so records of a module stay in order
+    #[default]
+    Target,
+
+    /// The value of a Key/Value pair of the record, such as a request ID;
+    /// records without it have no key
+    Field(String),
+
+    /// No key, records are spread over the partitions
+    None,
+}
+
+impl KafkaKey {
+    /// Key records by the value of one of their Key/Value pairs
+    pub fn field(key: impl Into<String>) -> Self {
+        KafkaKey::Field(key.into())
+    }
+
+    /// The key of a record
+    fn of(&self, record: &Record) -> Option<String> {
+        match self {
+            KafkaKey::Target => Some(record.target().to_string()),
+            KafkaKey::Field(key) => record
+                .key_values()
+                .get(kv::Key::from_str(key))
+                .map(|value| value.to_string()),
+            KafkaKey::None => None,
+        }
+    }
+}
+
+/// Format the opening of a record: its timestamp and key
+///
+/// The value follows as ndjson, behind its length, see `write_value`; the
+/// records of a batch are taken apart again by these lengths.
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+/// * `key` - What the key of the record is
+pub(crate) fn write_header<W: Write>(
+    out: &mut W,
+    record: &Record,
+    time: u64,
+    key: &KafkaKey,
+) -> io::Result<()> {
+    out.write_all(&(time as i64).to_be_bytes())?;
+    match key.of(record) {
+        Some(key) => {
+            out.write_all(&(key.len() as i32).to_be_bytes())?;
+            out.write_all(key.as_bytes())
+        }
+        None => out.write_all(&(-1i32).to_be_bytes()),
+    }
+}
+
+/// Format the value of a record, the record as ndjson
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `line` - The record as ndjson, with or without its newline
+pub(crate) fn write_value<W: Write>(out: &mut W, line: &[u8]) -> io::Result<()> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    out.write_all(&(line.len() as i32).to_be_bytes())?;
+    out.write_all(line)
+}
+
+/// Ends a record
+pub(crate) const TERMINATOR: &[u8] = b"\n";
+
+/// Keep the limits of a batch within what brokers take
+pub(crate) fn clamp(limits: Limits) -> Limits {
+    Limits {
+        max_bytes: limits.max_bytes.min(MAX_BYTES),
+        ..limits
+    }
+}
+
+/// A record of a batch, as `write_header` and `write_value` formatted it
+struct Message<'a> {
+    time: i64,
+    key: Option<&'a [u8]>,
+    value: &'a [u8],
+}
+
+/// Take the records of a batch apart
+fn messages(body: &[u8]) -> io::Result<Vec<Message<'_>>> {
+    let mut reader = Reader(body);
+    let mut messages = Vec::new();
+    while !reader.0.is_empty() {
+        if !messages.is_empty() {
+            // records are joined with commas
+            reader.take(1)?;
+        }
+        let time = reader.i64()?;
+        let key = reader.bytes()?;
+        let value = reader.bytes()?.unwrap_or_default();
+        messages.push(Message { time, key, value });
+    }
+    Ok(messages)
+}
+
+/// A Kafka request or response, being written
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn i16(&mut self, n: i16) {
+        self.0.extend_from_slice(&n.to_be_bytes());
+    }
+
+    fn i32(&mut self, n: i32) {
+        self.0.extend_from_slice(&n.to_be_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.i16(s.len() as i16);
+        self.0.extend_from_slice(s.as_bytes());
+    }
+
+    /// A zigzag varint, as record batches write their numbers
+    fn varint(&mut self, n: i64) {
+        let mut n = ((n << 1) ^ (n >> 63)) as u64;
+        while n >= 0x80 {
+            self.0.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        self.0.push(n as u8);
+    }
+
+    /// Bytes behind their length as a varint, -1 for none
+    fn varbytes(&mut self, bytes: Option<&[u8]>) {
+        match bytes {
+            Some(bytes) => {
+                self.varint(bytes.len() as i64);
+                self.0.extend_from_slice(bytes);
+            }
+            None => self.varint(-1),
+        }
+    }
+}
+
+/// A Kafka response, being read
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the Kafka message ended early",
+            ));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn i8(&mut self) -> io::Result<i8> {
+        Ok(self.take(1)?[0] as i8)
+    }
+
+    fn i16(&mut self) -> io::Result<i16> {
+        let bytes = self.take(2)?;
+        Ok(i16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(i32::from_be_bytes(bytes))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(i64::from_be_bytes(bytes))
+    }
+
+    /// Bytes behind their length as an `i32`, -1 for none
+    fn bytes(&mut self) -> io::Result<Option<&'a [u8]>> {
+        match self.i32()? {
+            len if len < 0 => Ok(None),
+            len => Ok(Some(self.take(len as usize)?)),
+        }
+    }
+
+    /// A string behind its length as an `i16`, -1 for none
+    fn string(&mut self) -> io::Result<Option<String>> {
+        match self.i16()? {
+            len if len < 0 => Ok(None),
+            len => Ok(Some(
+                String::from_utf8_lossy(self.take(len as usize)?).into_owned(),
+            )),
+        }
+    }
+
+    /// The length of an array
+    fn len(&mut self) -> io::Result<usize> {
+        Ok(self.i32()?.max(0) as usize)
+    }
+}
+
+/// Encode records as a record batch
+fn record_batch(messages: &[&Message]) -> Vec<u8> {
+    let first = messages
+        .iter()
+        .map(|message| message.time)
+        .min()
+        .unwrap_or(0);
+    let max = messages
+        .iter()
+        .map(|message| message.time)
+        .max()
+        .unwrap_or(0);
+
+    let mut records = Writer(Vec::new());
+    for (delta, message) in messages.iter().enumerate() {
+        let mut record = Writer(Vec::new());
+        // attributes, then the timestamp and offset deltas
+        record.0.push(0);
+        record.varint(message.time - first);
+        record.varint(delta as i64);
+        record.varbytes(message.key);
+        record.varbytes(Some(message.value));
+        // headers
+        record.varint(0);
+        records.varint(record.0.len() as i64);
+        records.0.extend_from_slice(&record.0);
+    }
+
+    // what the checksum covers, from the attributes on
+    let mut checked = Writer(Vec::new());
+    checked.i16(0);
+    checked.i32(messages.len() as i32 - 1);
+    checked.0.extend_from_slice(&first.to_be_bytes());
+    checked.0.extend_from_slice(&max.to_be_bytes());
+    // no producer ID, epoch or sequence, as idempotence is off
+    checked.0.extend_from_slice(&(-1i64).to_be_bytes());
+    checked.i16(-1);
+    checked.i32(-1);
+    checked.i32(messages.len() as i32);
+    checked.0.extend_from_slice(&records.0);
+
+    let mut batch = Writer(Vec::new());
+    batch.0.extend_from_slice(&0i64.to_be_bytes());
+    // the length counts what follows it: the leader epoch, magic, checksum
+    // and the checked part
+    batch.i32(4 + 1 + 4 + checked.0.len() as i32);
+    batch.i32(-1);
+    batch.0.push(2);
+    batch.0.extend_from_slice(&crc32c(&checked.0).to_be_bytes());
+    batch.0.extend_from_slice(&checked.0);
+    batch.0
+}
+
+/// CRC-32C (Castagnoli), the checksum of record batches
+fn crc32c(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0x82f6_3b78,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+/// Kafka's murmur2, which its clients pick the partition of a key by
+fn murmur2(data: &[u8]) -> u32 {
+    const M: u32 = 0x5bd1_e995;
+    let mut h = 0x9747_b28c ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> 24;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, &byte) in rest.iter().enumerate() {
+            h ^= u32::from(byte) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^ (h >> 15)
+}
+
+/// A record batch for a partition, not yet written
+struct Pending {
+    partition: i32,
+    batch: Vec<u8>,
+}
+
+/// The brokers of a cluster and the partitions of the topic
+struct Cluster {
+    /// Brokers the metadata is first asked of, as `host:port`
+    bootstrap: Vec<String>,
+    topic: String,
+
+    /// Address of each broker, by node ID
+    brokers: HashMap<i32, String>,
+
+    /// Leader of each partition, by partition, if the metadata was looked up
+    leaders: Option<Vec<i32>>,
+
+    /// Open connections, by node ID
+    connections: HashMap<i32, TcpStream>,
+    correlation: i32,
+
+    /// Partition the next batch's records without a key go to
+    next: usize,
+
+    /// A batch that partly failed, and the partitions it still has to be
+    /// written to
+    unfinished: Option<(Vec<u8>, Vec<Pending>)>,
+}
+
+impl Cluster {
+    /// Send a request and read its response, without its header
+    ///
+    /// # Arguments
+    /// * `stream` - Connection to the broker
+    /// * `api` - Key and version of the API
+    /// * `correlation` - ID of the request
+    /// * `body` - The request, without its header
+    fn call(
+        stream: &mut TcpStream,
+        (key, version): (i16, i16),
+        correlation: i32,
+        body: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let mut request = Writer(Vec::new());
+        request.i32(0);
+        request.i16(key);
+        request.i16(version);
+        request.i32(correlation);
+        request.string(CLIENT_ID);
+        request.0.extend_from_slice(body);
+        let len = (request.0.len() - 4) as i32;
+        request.0[..4].copy_from_slice(&len.to_be_bytes());
+        stream.write_all(&request.0)?;
+
+        let mut len = [0; 4];
+        stream.read_exact(&mut len)?;
+        let mut response = vec![0; i32::from_be_bytes(len).max(0) as usize];
+        stream.read_exact(&mut response)?;
+        let mut reader = Reader(&response);
+        if reader.i32()? != correlation {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "a Kafka response answered another request",
+            ));
+        }
+        Ok(response.split_off(4))
+    }
+
+    /// Connect to a broker, or reuse the connection to it
+    fn connect(&mut self, node: i32) -> io::Result<&mut TcpStream> {
+        if !self.connections.contains_key(&node) {
+            let address = self.brokers.get(&node).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Kafka broker {} isn't in the metadata", node),
+                )
+            })?;
+            let stream = open(address)?;
+            self.connections.insert(node, stream);
+        }
+        Ok(self.connections.get_mut(&node).unwrap())
+    }
+
+    /// Look the brokers and the leaders of the partitions up, asking the
+    /// bootstrap brokers in turn
+    fn refresh(&mut self) -> io::Result<()> {
+        let mut request = Writer(Vec::new());
+        request.i32(1);
+        request.string(&self.topic);
+
+        let mut last = None;
+        for address in &self.bootstrap {
+            self.correlation = self.correlation.wrapping_add(1);
+            let response = open(address).and_then(|mut stream| {
+                Self::call(&mut stream, METADATA, self.correlation, &request.0)
+            });
+            match response {
+                Ok(response) => {
+                    let (brokers, leaders) = metadata(&response, &self.topic)?;
+                    self.brokers = brokers;
+                    self.leaders = Some(leaders);
+                    self.connections.clear();
+                    return Ok(());
+                }
+                Err(err) => last = Some(err),
+            }
+        }
+        Err(last.unwrap_or_else(|| io::ErrorKind::NotFound.into()))
+    }
+
+    /// Split a batch into record batches by partition
+    fn assign(&mut self, body: &[u8]) -> io::Result<Vec<Pending>> {
+        let partitions = match &self.leaders {
+            Some(leaders) => leaders.len(),
+            None => 0,
+        };
+        if partitions == 0 {
+            return Err(io::Error::other("the Kafka topic has no partitions"));
+        }
+        let messages = messages(body)?;
+        let unkeyed = self.next % partitions;
+        self.next = self.next.wrapping_add(1);
+
+        let mut by_partition: Vec<Vec<&Message>> = (0..partitions).map(|_| Vec::new()).collect();
+        for message in &messages {
+            let partition = match message.key {
+                Some(key) => (murmur2(key) & 0x7fff_ffff) as usize % partitions,
+                None => unkeyed,
+            };
+            by_partition[partition].push(message);
+        }
+        Ok(by_partition
+            .iter()
+            .enumerate()
+            .filter(|(_, messages)| !messages.is_empty())
+            .map(|(partition, messages)| Pending {
+                partition: partition as i32,
+                batch: record_batch(messages),
+            })
+            .collect())
+    }
+
+    /// Write record batches to the leaders of their partitions
+    ///
+    /// Returns the ones that failed with an error that may pass, or fails
+    /// with `InvalidInput` if any failed otherwise.
+    fn produce(&mut self, pending: Vec<Pending>) -> io::Result<Vec<Pending>> {
+        let leaders = self.leaders.clone().unwrap_or_default();
+        let mut by_leader: HashMap<i32, Vec<Pending>> = HashMap::new();
+        for pending in pending {
+            let leader = leaders
+                .get(pending.partition as usize)
+                .copied()
+                .unwrap_or(-1);
+            by_leader.entry(leader).or_default().push(pending);
+        }
+
+        let mut failed = Vec::new();
+        let mut refused = None;
+        for (leader, pending) in by_leader {
+            match self.produce_to(leader, &pending) {
+                Ok(errors) => {
+                    for (pending, error) in pending.into_iter().zip(errors) {
+                        match error {
+                            0 => {}
+                            code if RETRIABLE.contains(&code) => failed.push(pending),
+                            code => refused = Some(code),
+                        }
+                    }
+                }
+                Err(_) => {
+                    self.connections.remove(&leader);
+                    failed.extend(pending);
+                }
+            }
+        }
+        match refused {
+            Some(code) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Kafka refused the records with error code {}", code),
+            )),
+            None => Ok(failed),
+        }
+    }
+
+    /// Write record batches to a broker
+    ///
+    /// Returns the error code of each, in order.
+    fn produce_to(&mut self, node: i32, pending: &[Pending]) -> io::Result<Vec<i16>> {
+        let mut request = Writer(Vec::new());
+        // no transactional ID, acks from all in-sync replicas
+        request.i16(-1);
+        request.i16(-1);
+        request.i32(REPLICA_TIMEOUT);
+        request.i32(1);
+        request.string(&self.topic);
+        request.i32(pending.len() as i32);
+        for pending in pending {
+            request.i32(pending.partition);
+            request.i32(pending.batch.len() as i32);
+            request.0.extend_from_slice(&pending.batch);
+        }
+
+        self.correlation = self.correlation.wrapping_add(1);
+        let correlation = self.correlation;
+        let stream = self.connect(node)?;
+        let response = Self::call(stream, PRODUCE, correlation, &request.0)?;
+
+        let mut codes: HashMap<i32, i16> = HashMap::new();
+        let mut reader = Reader(&response);
+        for _ in 0..reader.len()? {
+            reader.string()?;
+            for _ in 0..reader.len()? {
+                let partition = reader.i32()?;
+                let code = reader.i16()?;
+                // base offset and log append time
+                reader.i64()?;
+                reader.i64()?;
+                codes.insert(partition, code);
+            }
+        }
+        // a partition left out of the response is taken as timed out
+        Ok(pending
+            .iter()
+            .map(|pending| codes.get(&pending.partition).copied().unwrap_or(7))
+            .collect())
+    }
+
+    /// Produce a batch, or what's left of it after a failure
+    fn send(&mut self, body: &[u8]) -> io::Result<()> {
+        if self.leaders.is_none() {
+            self.refresh()?;
+        }
+        let pending = match self.unfinished.take() {
+            Some((unfinished, pending)) if unfinished == body => pending,
+            _ => self.assign(body)?,
+        };
+        let failed = self.produce(pending)?;
+        if failed.is_empty() {
+            return Ok(());
+        }
+
+        let partitions: Vec<String> = failed
+            .iter()
+            .map(|pending| pending.partition.to_string())
+            .collect();
+        self.unfinished = Some((body.to_vec(), failed));
+        // whatever went wrong, the leaders may have moved
+        self.leaders = None;
+        Err(io::Error::other(format!(
+            "Kafka partitions {} of {} failed to take the records",
+            partitions.join(", "),
+            self.topic
+        )))
+    }
+}
+
+/// The brokers, and the leader of each partition of the topic, from a
+/// Metadata response
+fn metadata(response: &[u8], topic: &str) -> io::Result<(HashMap<i32, String>, Vec<i32>)> {
+    let mut reader = Reader(response);
+    let mut brokers = HashMap::new();
+    for _ in 0..reader.len()? {
+        let node = reader.i32()?;
+        let host = reader.string()?.unwrap_or_default();
+        let port = reader.i32()?;
+        // rack
+        reader.string()?;
+        brokers.insert(node, format!("{}:{}", host, port));
+    }
+    // controller
+    reader.i32()?;
+
+    let mut leaders = Vec::new();
+    for _ in 0..reader.len()? {
+        let code = reader.i16()?;
+        let name = reader.string()?;
+        // internal
+        reader.i8()?;
+        let partitions = reader.len()?;
+        let mut found = Vec::with_capacity(partitions);
+        for _ in 0..partitions {
+            // error code
+            reader.i16()?;
+            let partition = reader.i32()?;
+            let leader = reader.i32()?;
+            for _ in 0..2 {
+                // replicas and in-sync replicas
+                for _ in 0..reader.len()? {
+                    reader.i32()?;
+                }
+            }
+            found.push((partition, leader));
+        }
+        if name.as_deref() != Some(topic) {
+            continue;
+        }
+        if code != 0 {
+            return Err(io::Error::other(format!(
+                "Kafka has no metadata for {}, error code {}",
+                topic, code
+            )));
+        }
+        found.sort();
+        leaders = found.into_iter().map(|(_, leader)| leader).collect();
+    }
+    Ok((brokers, leaders))
+}
+
+/// Connect to a broker
+fn open(address: &str) -> io::Result<TcpStream> {
+    let addr = address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} didn't resolve to an address", address),
+        )
+    })?;
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    Ok(stream)
+}
+
+/// Start producing batches of records to a topic
+///
+/// # Arguments
+/// * `brokers` - Bootstrap brokers, as a comma-separated list of `host:port`
+/// * `topic` - The topic
+/// * `name` - Name of the destination, for reports
+pub(crate) fn start(brokers: &str, topic: &str, name: String) -> io::Result<Batcher> {
+    let bootstrap: Vec<String> = brokers
+        .split(',')
+        .map(str::trim)
+        .filter(|broker| !broker.is_empty())
+        .map(String::from)
+        .collect();
+    if bootstrap.is_empty() || topic.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Kafka needs a broker and a topic",
+        ));
+    }
+    let mut cluster = Cluster {
+        bootstrap,
+        topic: topic.to_string(),
+        brokers: HashMap::new(),
+        leaders: None,
+        connections: HashMap::new(),
+        correlation: 0,
+        next: 0,
+        unfinished: None,
+    };
+    let envelope = Envelope {
+        prefix: Vec::new(),
+        suffix: Vec::new(),
+    };
+    let send: batch::Send = Box::new(move |body, _, _, _| cluster.send(body));
+    Batcher::start(name, envelope, send)
+}
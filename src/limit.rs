@@ -0,0 +1,226 @@
+//! Records cut down to a size, see `Femme::max_record_size`.
+//!
+//! A record formatted larger than the limit loses its largest Key/Value
+//! pairs first, one at a time, until it fits; if it still doesn't without
+//! any, its message is cut short as well. The keys it lost are named in a
+//! `truncated_fields` pair, which JSON output writes as an array, and a cut
+//! message is marked by a `msg_truncated` pair:
+//!
+//! ```text
+//! {"level":30,"time":1566390896789,"msg":"Upload done","file":"a.png","truncated_fields":["body"]}
+//! {"level":30,"time":1566390896789,"msg":"Upload d…","truncated_fields":["body","file"],"msg_truncated":true}
+//! ```
+
+use crate::capture::{self, Captured};
+use crate::escape;
+use log::{kv, Record};
+use std::fmt;
+
+/// Key of the pair naming the pairs cut
+const TRUNCATED_KEY: &str = "truncated_fields";
+
+/// Key of the pair saying the message was cut
+const MSG_TRUNCATED_KEY: &str = "msg_truncated";
+
+/// Smallest limit allowed, which leaves room for the header of a record
+pub(crate) const MIN_MAX_SIZE: usize = 256;
+
+/// Names of the keys cut, formatted as a JSON array of strings
+struct Names(Vec<String>);
+
+impl fmt::Display for Names {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = Vec::new();
+        out.push(b'[');
+        for (i, name) in self.0.iter().enumerate() {
+            if i > 0 {
+                out.push(b',');
+            }
+            escape::json_str(&mut out, name).map_err(|_| fmt::Error)?;
+        }
+        out.push(b']');
+        let text = String::from_utf8_lossy(&out);
+        capture::offer(|| Captured::Names(text.to_string()));
+        f.write_str(&text)
+    }
+}
+
+/// The size of each pair of a record, as its key and value are formatted
+struct Sizes(Vec<(String, usize)>);
+
+impl<'kvs> kv::Visitor<'kvs> for Sizes {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, val: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let key = key.as_str();
+        self.0
+            .push((key.to_string(), key.len() + val.to_string().len()));
+        Ok(())
+    }
+}
+
+/// The pairs of a record without those dropped
+struct Without<'a> {
+    source: &'a dyn kv::Source,
+    dropped: &'a [String],
+}
+
+impl kv::Source for Without<'_> {
+    fn visit<'kvs>(&'kvs self, visitor: &mut dyn kv::Visitor<'kvs>) -> Result<(), kv::Error> {
+        struct Kept<'a, 'v, 'kvs> {
+            visitor: &'v mut dyn kv::Visitor<'kvs>,
+            dropped: &'a [String],
+        }
+
+        impl<'kvs> kv::Visitor<'kvs> for Kept<'_, '_, 'kvs> {
+            fn visit_pair(
+                &mut self,
+                key: kv::Key<'kvs>,
+                val: kv::Value<'kvs>,
+            ) -> Result<(), kv::Error> {
+                match self.dropped.iter().any(|dropped| dropped == key.as_str()) {
+                    true => Ok(()),
+                    false => self.visitor.visit_pair(key, val),
+                }
+            }
+        }
+
+        self.source.visit(&mut Kept {
+            visitor,
+            dropped: self.dropped,
+        })
+    }
+}
+
+/// Call `f` with the record cut down until `measure` finds it no larger
+/// than `max`
+///
+/// Records that fit are passed on as they are. One still too large without
+/// any pairs and with an empty message is passed on all the same.
+///
+/// # Arguments
+/// * `record` - Record to write
+/// * `max` - Most bytes of the formatted record
+/// * `measure` - Formats a record, returning its size
+/// * `f` - Callback receiving the record to write
+pub(crate) fn fit<T>(
+    record: &Record,
+    max: usize,
+    mut measure: impl FnMut(&Record) -> usize,
+    f: impl FnOnce(&Record) -> T,
+) -> T {
+    let mut sizes = Sizes(Vec::new());
+    let _ = record.key_values().visit(&mut sizes);
+    // the largest first, and of those the last logged
+    let mut largest: Vec<String> = Vec::new();
+    sizes.0.sort_by_key(|(_, size)| *size);
+    for (key, _) in sizes.0.into_iter().rev() {
+        if !largest.contains(&key) {
+            largest.push(key);
+        }
+    }
+    let mut largest = largest.into_iter();
+
+    let mut dropped: Vec<String> = Vec::new();
+    let mut message: Option<String> = None;
+    loop {
+        let names = Names(dropped.clone());
+        let kept = Without {
+            source: record.key_values(),
+            dropped: &dropped,
+        };
+        let marker =
+            Some((TRUNCATED_KEY, kv::Value::from_display(&names))).filter(|_| !names.0.is_empty());
+        let msg_marker = message.as_ref().map(|_| (MSG_TRUNCATED_KEY, true));
+        let sources: &[&dyn kv::Source] = &[&kept, &marker, &msg_marker];
+        let text: &dyn fmt::Display = match &message {
+            Some(message) => message,
+            None => record.args(),
+        };
+        let args = format_args!("{}", text);
+        let cut = record.to_builder().args(args).key_values(&sources).build();
+
+        let size = measure(&cut);
+        if size <= max {
+            return f(&cut);
+        }
+        if let Some(key) = largest.next() {
+            dropped.push(key);
+            continue;
+        }
+
+        // without any pairs, the message is cut by what's left over, and
+        // again until it fits
+        let text = text.to_string();
+        if text.is_empty() {
+            return f(&cut);
+        }
+        let mut end = text.len().saturating_sub(size - max + '…'.len_utf8());
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        message = Some(match end {
+            0 => String::new(),
+            end => format!("{}…", &text[..end]),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use log::Level;
+
+    #[test]
+    fn drops_largest_pairs() {
+        if testing::compiled_out() {
+            return;
+        }
+        let body = "x".repeat(4096);
+        let output = testing::log(
+            crate::ndjson().max_record_size(1024),
+            Level::Warn,
+            "Upload refused",
+            &[("file", "a.png".into()), ("body", body.as_str().into())],
+        );
+        assert!(output.len() <= 1024);
+        assert!(output.ends_with(
+            ",\"msg\":\"Upload refused\",\"file\":\"a.png\",\"truncated_fields\":[\"body\"]}\n"
+        ));
+    }
+
+    #[test]
+    fn cuts_message() {
+        if testing::compiled_out() {
+            return;
+        }
+        let message = "y".repeat(1024);
+        let output = testing::log(
+            crate::ndjson().max_record_size(256),
+            Level::Info,
+            &message,
+            &[
+                ("msg", "message".into()),
+                ("time", 3.into()),
+                ("level", 10.into()),
+            ],
+        );
+        assert!(output.len() <= 256);
+        assert!(output.contains("yyy…\","));
+        assert!(output.ends_with(
+            ",\"truncated_fields\":[\"msg\",\"level\",\"time\"],\"msg_truncated\":true}\n"
+        ));
+    }
+
+    #[test]
+    fn names_only_its_own_marker() {
+        if testing::compiled_out() {
+            return;
+        }
+        let output = testing::log(
+            crate::ndjson().max_record_size(256),
+            Level::Info,
+            "x",
+            &[("truncated_fields", "[\"fake\"]".into())],
+        );
+        assert!(output.ends_with(",\"truncated_fields\":\"[\\\"fake\\\"]\"}\n"));
+    }
+}
@@ -0,0 +1,238 @@
+//! logfmt lines, as Heroku, Grafana Loki and many Go services read them.
+//!
+//! A record becomes a line of `key=value` pairs:
+//!
+//! ```text
+//! ts=2019-08-21T12:34:56.789Z level=info target=app::server msg="Listening on port 8080" port=8080
+//! ```
+//!
+//! Values are quoted when they are empty or hold spaces, `=` or quotes, with
+//! quotes, backslashes and control characters escaped; keys lose those
+//! characters to underscores, and an empty key becomes `_`. Pairs whose key
+//! is one the line starts with follow `Femme::key_collision`, as in ndjson.
+
+use crate::escape::Scalar;
+use crate::json::{overridden, rename};
+use crate::{time, Femme};
+use log::{kv, Record};
+use std::io::{self, Write};
+
+/// Keys every line starts with, in the order they are written
+pub(crate) const RESERVED: [&str; 4] = ["ts", "level", "target", "msg"];
+
+/// Keys a Loki line starts with, as it has no timestamp of its own
+pub(crate) const LINE_RESERVED: [&str; 3] = ["level", "target", "msg"];
+
+/// Format the opening of a line, up to the value of `msg`
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+/// * `femme` - Logger configuration
+pub(crate) fn write_header<W: Write>(
+    out: &mut W,
+    record: &Record,
+    time: u64,
+    femme: &Femme,
+) -> io::Result<()> {
+    write!(out, "ts=")?;
+    match overridden(record, femme, "ts") {
+        Some(ts) => write_kv_value(out, &ts)?,
+        None => time::write_rfc3339(out, time)?,
+    }
+    write!(out, " ")?;
+    write_source(out, record, femme)?;
+    write!(out, " msg=")
+}
+
+/// Format `level` and `target`, which a Loki line starts with
+pub(crate) fn write_source<W: Write>(
+    out: &mut W,
+    record: &Record,
+    femme: &Femme,
+) -> io::Result<()> {
+    write!(out, "level=")?;
+    match overridden(record, femme, "level") {
+        Some(level) => write_kv_value(out, &level)?,
+        None => write!(out, "{}", record.level().to_string().to_ascii_lowercase())?,
+    }
+    write!(out, " target=")?;
+    match overridden(record, femme, "target") {
+        Some(target) => write_kv_value(out, &target),
+        None => write_value(out, record.target()),
+    }
+}
+
+/// Format the value of `msg`
+pub(crate) fn write_message<W: Write>(
+    out: &mut W,
+    record: &Record,
+    femme: &Femme,
+) -> io::Result<()> {
+    match overridden(record, femme, "msg") {
+        Some(msg) => write_kv_value(out, &msg),
+        None => write_value(out, &record.args().to_string()),
+    }
+}
+
+/// Format Key/Value pairs, each after a space
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `femme` - Logger configuration
+/// * `reserved` - Keys the line starts with, such as `RESERVED`
+pub(crate) fn write_pairs<W: Write>(
+    out: &mut W,
+    record: &Record,
+    femme: &Femme,
+    reserved: &[&str],
+) -> io::Result<()> {
+    struct Visitor<'a, 'r, W> {
+        out: &'a mut W,
+        reserved: &'r [&'r str],
+        collision: crate::KeyCollision,
+    }
+
+    impl<'kvs, 'a, 'r, W: Write> kv::Visitor<'kvs> for Visitor<'a, 'r, W> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            let key = match rename(key.as_str(), self.reserved, self.collision) {
+                Some(key) => key,
+                None => return Ok(()),
+            };
+            let key: String = match key.is_empty() {
+                true => "_".to_string(),
+                false => key
+                    .chars()
+                    .map(|c| match c {
+                        c if c <= ' ' || matches!(c, '=' | '"') || c.is_control() => '_',
+                        c => c,
+                    })
+                    .collect(),
+            };
+            write!(self.out, " {}=", key)?;
+            write_kv_value(self.out, &val)?;
+            Ok(())
+        }
+    }
+
+    let mut visitor = Visitor {
+        out,
+        reserved,
+        collision: femme.key_collision.unwrap_or_default(),
+    };
+    record
+        .key_values()
+        .visit(&mut visitor)
+        .map_err(crate::error::kv_to_io)?;
+    Ok(())
+}
+
+/// Write a logged value, numbers and booleans as they are
+fn write_kv_value<W: Write>(out: &mut W, val: &kv::Value) -> io::Result<()> {
+    let text = val.to_string();
+    let mut unescaped = String::new();
+    match Scalar::parse(&text, &mut unescaped) {
+        Scalar::Raw(raw) => write!(out, "{}", raw),
+        Scalar::Str(text) | Scalar::Text(text) => write_value(out, text),
+    }
+}
+
+/// Write a value, quoted if it has to be
+pub(crate) fn write_value<W: Write>(out: &mut W, value: &str) -> io::Result<()> {
+    let quote = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c <= ' ' || matches!(c, '=' | '"' | '\\') || c.is_control());
+    if !quote {
+        return out.write_all(value.as_bytes());
+    }
+
+    out.write_all(b"\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing, KeyCollision};
+    use log::Level;
+
+    #[test]
+    fn layout() {
+        if testing::compiled_out() {
+            return;
+        }
+        let output = testing::log(
+            crate::logfmt(),
+            Level::Info,
+            "listening",
+            &[("port", 8080.into()), ("user", "Jane Doe".into())],
+        );
+        assert!(output.starts_with("ts="));
+        assert!(
+            output.ends_with(" level=info target=app msg=listening port=8080 user=\"Jane Doe\"\n")
+        );
+    }
+
+    #[test]
+    fn keys() {
+        if testing::compiled_out() {
+            return;
+        }
+        let pairs: &[(&str, kv::Value)] = &[
+            ("msg", "dup".into()),
+            ("level", 1.into()),
+            ("time", 3.into()),
+            ("a b=\"c\"", "".into()),
+            ("", "e".into()),
+        ];
+        let line = |collision| {
+            let femme = crate::logfmt().key_collision(collision);
+            testing::log(femme, Level::Info, "x", pairs)
+        };
+        assert!(line(KeyCollision::Prefix)
+            .ends_with(" level=info target=app msg=x _msg=dup _level=1 time=3 a_b__c_=\"\" _=e\n"));
+        assert!(line(KeyCollision::Drop)
+            .ends_with(" level=info target=app msg=x time=3 a_b__c_=\"\" _=e\n"));
+        assert!(line(KeyCollision::Override)
+            .ends_with(" level=1 target=app msg=dup time=3 a_b__c_=\"\" _=e\n"));
+    }
+
+    #[test]
+    fn values() {
+        let mut out = Vec::new();
+        for value in [
+            "plain",
+            "",
+            "a b",
+            "a=b",
+            "say \"hi\"",
+            "back\\slash",
+            "1\n2\t\u{1}",
+        ] {
+            write_value(&mut out, value).unwrap();
+            out.push(b' ');
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "plain \"\" \"a b\" \"a=b\" \"say \\\"hi\\\"\" \"back\\\\slash\" \"1\\n2\\t\\u0001\" "
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! JSON events in the shape Logstash gives its own, for ELK pipelines.
+//!
+//! A record becomes a line of JSON:
+//!
+//! ```text
+//! {"@timestamp":"2019-08-21T12:34:56.789Z","@version":"1","level":"INFO","logger_name":"app::server","message":"Listening on port 8080","port":8080}
+//! ```
+//!
+//! Key/Value pairs are top-level fields, as in ndjson output, and collide
+//! with the fields above the same way.
+
+use crate::{escape, json, time, Femme};
+use log::Record;
+use std::io::{self, Write};
+
+/// Fields femme writes itself, in the order they are written
+pub(crate) const RESERVED: [&str; 5] =
+    ["@timestamp", "@version", "level", "logger_name", "message"];
+
+/// Format the opening of an event, up to the value of `message`
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+/// * `femme` - Logger configuration
+pub(crate) fn write_header<W: Write>(
+    out: &mut W,
+    record: &Record,
+    time: u64,
+    femme: &Femme,
+) -> io::Result<()> {
+    write!(out, "{{\"@timestamp\":")?;
+    match json::overridden(record, femme, "@timestamp") {
+        Some(timestamp) => escape::json_value(out, &timestamp)?,
+        None => {
+            write!(out, "\"")?;
+            time::write_rfc3339(out, time)?;
+            write!(out, "\"")?;
+        }
+    }
+    write!(out, ",\"@version\":\"1\",\"level\":")?;
+    match json::overridden(record, femme, "level") {
+        Some(level) => escape::json_value(out, &level)?,
+        None => write!(out, "\"{}\"", record.level())?,
+    }
+    write!(out, ",\"logger_name\":")?;
+    match json::overridden(record, femme, "logger_name") {
+        Some(name) => escape::json_value(out, &name)?,
+        None => escape::json_str(out, record.target())?,
+    }
+    write!(out, ",\"message\":")
+}
+
+/// Format the value of `message`
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `femme` - Logger configuration
+pub(crate) fn write_message<W: Write>(
+    out: &mut W,
+    record: &Record,
+    femme: &Femme,
+) -> io::Result<()> {
+    match json::overridden(record, femme, "message") {
+        Some(message) => escape::json_value(out, &message),
+        None => escape::json_str(out, record.args()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use log::Level;
+
+    #[test]
+    fn layout() {
+        if testing::compiled_out() {
+            return;
+        }
+        let output = testing::log(
+            crate::logstash(),
+            Level::Info,
+            "Listening on port 8080",
+            &[("port", 8080.into())],
+        );
+        assert!(output.starts_with("{\"@timestamp\":\""));
+        assert!(output.contains(",\"@version\":\"1\",\"level\":\"INFO\",\"logger_name\":\"app\","));
+        assert!(output.ends_with(",\"message\":\"Listening on port 8080\",\"port\":8080}\n"));
+    }
+}
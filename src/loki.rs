@@ -0,0 +1,132 @@
+//! Grafana Loki streams, pushed in batches over HTTP.
+//!
+//! Each record becomes a stream of its own in the JSON body of a push, with
+//! its labels and a single entry, whose line is the record as logfmt:
+//!
+//! ```text
+//! {"stream":{"level":"info","app":"web"},"values":[["1566390896789000000","level=info target=app::server msg=\"Listening on port 8080\" port=8080"]]}
+//! ```
+//!
+//! Loki merges the entries of streams with the same labels, so records of a
+//! batch needn't be grouped. Labels should be few and of low cardinality:
+//! the level by default, the target if asked for, and any fixed ones, such
+//! as the name of the app.
+
+use crate::batch::{self, Batcher, Envelope};
+use crate::{escape, http, logfmt, Femme};
+use log::Record;
+use std::io::{self, Write};
+
+/// Path of the push API, used when the URL has none
+const PATH: &str = "/loki/api/v1/push";
+
+/// The labels of each stream, see `Femme::loki_label`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Labels {
+    /// Whether the level is a label
+    pub(crate) level: bool,
+
+    /// Whether the target is a label
+    pub(crate) target: bool,
+
+    /// Labels with the same value for every record
+    pub(crate) fixed: Vec<(String, String)>,
+}
+
+impl Default for Labels {
+    fn default() -> Self {
+        Labels {
+            level: true,
+            target: false,
+            fixed: Vec::new(),
+        }
+    }
+}
+
+/// Format the labels and the timestamp of the entry, up to its line
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+/// * `labels` - Which labels the stream has
+pub(crate) fn write_header<W: Write>(
+    out: &mut W,
+    record: &Record,
+    time: u64,
+    labels: &Labels,
+) -> io::Result<()> {
+    write!(out, "{{\"stream\":{{")?;
+    let level = record.level().to_string().to_ascii_lowercase();
+    let derived = [
+        ("level", level.as_str(), labels.level),
+        ("target", record.target(), labels.target),
+    ];
+    let derived = derived
+        .iter()
+        .filter(|(_, _, set)| *set)
+        .map(|(name, value, _)| (*name, *value));
+    let fixed = labels
+        .fixed
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()));
+    for (i, (name, value)) in derived.chain(fixed).enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write_label_name(out, name)?;
+        write!(out, ":")?;
+        escape::json_str(out, value)?;
+    }
+    write!(out, "}},\"values\":[[\"{}000000\",", time)
+}
+
+/// Format the line of the entry, the record as logfmt without its timestamp
+pub(crate) fn write_line<W: Write>(out: &mut W, record: &Record, femme: &Femme) -> io::Result<()> {
+    let mut line = Vec::new();
+    logfmt::write_source(&mut line, record, femme)?;
+    write!(line, " msg=")?;
+    logfmt::write_message(&mut line, record, femme)?;
+    logfmt::write_pairs(&mut line, record, femme, &logfmt::LINE_RESERVED)?;
+    escape::json_str(out, String::from_utf8_lossy(&line))
+}
+
+/// Ends an entry and its stream
+pub(crate) const TERMINATOR: &[u8] = b"]]}\n";
+
+/// Write a label name, with the characters Loki doesn't allow replaced by
+/// underscores
+fn write_label_name<W: Write>(out: &mut W, name: &str) -> io::Result<()> {
+    let name: String = name
+        .chars()
+        .enumerate()
+        .map(|(i, c)| match c {
+            'a'..='z' | 'A'..='Z' | '_' => c,
+            '0'..='9' if i > 0 => c,
+            _ => '_',
+        })
+        .collect();
+    write!(out, "\"{}\"", name)
+}
+
+/// Start pushing batches of streams to Loki
+///
+/// # Arguments
+/// * `url` - Loki, such as `http://localhost:3100`
+/// * `name` - Name of the destination, for reports
+pub(crate) fn start(url: &str, name: String) -> io::Result<Batcher> {
+    let mut url = http::Url::parse(url)?;
+    if url.path == "/" {
+        url.path = PATH.to_string();
+    }
+    let envelope = Envelope {
+        prefix: b"{\"streams\":[".to_vec(),
+        suffix: b"]}".to_vec(),
+    };
+    let send: batch::Send = Box::new(move |body, encoding, auth, proxy| {
+        let mut headers = vec![("Content-Type", "application/json")];
+        headers.extend(encoding.map(|encoding| ("Content-Encoding", encoding)));
+        http::post(&url, &headers, body, auth, proxy)?.check(&url)
+    });
+    Batcher::start(name, envelope, send)
+}
@@ -0,0 +1,192 @@
+//! LZ4 compression, with a small encoder of its own.
+//!
+//! A batch becomes a single LZ4 frame of independent blocks of up to 4 MiB,
+//! each a run of sequences: literal bytes, then a reference back to a
+//! string the block repeats, found with the hash chains of `lz77`. The frame
+//! ends with the xxHash32 of the batch, which decoders check.
+
+use crate::lz77::Matcher;
+
+/// Opens a frame
+const MAGIC: u32 = 0x184d_2204;
+
+/// Version 1, independent blocks, and a checksum of the content
+const FLAGS: u8 = 0x64;
+
+/// Blocks of up to 4 MiB
+const BLOCK_DESCRIPTOR: u8 = 0x70;
+const MAX_BLOCK: usize = 4 << 20;
+
+/// Largest distance a match may reach back
+const WINDOW: usize = 65_535;
+
+/// Shortest match LZ4 can encode
+const MIN_MATCH: usize = 4;
+
+/// How close to the end of a block the last match may start, and how many
+/// bytes at the end are always literals
+const MATCH_LIMIT: usize = 12;
+const LAST_LITERALS: usize = 5;
+
+/// Primes of xxHash32
+const PRIME_1: u32 = 2_654_435_761;
+const PRIME_2: u32 = 2_246_822_519;
+const PRIME_3: u32 = 3_266_489_917;
+const PRIME_4: u32 = 668_265_263;
+const PRIME_5: u32 = 374_761_393;
+
+/// Compress `data` into an LZ4 frame
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = MAGIC.to_le_bytes().to_vec();
+    out.extend_from_slice(&[FLAGS, BLOCK_DESCRIPTOR]);
+    out.push((xxh32(&[FLAGS, BLOCK_DESCRIPTOR]) >> 8) as u8);
+    for block in data.chunks(MAX_BLOCK) {
+        let compressed = compress_block(block);
+        // a block that doesn't get smaller is stored as it is
+        match compressed.len() < block.len() {
+            true => {
+                out.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+                out.extend_from_slice(&compressed);
+            }
+            false => {
+                out.extend_from_slice(&(block.len() as u32 | 1 << 31).to_le_bytes());
+                out.extend_from_slice(block);
+            }
+        }
+    }
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&xxh32(data).to_le_bytes());
+    out
+}
+
+/// Compress a block into sequences, the last of them only literals
+fn compress_block(block: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut matcher = Matcher::new(block, WINDOW);
+    let mut literals = 0;
+    let mut at = 0;
+    while at + MATCH_LIMIT <= block.len() {
+        let (len, distance) = matcher.longest(at, block.len() - LAST_LITERALS - at);
+        if len >= MIN_MATCH {
+            write_sequence(&mut out, &block[literals..at], Some((len, distance)));
+            for at in at..at + len {
+                matcher.insert(at);
+            }
+            at += len;
+            literals = at;
+        } else {
+            matcher.insert(at);
+            at += 1;
+        }
+    }
+    write_sequence(&mut out, &block[literals..], None);
+    out
+}
+
+/// Write literals, and the match of a length and distance following them
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], reference: Option<(usize, usize)>) {
+    let len = reference.map_or(0, |(len, _)| len - MIN_MATCH);
+    out.push((literals.len().min(15) << 4 | len.min(15)) as u8);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((_, distance)) = reference {
+        out.extend_from_slice(&(distance as u16).to_le_bytes());
+        if len >= 15 {
+            write_length(out, len - 15);
+        }
+    }
+}
+
+/// Write what's left of a length after its token, in bytes of up to 255
+fn write_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+/// xxHash32 of some bytes, with a seed of 0
+fn xxh32(data: &[u8]) -> u32 {
+    let word = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let round = |acc: u32, bytes: &[u8]| {
+        acc.wrapping_add(word(bytes).wrapping_mul(PRIME_2))
+            .rotate_left(13)
+            .wrapping_mul(PRIME_1)
+    };
+
+    let mut stripes = data.chunks_exact(16);
+    let mut hash = match data.len() >= 16 {
+        true => {
+            let mut acc = [
+                PRIME_1.wrapping_add(PRIME_2),
+                PRIME_2,
+                0,
+                0u32.wrapping_sub(PRIME_1),
+            ];
+            for stripe in &mut stripes {
+                for (i, acc) in acc.iter_mut().enumerate() {
+                    *acc = round(*acc, &stripe[4 * i..]);
+                }
+            }
+            acc[0]
+                .rotate_left(1)
+                .wrapping_add(acc[1].rotate_left(7))
+                .wrapping_add(acc[2].rotate_left(12))
+                .wrapping_add(acc[3].rotate_left(18))
+        }
+        false => PRIME_5,
+    };
+    hash = hash.wrapping_add(data.len() as u32);
+
+    let mut words = stripes.remainder().chunks_exact(4);
+    for bytes in &mut words {
+        hash = hash
+            .wrapping_add(word(bytes).wrapping_mul(PRIME_3))
+            .rotate_left(17)
+            .wrapping_mul(PRIME_4);
+    }
+    for &byte in words.remainder() {
+        hash = hash
+            .wrapping_add(u32::from(byte).wrapping_mul(PRIME_5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME_1);
+    }
+
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(PRIME_2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(PRIME_3);
+    hash ^ hash >> 16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hex;
+
+    #[test]
+    fn xxh32_vectors() {
+        assert_eq!(xxh32(b""), 0x02cc_5d05);
+        assert_eq!(xxh32(b"abc"), 0x32d1_53ff);
+        assert_eq!(
+            xxh32(b"Nobody inspects the spammish repetition"),
+            0xe229_3b2f
+        );
+    }
+
+    // checked with `lz4 -d`
+    #[test]
+    fn vectors() {
+        assert_eq!(hex(&compress(b"")), "04224d186470b900000000055dcc02");
+        assert_eq!(
+            hex(&compress(
+                b"GET /users 200\nGET /users 200\nGET /users/7 404\n"
+            )),
+            "04224d186470b91c000000ff00474554202f7573657273203230300a0f00\
+             06702f37203430340a0000000072c42320"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Repeated strings of a batch being compressed, found with hash chains.
+//!
+//! Gzip, zstd and lz4 all encode a batch as literal bytes and references
+//! back to strings seen before; they share this search for the longest
+//! earlier string each position repeats, within a window of their own.
+
+/// Bytes hashed to find candidates, the shortest match any codec encodes
+pub(crate) const MIN_MATCH: usize = 3;
+
+/// Bits of the hash of the next `MIN_MATCH` bytes
+const HASH_BITS: u32 = 15;
+
+/// Most earlier positions tried for a match
+const MAX_CHAIN: usize = 64;
+
+/// Earlier positions of the strings of some data, by their first bytes
+pub(crate) struct Matcher<'a> {
+    data: &'a [u8],
+
+    /// Largest distance a match may reach back
+    window: usize,
+
+    /// Most recent position of each hash
+    head: Vec<usize>,
+
+    /// Previous position with the same hash, for each position in the window
+    prev: Vec<usize>,
+}
+
+impl<'a> Matcher<'a> {
+    /// Search `data` for matches reaching at most `window` bytes back
+    pub(crate) fn new(data: &'a [u8], window: usize) -> Self {
+        Matcher {
+            data,
+            window,
+            head: vec![usize::MAX; 1 << HASH_BITS],
+            prev: vec![usize::MAX; window.min(data.len()).max(1)],
+        }
+    }
+
+    fn hash(&self, at: usize) -> usize {
+        let data = self.data;
+        let word = u32::from_be_bytes([0, data[at], data[at + 1], data[at + 2]]);
+        (word.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    /// Remember that a string starts at `at`, for matches after it
+    pub(crate) fn insert(&mut self, at: usize) {
+        if at + MIN_MATCH <= self.data.len() {
+            let hash = self.hash(at);
+            let slot = at % self.prev.len();
+            self.prev[slot] = self.head[hash];
+            self.head[hash] = at;
+        }
+    }
+
+    /// The longest earlier string the bytes at `at` repeat, of at most `max`
+    /// bytes, as its length and how far back it starts
+    ///
+    /// The length is 0 when no string of `MIN_MATCH` bytes or more repeats.
+    pub(crate) fn longest(&self, at: usize, max: usize) -> (usize, usize) {
+        let data = self.data;
+        let max = max.min(data.len().saturating_sub(at));
+        let (mut best_len, mut best_distance) = (0, 0);
+        if max < MIN_MATCH {
+            return (best_len, best_distance);
+        }
+        let mut candidate = self.head[self.hash(at)];
+        let mut chain = MAX_CHAIN;
+        while candidate < at && at - candidate <= self.window && chain > 0 {
+            let len = data[candidate..]
+                .iter()
+                .zip(&data[at..at + max])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best_len {
+                best_len = len;
+                best_distance = at - candidate;
+                if len == max {
+                    break;
+                }
+            }
+            let next = self.prev[candidate % self.prev.len()];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain -= 1;
+        }
+        match best_len >= MIN_MATCH {
+            true => (best_len, best_distance),
+            false => (0, 0),
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Logging macros with well-known fields.
+
+use log::{kv, Level, Record};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::{error, fmt};
+
+/// Log an error at the error level, together with its details
+///
+/// Next to the message, the record carries:
+/// - `error.message`: the error's `Display` output
+/// - `error.type`: the error's type name, unless it's only known as a trait
+///   object such as `dyn Error`
+/// - `error.chain`: the errors it was caused by, separated by `: `, if any
+/// - `error.backtrace`: a backtrace of the call, if `RUST_BACKTRACE` or
+///   `RUST_LIB_BACKTRACE` enables them
+///
+/// so errors look the same in every part of a codebase.
+///
+/// Error reports such as `anyhow::Error` and `eyre::Report` dereference to
+/// `dyn std::error::Error`; pass `*report` to log their whole chain. Their
+/// own backtraces aren't reachable through that trait, so the backtrace is
+/// the one captured by the call. To log an error as a value of a pair
+/// instead, see `Report`.
+///
+/// # Examples
+/// ```
+/// use std::{error, io};
+///
+/// femme::start();
+///
+/// let err = io::Error::new(io::ErrorKind::Other, "disk full");
+/// femme::error_with!(err, "failed to sync {} records", 12);
+///
+/// let report: Box<dyn error::Error + Send + Sync> = Box::new(err);
+/// femme::error_with!(*report, "failed to sync");
+/// ```
+#[macro_export]
+macro_rules! error_with {
+    ($err:expr, $($arg:tt)+) => {
+        $crate::__error_with(
+            &$err,
+            format_args!($($arg)+),
+            &(module_path!(), file!(), line!()),
+        )
+    };
+}
+
+/// Implementation of `error_with!`, not part of the public API
+///
+/// # Arguments
+/// * `err` - The error to describe
+/// * `args` - The message
+/// * `location` - Module path, file and line of the call
+#[doc(hidden)]
+pub fn __error_with<E: error::Error + ?Sized>(
+    err: &E,
+    args: fmt::Arguments<'_>,
+    &(module_path, file, line): &(&'static str, &'static str, u32),
+) {
+    if Level::Error > log::STATIC_MAX_LEVEL || Level::Error > log::max_level() {
+        return;
+    }
+
+    let message = err.to_string();
+    let mut chain = Vec::new();
+    let mut source = err.source();
+    while let Some(err) = source {
+        chain.push(err.to_string());
+        source = err.source();
+    }
+    let chain = chain.join(": ");
+    let backtrace = Backtrace::capture();
+    let backtrace = match backtrace.status() {
+        BacktraceStatus::Captured => Some(backtrace.to_string()),
+        _ => None,
+    };
+
+    // the name of a trait object says nothing about the error behind it
+    let type_name = Some(std::any::type_name::<E>())
+        .filter(|name| !name.starts_with("dyn "))
+        .map(|name| ("error.type", kv::Value::from(name)));
+    let fields: &[(&str, kv::Value)] = &[("error.message", message.as_str().into())];
+    let chain = Some(chain.as_str())
+        .filter(|chain| !chain.is_empty())
+        .map(|chain| ("error.chain", kv::Value::from(chain)));
+    let backtrace = backtrace
+        .as_deref()
+        .map(|backtrace| ("error.backtrace", kv::Value::from(backtrace)));
+    let sources: &[&dyn kv::Source] = &[&fields, &type_name, &chain, &backtrace];
+
+    log::logger().log(
+        &Record::builder()
+            .level(Level::Error)
+            .target(module_path)
+            .module_path_static(Some(module_path))
+            .file_static(Some(file))
+            .line(Some(line))
+            .args(args)
+            .key_values(&sources)
+            .build(),
+    );
+}
+
+/// Key of the pair that makes a record a marker
+pub(crate) const MARKER_KEY: &str = "marker";
+
+/// Log a marker between the phases of a long run
+///
+/// Pretty-printed, a marker is a line of its own that stands out from the
+/// records around it; in ndjson it's a record at the info level with a
+/// `marker` member set to `true`, so phases are easy to find either way.
+///
+/// # Examples
+/// ```
+/// femme::start();
+///
+/// femme::marker("=== starting phase 2 ===");
+/// log::info!("Migrating 12 tables");
+/// ```
+#[track_caller]
+pub fn marker(text: &str) {
+    if Level::Info > log::STATIC_MAX_LEVEL || Level::Info > log::max_level() {
+        return;
+    }
+
+    let location = std::panic::Location::caller();
+    let fields: &[(&str, bool)] = &[(MARKER_KEY, true)];
+    log::logger().log(
+        &Record::builder()
+            .level(Level::Info)
+            .target("femme")
+            .file_static(Some(location.file()))
+            .line(Some(location.line()))
+            .args(format_args!("{}", text))
+            .key_values(&fields)
+            .build(),
+    );
+}
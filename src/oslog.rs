@@ -0,0 +1,108 @@
+//! Apple's unified logging system, through os_log.
+//!
+//! Each target gets a log handle of its own, with the target as the
+//! category, so Console.app can filter records by subsystem and category.
+//! Handles are created on first use and kept for the life of the process, as
+//! os_log expects.
+
+use crate::frame;
+use log::{Level, Record};
+use std::collections::HashMap;
+use std::ffi::{c_void, CStr, CString};
+use std::io;
+use std::os::raw::c_char;
+use std::sync::{Mutex, PoisonError};
+
+extern "C" {
+    /// Start of the image the format string below lives in; os_log records
+    /// where format strings are relative to it
+    static __dso_handle: u8;
+
+    fn os_log_create(subsystem: *const c_char, category: *const c_char) -> *mut c_void;
+
+    fn _os_log_impl(
+        dso: *const c_void,
+        log: *mut c_void,
+        kind: u8,
+        format: *const c_char,
+        buf: *const u8,
+        size: u32,
+    );
+}
+
+/// Formats the text as a single string shown in full, rather than redacted
+const FORMAT: &[u8] = b"%{public}s\0";
+
+/// Format a record as a frame, with the target as the category
+///
+/// # Arguments
+/// * `out` - Buffer to format into
+/// * `record` - Record to write
+pub(crate) fn write_frame(out: &mut Vec<u8>, record: &Record) -> io::Result<()> {
+    // OS_LOG_TYPE_* from os/log.h
+    let kind = match record.level() {
+        Level::Error => 0x10,
+        Level::Warn => 0x00,
+        Level::Info => 0x01,
+        Level::Debug | Level::Trace => 0x02,
+    };
+    frame::write(out, kind, record)
+}
+
+/// Log handles of a subsystem, one per category
+pub(crate) struct Logs {
+    subsystem: CString,
+
+    /// `os_log_t` handles by category, which are never released
+    handles: Mutex<HashMap<CString, usize>>,
+}
+
+impl Logs {
+    /// Prepare the handles of a subsystem
+    ///
+    /// # Arguments
+    /// * `subsystem` - Reverse DNS name of the app, such as `com.example.app`
+    pub(crate) fn new(subsystem: &str) -> Self {
+        let subsystem = subsystem.replace('\0', "");
+        Logs {
+            subsystem: CString::new(subsystem).unwrap_or_default(),
+            handles: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Write a frame made by `write_frame` to the unified log
+    pub(crate) fn send(&self, frame: &[u8]) -> io::Result<()> {
+        let frame = frame::parse(frame)?;
+        let log = self.handle(frame.tag) as *mut c_void;
+
+        // the arguments of the format string: a summary byte saying there
+        // are non-scalar arguments, the number of arguments, then the single
+        // public string's descriptor, size and pointer
+        let mut args = [0; 12];
+        args[..4].copy_from_slice(&[0x02, 0x01, 0x22, 0x08]);
+        args[4..].copy_from_slice(&(frame.text.as_ptr() as u64).to_le_bytes());
+
+        unsafe {
+            _os_log_impl(
+                &__dso_handle as *const u8 as *const c_void,
+                log,
+                frame.level,
+                FORMAT.as_ptr() as *const c_char,
+                args.as_ptr(),
+                args.len() as u32,
+            );
+        }
+        Ok(())
+    }
+
+    /// The handle of a category, created on first use
+    fn handle(&self, category: &CStr) -> usize {
+        let mut handles = self.handles.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(&log) = handles.get(category) {
+            return log;
+        }
+        let log = unsafe { os_log_create(self.subsystem.as_ptr(), category.as_ptr()) } as usize;
+        handles.insert(category.to_owned(), log);
+        log
+    }
+}
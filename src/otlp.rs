@@ -0,0 +1,472 @@
+//! OpenTelemetry log records, exported to a collector over OTLP/HTTP.
+//!
+//! Each record becomes a `LogRecord` of the OTLP JSON encoding:
+//!
+//! ```text
+//! {"timeUnixNano":"1566390896789000000","severityNumber":9,"severityText":"INFO","body":{"stringValue":"Listening on port 8080"},"attributes":[{"key":"code.namespace","value":{"stringValue":"app::server"}},{"key":"port","value":{"intValue":"8080"}}]}
+//! ```
+//!
+//! Batches of them are POSTed to `/v1/logs`, under a resource naming the
+//! service after the program. Exported over gRPC instead, the records of a
+//! batch are encoded again as the protobuf of an `ExportLogsServiceRequest`,
+//! under the same resource, for the `Export` method of the collector's
+//! `LogsService`.
+
+use crate::batch::{self, Batcher, Envelope};
+use crate::escape::{self, Scalar};
+use crate::{grpc, http, syslog};
+use log::{kv, Level, Record};
+use std::io::{self, Write};
+
+/// Path logs are exported to, unless the URL has one
+const PATH: &str = "/v1/logs";
+
+/// Method logs are exported with over gRPC
+const GRPC_METHOD: &str = "/opentelemetry.proto.collector.logs.v1.LogsService/Export";
+
+/// Wire types of protobuf fields
+const VARINT: u32 = 0;
+const FIXED64: u32 = 1;
+const LEN: u32 = 2;
+
+/// Format the opening of a log record, up to the body's value
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+pub(crate) fn write_header<W: Write>(out: &mut W, record: &Record, time: u64) -> io::Result<()> {
+    let (number, text) = match record.level() {
+        Level::Error => (17, "ERROR"),
+        Level::Warn => (13, "WARN"),
+        Level::Info => (9, "INFO"),
+        Level::Debug => (5, "DEBUG"),
+        Level::Trace => (1, "TRACE"),
+    };
+    write!(
+        out,
+        "{{\"timeUnixNano\":\"{}000000\",\"severityNumber\":{},\"severityText\":\"{}\",\"body\":{{\"stringValue\":",
+        time, number, text
+    )
+}
+
+/// Format the attributes: the target, file and line, then Key/Value pairs
+pub(crate) fn write_attributes<W: Write>(out: &mut W, record: &Record) -> io::Result<()> {
+    write!(out, "}},\"attributes\":[")?;
+    write!(
+        out,
+        "{{\"key\":\"code.namespace\",\"value\":{{\"stringValue\":"
+    )?;
+    escape::json_str(out, record.target())?;
+    write!(out, "}}}}")?;
+    if let Some(file) = record.file() {
+        write!(
+            out,
+            ",{{\"key\":\"code.filepath\",\"value\":{{\"stringValue\":"
+        )?;
+        escape::json_str(out, file)?;
+        write!(out, "}}}}")?;
+    }
+    if let Some(line) = record.line() {
+        write!(
+            out,
+            ",{{\"key\":\"code.lineno\",\"value\":{{\"intValue\":\"{}\"}}}}",
+            line
+        )?;
+    }
+
+    struct Visitor<'a, W> {
+        out: &'a mut W,
+    }
+
+    impl<'kvs, 'a, W: Write> kv::Visitor<'kvs> for Visitor<'a, W> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            write!(self.out, ",{{\"key\":")?;
+            escape::json_str(self.out, key)?;
+            write!(self.out, ",\"value\":")?;
+
+            let text = val.to_string();
+            let mut unescaped = String::new();
+            match Scalar::parse(&text, &mut unescaped) {
+                Scalar::Raw("null") => write!(self.out, "{{}}")?,
+                Scalar::Raw(raw @ ("true" | "false")) => {
+                    write!(self.out, "{{\"boolValue\":{}}}", raw)?
+                }
+                Scalar::Raw(raw) if raw.parse::<i64>().is_ok() => {
+                    write!(self.out, "{{\"intValue\":\"{}\"}}", raw)?
+                }
+                Scalar::Raw(raw) => write!(self.out, "{{\"doubleValue\":{}}}", raw)?,
+                Scalar::Str(text) | Scalar::Text(text) => {
+                    write!(self.out, "{{\"stringValue\":")?;
+                    escape::json_str(self.out, text)?;
+                    write!(self.out, "}}")?;
+                }
+            }
+            write!(self.out, "}}")?;
+            Ok(())
+        }
+    }
+
+    record
+        .key_values()
+        .visit(&mut Visitor { out })
+        .map_err(crate::error::kv_to_io)?;
+    write!(out, "]")
+}
+
+/// Start exporting batches of log records to a collector
+///
+/// # Arguments
+/// * `url` - The collector, such as `http://localhost:4318`
+/// * `name` - Name of the destination, for reports
+pub(crate) fn start(url: &str, name: String) -> io::Result<Batcher> {
+    let mut url = http::Url::parse(url)?;
+    if url.path == "/" {
+        url.path = PATH.to_string();
+    }
+
+    let mut prefix = Vec::new();
+    write!(
+        prefix,
+        "{{\"resourceLogs\":[{{\"resource\":{{\"attributes\":[{{\"key\":\"service.name\",\"value\":{{\"stringValue\":"
+    )?;
+    let service = syslog::Config::default();
+    escape::json_str(&mut prefix, service.app_name().unwrap_or("unknown_service"))?;
+    write!(
+        prefix,
+        "}}}}]}},\"scopeLogs\":[{{\"scope\":{{\"name\":\"femme\",\"version\":\"{}\"}},\"logRecords\":[",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    let envelope = Envelope {
+        prefix,
+        suffix: b"]}]}]}".to_vec(),
+    };
+
+    let send: batch::Send = Box::new(move |body, encoding, auth, proxy| {
+        let mut headers = vec![("Content-Type", "application/json")];
+        headers.extend(encoding.map(|encoding| ("Content-Encoding", encoding)));
+        http::post(&url, &headers, body, auth, proxy)?.check(&url)
+    });
+    Batcher::start(name, envelope, send)
+}
+
+/// Start exporting batches of log records to a collector over OTLP/gRPC
+///
+/// # Arguments
+/// * `url` - The collector, such as `http://localhost:4317`
+/// * `name` - Name of the destination, for reports
+pub(crate) fn start_grpc(url: &str, name: String) -> io::Result<Batcher> {
+    let url = http::Url::parse(url)?;
+
+    // the records of a batch are a JSON array, encoded again when it's sent
+    let service = syslog::Config::default();
+    let mut attribute = Vec::new();
+    write_bytes(&mut attribute, 1, b"service.name");
+    let mut value = Vec::new();
+    let app_name = service.app_name().unwrap_or("unknown_service");
+    write_bytes(&mut value, 1, app_name.as_bytes());
+    write_bytes(&mut attribute, 2, &value);
+    let mut resource = Vec::new();
+    write_bytes(&mut resource, 1, &attribute);
+    let mut scope = Vec::new();
+    write_bytes(&mut scope, 1, b"femme");
+    write_bytes(&mut scope, 2, env!("CARGO_PKG_VERSION").as_bytes());
+    let envelope = Envelope {
+        prefix: b"[".to_vec(),
+        suffix: b"]".to_vec(),
+    };
+
+    let send: batch::Send = Box::new(move |body, _, auth, proxy| {
+        let request = encode_request(&resource, &scope, body)?;
+        grpc::call(&url, GRPC_METHOD, &request, auth, proxy).map(drop)
+    });
+    Batcher::start(name, envelope, send)
+}
+
+/// Encode a batch as an `ExportLogsServiceRequest`
+///
+/// # Arguments
+/// * `resource` - The encoded `Resource` of the records
+/// * `scope` - The encoded `InstrumentationScope` of the records
+/// * `body` - The records, as a JSON array of OTLP/HTTP's `LogRecord`s
+fn encode_request(resource: &[u8], scope: &[u8], body: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "failed to encode a batch");
+    let records = match Json::parse(body).ok_or_else(invalid)? {
+        Json::Array(records) => records,
+        _ => return Err(invalid()),
+    };
+    let mut scope_logs = Vec::new();
+    write_bytes(&mut scope_logs, 1, scope);
+    for record in &records {
+        let record = encode_record(record).ok_or_else(invalid)?;
+        write_bytes(&mut scope_logs, 2, &record);
+    }
+    let mut resource_logs = Vec::new();
+    write_bytes(&mut resource_logs, 1, resource);
+    write_bytes(&mut resource_logs, 2, &scope_logs);
+    let mut request = Vec::new();
+    write_bytes(&mut request, 1, &resource_logs);
+    Ok(request)
+}
+
+/// Encode a `LogRecord` from its JSON encoding
+fn encode_record(record: &Json) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    for (key, value) in record.members()? {
+        match (key.as_str(), value) {
+            ("timeUnixNano", Json::String(time)) => {
+                write_tag(&mut out, 1, FIXED64);
+                out.extend_from_slice(&time.parse::<u64>().ok()?.to_le_bytes());
+            }
+            ("severityNumber", Json::Number(number)) => {
+                write_tag(&mut out, 2, VARINT);
+                write_varint(&mut out, number.parse().ok()?);
+            }
+            ("severityText", Json::String(text)) => write_bytes(&mut out, 3, text.as_bytes()),
+            ("body", body) => write_bytes(&mut out, 5, &encode_value(body)?),
+            ("attributes", Json::Array(attributes)) => {
+                for attribute in attributes {
+                    write_bytes(&mut out, 6, &encode_attribute(attribute)?);
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(out)
+}
+
+/// Encode a `KeyValue` from its JSON encoding
+fn encode_attribute(attribute: &Json) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    for (key, value) in attribute.members()? {
+        match (key.as_str(), value) {
+            ("key", Json::String(key)) => write_bytes(&mut out, 1, key.as_bytes()),
+            ("value", value) => write_bytes(&mut out, 2, &encode_value(value)?),
+            _ => {}
+        }
+    }
+    Some(out)
+}
+
+/// Encode an `AnyValue` from its JSON encoding; `{}` is one without a value
+fn encode_value(value: &Json) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    for (key, value) in value.members()? {
+        match (key.as_str(), value) {
+            ("stringValue", Json::String(text)) => write_bytes(&mut out, 1, text.as_bytes()),
+            ("boolValue", Json::Bool(value)) => {
+                write_tag(&mut out, 2, VARINT);
+                write_varint(&mut out, u64::from(*value));
+            }
+            // an int64 is written as the varint of its two's complement
+            ("intValue", Json::String(int) | Json::Number(int)) => {
+                write_tag(&mut out, 3, VARINT);
+                write_varint(&mut out, int.parse::<i64>().ok()? as u64);
+            }
+            ("doubleValue", Json::Number(double)) => {
+                write_tag(&mut out, 4, FIXED64);
+                out.extend_from_slice(&double.parse::<f64>().ok()?.to_le_bytes());
+            }
+            _ => {}
+        }
+    }
+    Some(out)
+}
+
+fn write_tag(out: &mut Vec<u8>, field: u32, wire_type: u32) {
+    write_varint(out, u64::from(field << 3 | wire_type));
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Write a field of bytes, a string or an embedded message
+fn write_bytes(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_tag(out, field, LEN);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// A JSON value, as the records of a batch are read back
+enum Json {
+    Null,
+    Bool(bool),
+
+    /// The text of a number, as it was written
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parse a whole JSON text
+    fn parse(text: &[u8]) -> Option<Json> {
+        let mut reader = Reader { text, at: 0 };
+        let value = reader.value()?;
+        reader.skip_whitespace();
+        match reader.at == text.len() {
+            true => Some(value),
+            false => None,
+        }
+    }
+
+    fn members(&self) -> Option<&[(String, Json)]> {
+        match self {
+            Json::Object(members) => Some(members),
+            _ => None,
+        }
+    }
+}
+
+/// Reads JSON values from text, from where the last one ended
+struct Reader<'a> {
+    text: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .text
+            .get(self.at)
+            .is_some_and(|byte| b" \t\r\n".contains(byte))
+        {
+            self.at += 1;
+        }
+    }
+
+    /// Consume `literal` if the text continues with it
+    fn eat(&mut self, literal: &[u8]) -> bool {
+        let matches = self.text[self.at..].starts_with(literal);
+        if matches {
+            self.at += literal.len();
+        }
+        matches
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        self.skip_whitespace();
+        match *self.text.get(self.at)? {
+            b'{' => {
+                self.at += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.eat(b"}") {
+                    return Some(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if !self.eat(b":") {
+                        return None;
+                    }
+                    members.push((key, self.value()?));
+                    self.skip_whitespace();
+                    if self.eat(b"}") {
+                        return Some(Json::Object(members));
+                    }
+                    if !self.eat(b",") {
+                        return None;
+                    }
+                }
+            }
+            b'[' => {
+                self.at += 1;
+                let mut elements = Vec::new();
+                self.skip_whitespace();
+                if self.eat(b"]") {
+                    return Some(Json::Array(elements));
+                }
+                loop {
+                    elements.push(self.value()?);
+                    self.skip_whitespace();
+                    if self.eat(b"]") {
+                        return Some(Json::Array(elements));
+                    }
+                    if !self.eat(b",") {
+                        return None;
+                    }
+                }
+            }
+            b'"' => self.string().map(Json::String),
+            b't' if self.eat(b"true") => Some(Json::Bool(true)),
+            b'f' if self.eat(b"false") => Some(Json::Bool(false)),
+            b'n' if self.eat(b"null") => Some(Json::Null),
+            _ => {
+                let start = self.at;
+                while self
+                    .text
+                    .get(self.at)
+                    .is_some_and(|byte| b"+-.0123456789eE".contains(byte))
+                {
+                    self.at += 1;
+                }
+                match self.at > start {
+                    true => Some(Json::Number(
+                        String::from_utf8_lossy(&self.text[start..self.at]).into_owned(),
+                    )),
+                    false => None,
+                }
+            }
+        }
+    }
+
+    /// A string, its escapes undone
+    fn string(&mut self) -> Option<String> {
+        if !self.eat(b"\"") {
+            return None;
+        }
+        let mut out = Vec::new();
+        loop {
+            let byte = *self.text.get(self.at)?;
+            self.at += 1;
+            match byte {
+                b'"' => return String::from_utf8(out).ok(),
+                b'\\' => {
+                    let escaped = *self.text.get(self.at)?;
+                    self.at += 1;
+                    let unescaped = match escaped {
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            // a character beyond the BMP is a surrogate pair
+                            let high = self.hex4()?;
+                            let code = match high {
+                                0xd800..=0xdbff if self.eat(b"\\u") => {
+                                    let low = self.hex4()?;
+                                    0x10000 + ((high - 0xd800) << 10) + low.checked_sub(0xdc00)?
+                                }
+                                _ => high,
+                            };
+                            char::from_u32(code)?
+                        }
+                        other => char::from(other),
+                    };
+                    let mut utf8 = [0; 4];
+                    out.extend_from_slice(unescaped.encode_utf8(&mut utf8).as_bytes());
+                }
+                _ => out.push(byte),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let hex = std::str::from_utf8(self.text.get(self.at..self.at + 4)?).ok()?;
+        self.at += 4;
+        u32::from_str_radix(hex, 16).ok()
+    }
+}
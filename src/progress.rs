@@ -0,0 +1,117 @@
+//! Progress of long operations, as throttled records.
+
+use crate::escape::Scalar;
+use log::{kv, Level, Record};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Key naming the operation a progress record is about
+const PROGRESS_KEY: &str = "progress";
+
+/// Shortest time between two records about the same operation
+const INTERVAL: Duration = Duration::from_millis(500);
+
+/// When each unfinished operation was last logged
+static LAST: OnceLock<Mutex<HashMap<String, Instant>>> = OnceLock::new();
+
+/// Log the progress of an operation, such as an upload
+///
+/// A record at the info level carries the operation as `progress`, the work
+/// `done` out of `total` and the percentage as `pct`. Records about the same
+/// operation are throttled to two a second, but the first and the one that
+/// completes it are always logged, so this can be called on every step.
+///
+/// Pretty-printed to a terminal, progress is a line updated in place; it's
+/// a record like any other elsewhere.
+///
+/// # Examples
+/// ```
+/// femme::start();
+///
+/// let total = 1000;
+/// for done in (0..=total).step_by(100) {
+///     femme::progress("upload", done, total);
+/// }
+/// ```
+#[track_caller]
+pub fn progress(operation: &str, done: u64, total: u64) {
+    if Level::Info > log::STATIC_MAX_LEVEL || Level::Info > log::max_level() {
+        return;
+    }
+
+    let finished = done >= total;
+    {
+        let mut last = LAST
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        match finished {
+            true => {
+                last.remove(operation);
+            }
+            false => match last.get_mut(operation) {
+                Some(at) if now.duration_since(*at) < INTERVAL => return,
+                Some(at) => *at = now,
+                None => {
+                    last.insert(operation.to_string(), now);
+                }
+            },
+        }
+    }
+
+    let pct = match total {
+        0 => 100,
+        total => (u128::from(done.min(total)) * 100 / u128::from(total)) as u64,
+    };
+    let location = std::panic::Location::caller();
+    let fields: &[(&str, kv::Value)] = &[
+        (PROGRESS_KEY, operation.into()),
+        ("done", done.into()),
+        ("total", total.into()),
+        ("pct", pct.into()),
+    ];
+    log::logger().log(
+        &Record::builder()
+            .level(Level::Info)
+            .target("femme")
+            .file_static(Some(location.file()))
+            .line(Some(location.line()))
+            .args(format_args!("{} {}%", operation, pct))
+            .key_values(&fields)
+            .build(),
+    );
+}
+
+/// The fields of a progress record
+pub(crate) struct Progress {
+    pub(crate) operation: String,
+    pub(crate) done: u64,
+    pub(crate) total: u64,
+    pub(crate) pct: u64,
+}
+
+impl Progress {
+    /// Read the fields of a record, if it is about progress
+    pub(crate) fn parse(record: &Record) -> Option<Self> {
+        let kvs = record.key_values();
+        let operation = kvs.get(kv::Key::from_str(PROGRESS_KEY))?.to_string();
+        let mut unescaped = String::new();
+        let operation = match Scalar::parse(&operation, &mut unescaped) {
+            Scalar::Str(text) | Scalar::Raw(text) | Scalar::Text(text) => text.to_string(),
+        };
+        let number = |key: &str| kvs.get(kv::Key::from_str(key))?.to_string().parse().ok();
+        Some(Progress {
+            operation,
+            done: number("done")?,
+            total: number("total")?,
+            pct: number("pct")?,
+        })
+    }
+
+    /// Whether the operation is complete
+    pub(crate) fn finished(&self) -> bool {
+        self.done >= self.total
+    }
+}
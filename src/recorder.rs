@@ -0,0 +1,164 @@
+//! Flight recorder: the most recent records, kept in memory.
+//!
+//! Debug consoles and crash reporters read them back through
+//! `femme::recent()`, without parsing any output.
+
+use crate::escape::Scalar;
+use crate::time;
+use log::{kv, Level, LevelFilter, Record};
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+static RECORDER: OnceLock<Recorder> = OnceLock::new();
+
+/// The records kept, oldest first
+struct Recorder {
+    capacity: usize,
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+/// A record kept by the flight recorder
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// When the record was logged
+    pub time: SystemTime,
+
+    /// Level of the record
+    pub level: Level,
+
+    /// Target of the record, such as `app::db`
+    pub target: String,
+
+    /// The formatted message
+    pub message: String,
+
+    /// Key/Value pairs, with strings unquoted
+    pub fields: Vec<(String, String)>,
+}
+
+/// The most recent records, oldest first
+///
+/// The query methods narrow the records down and can be chained.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Recent {
+    /// The records, oldest first
+    pub entries: Vec<Entry>,
+}
+
+impl Recent {
+    /// Keep the records logged at or after `since`
+    pub fn entries_since(mut self, since: SystemTime) -> Self {
+        self.entries.retain(|entry| entry.time >= since);
+        self
+    }
+
+    /// Keep the records at `level` or more severe, from `target` or a module
+    /// below it
+    ///
+    /// # Arguments
+    /// * `level` - Least severe level to keep
+    /// * `target` - Target such as `app::db`, which keeps `app::db::pool`
+    ///   too; `""` keeps every target
+    pub fn filter(mut self, level: LevelFilter, target: &str) -> Self {
+        self.entries.retain(|entry| {
+            let below = entry
+                .target
+                .strip_prefix(target)
+                .is_some_and(|rest| target.is_empty() || rest.is_empty() || rest.starts_with("::"));
+            entry.level <= level && below
+        });
+        self
+    }
+}
+
+/// Read back the records kept by the flight recorder
+///
+/// Records are kept once `Femme::flight_recorder` is set, after they pass
+/// the level filters. Without a flight recorder none are.
+///
+/// # Examples
+/// ```
+/// # if ::log::STATIC_MAX_LEVEL == ::log::LevelFilter::Off { return; }
+/// use femme::LevelFilter;
+/// use std::time::{Duration, SystemTime};
+///
+/// femme::ndjson().flight_recorder(100).finish().unwrap();
+/// log::info!("Listening on port 8080");
+/// log::warn!(target: "app::db", "Slow query");
+///
+/// let minute_ago = SystemTime::now() - Duration::from_secs(60);
+/// let recent = femme::recent()
+///     .entries_since(minute_ago)
+///     .filter(LevelFilter::Warn, "app");
+/// assert_eq!(recent.entries.len(), 1);
+/// assert_eq!(recent.entries[0].message, "Slow query");
+/// ```
+pub fn recent() -> Recent {
+    let entries = match RECORDER.get() {
+        Some(recorder) => recorder
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect(),
+        None => Vec::new(),
+    };
+    Recent { entries }
+}
+
+/// Start keeping up to `capacity` records
+///
+/// Only the first flight recorder is kept, like only the first logger is.
+pub(crate) fn install(capacity: usize) {
+    let _ = RECORDER.set(Recorder {
+        capacity,
+        entries: Mutex::new(VecDeque::with_capacity(capacity)),
+    });
+}
+
+/// Keep a record, dropping the oldest one if the recorder is full
+pub(crate) fn record(record: &Record) {
+    let recorder = match RECORDER.get() {
+        Some(recorder) if recorder.capacity > 0 => recorder,
+        _ => return,
+    };
+
+    struct Fields(Vec<(String, String)>);
+
+    impl<'kvs> kv::Visitor<'kvs> for Fields {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            let text = val.to_string();
+            let mut unescaped = String::new();
+            let text = match Scalar::parse(&text, &mut unescaped) {
+                Scalar::Str(text) | Scalar::Raw(text) | Scalar::Text(text) => text.to_string(),
+            };
+            self.0.push((key.to_string(), text));
+            Ok(())
+        }
+    }
+
+    let mut fields = Fields(Vec::new());
+    let _ = record.key_values().visit(&mut fields);
+    let entry = Entry {
+        time: UNIX_EPOCH + Duration::from_millis(time::now()),
+        level: record.level(),
+        target: record.target().to_string(),
+        message: record.args().to_string(),
+        fields: fields.0,
+    };
+
+    let mut entries = recorder
+        .entries
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if entries.len() == recorder.capacity {
+        entries.pop_front();
+    }
+    entries.push_back(entry);
+}
@@ -0,0 +1,65 @@
+//! Errors logged as Key/Value values, with the errors they were caused by.
+//!
+//! A value of `femme::Report` formats as the error's message, like the error
+//! itself. Formats that know of it take the error's sources from the value
+//! while formatting it: ndjson writes an object with the `message` and the
+//! `chain` of sources, separated by `: ` like `error_with!` writes them, and
+//! pretty output lists the sources below the message:
+//!
+//! ```text
+//! {"level":50,"time":1566390896789,"msg":"Sync failed","err":{"message":"failed to sync","chain":"disk full"}}
+//! ```
+//!
+//! ```text
+//! scratch Sync failed
+//!     err failed to sync
+//!         caused by: disk full
+//! ```
+//!
+//! femme doesn't depend on `anyhow` or `eyre`, so their reports aren't
+//! recognised as values by themselves. Both dereference to
+//! `dyn std::error::Error`; wrap `&*report` in a `Report` to log their chain.
+//! Their backtraces aren't reachable through that trait, and aren't written.
+
+use crate::capture::{self, Captured};
+use log::kv;
+use std::{error, fmt};
+
+/// An error to log as a value, together with the errors it was caused by
+///
+/// # Examples
+/// ```
+/// use kv_log_macro::error;
+/// use std::io;
+///
+/// femme::start();
+///
+/// let err = io::Error::new(io::ErrorKind::Other, "disk full");
+/// error!("Sync failed", { err: femme::Report(&err) });
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Report<'a>(pub &'a dyn error::Error);
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        capture::offer(|| {
+            let mut chain = Vec::new();
+            let mut source = self.0.source();
+            while let Some(err) = source {
+                chain.push(err.to_string());
+                source = err.source();
+            }
+            Captured::Report {
+                message: self.0.to_string(),
+                chain,
+            }
+        });
+        fmt::Display::fmt(self.0, f)
+    }
+}
+
+impl kv::ToValue for Report<'_> {
+    fn to_value(&self) -> kv::Value<'_> {
+        kv::Value::from_display(self)
+    }
+}
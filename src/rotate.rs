@@ -0,0 +1,439 @@
+//! Rotation of log files.
+//!
+//! A rotating file keeps track of how many bytes it holds and which period
+//! its records belong to. Before a write would take it past the configured
+//! size, the file is renamed out of the way and writing continues in a fresh
+//! one: `app.log` becomes `app.log.1`, the previous `app.log.1` becomes
+//! `app.log.2`, and so on. Once the clock moves into a new hour or day, the
+//! file is renamed after the period it covers instead, such as
+//! `app.log.2019-08-21`. Both checks happen lazily, on write, and the swap
+//! happens under the output's lock, so no record is split between two files.
+//!
+//! After each rotation the oldest rotated files are deleted, as far as the
+//! retention limits require.
+//!
+//! Another program may move or delete the file too, as logrotate does. At
+//! most once a second, before a write, the path is looked up again: when it
+//! no longer leads to the open file, the file is reopened at the path, so
+//! records don't keep going to a deleted file.
+//!
+//! A path may contain placeholders, resolved when the file is opened and
+//! again on each rotation: when the resolved path changed, for instance
+//! because it holds the date, writing simply moves on to the new path and
+//! nothing is renamed.
+
+use crate::diagnostics;
+use crate::time::{self, Civil};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IoSlice, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/// How often the path is checked for a file replaced by another program
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When to rotate a log file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Policy {
+    /// Largest size in bytes a file grows to, unless a single write is larger
+    pub(crate) size: Option<u64>,
+
+    /// Period each file covers, in UTC
+    pub(crate) interval: Option<Interval>,
+
+    /// Number of rotated files to keep
+    pub(crate) max_files: Option<usize>,
+
+    /// Largest size in bytes of the current file and the rotated files
+    /// together
+    pub(crate) max_total_size: Option<u64>,
+}
+
+/// A period of time in UTC
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Interval {
+    Hourly,
+    Daily,
+}
+
+impl Interval {
+    /// Name of the builder method selecting the interval
+    pub(crate) fn option(self) -> &'static str {
+        match self {
+            Interval::Hourly => "rotate_hourly",
+            Interval::Daily => "rotate_daily",
+        }
+    }
+
+    fn millis(self) -> u64 {
+        match self {
+            Interval::Hourly => 3_600_000,
+            Interval::Daily => 86_400_000,
+        }
+    }
+
+    /// Suffix naming a period, such as `2019-08-21` or `2019-08-21T13`
+    ///
+    /// # Arguments
+    /// * `period` - Number of whole intervals since the Unix epoch
+    fn stamp(self, period: u64) -> String {
+        let civil = Civil::from_millis(period * self.millis());
+        let date = format!("{:04}-{:02}-{:02}", civil.year, civil.month, civil.day);
+        match self {
+            Interval::Hourly => format!("{}T{:02}", date, civil.hour),
+            Interval::Daily => date,
+        }
+    }
+}
+
+/// A log file that is rotated according to a policy
+pub(crate) struct RotatingFile {
+    /// The configured path, possibly with placeholders
+    template: PathBuf,
+
+    /// Path of the current file, with the placeholders resolved
+    path: PathBuf,
+
+    /// Whether missing parent directories are created
+    create_dirs: bool,
+    file: File,
+    policy: Policy,
+
+    /// Bytes in the current file
+    len: u64,
+
+    /// Number of whole intervals between the Unix epoch and the period the
+    /// current file covers
+    period: u64,
+
+    /// When the path was last checked for a replaced file
+    followed: Instant,
+}
+
+impl RotatingFile {
+    /// Open a log file
+    ///
+    /// # Arguments
+    /// * `template` - Path of the file, possibly with placeholders
+    /// * `truncate` - Whether to empty the file instead of appending to it
+    /// * `create_dirs` - Whether to create missing parent directories
+    /// * `policy` - When to rotate the file
+    pub(crate) fn open(
+        template: &Path,
+        truncate: bool,
+        create_dirs: bool,
+        policy: Policy,
+    ) -> io::Result<Self> {
+        let path = resolve(template);
+        if create_dirs {
+            create_parent(&path)?;
+        }
+        let file = open(&path, truncate)?;
+        let metadata = file.metadata()?;
+        let len = metadata.len();
+
+        // a file left over from an earlier run covers the period it was last
+        // written in
+        let written = match len {
+            0 => time::now(),
+            _ => metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or_else(time::now, |elapsed| elapsed.as_millis() as u64),
+        };
+        let period = policy
+            .interval
+            .map_or(0, |interval| written / interval.millis());
+
+        Ok(RotatingFile {
+            template: template.to_path_buf(),
+            path,
+            create_dirs,
+            file,
+            policy,
+            len,
+            period,
+            followed: Instant::now(),
+        })
+    }
+
+    /// The file currently written to
+    pub(crate) fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Get ready to write `incoming` bytes, rotating the file if needed
+    ///
+    /// A file that fails to rotate is written to regardless: losing records
+    /// is worse than a file growing past its size.
+    pub(crate) fn prepare(&mut self, incoming: u64) {
+        if self.followed.elapsed() >= FOLLOW_INTERVAL {
+            self.followed = Instant::now();
+            if let Err(err) = self.follow() {
+                diagnostics::report(format_args!(
+                    "failed to reopen {}: {}",
+                    self.path.display(),
+                    err
+                ));
+            }
+        }
+
+        let res = match self.policy.interval {
+            Some(interval) if time::now() / interval.millis() != self.period => {
+                self.rotate_period(interval)
+            }
+            _ => match self.policy.size {
+                Some(size) if self.len > 0 && self.len + incoming > size => self.rotate(),
+                _ => Ok(()),
+            },
+        };
+        if let Err(err) = res {
+            diagnostics::report(format_args!(
+                "failed to rotate {}: {}",
+                self.path.display(),
+                err
+            ));
+        }
+    }
+
+    /// Reopen the file if another program moved, deleted or replaced it
+    ///
+    /// A file emptied in place, as logrotate's `copytruncate` does, is kept
+    /// but its size is taken again.
+    fn follow(&mut self) -> io::Result<()> {
+        let current = self.file.metadata()?;
+        match fs::metadata(&self.path) {
+            Ok(metadata) if same_file(&metadata, &current) => {
+                self.len = self.len.min(metadata.len());
+                return Ok(());
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        self.file.flush()?;
+        if self.create_dirs {
+            create_parent(&self.path)?;
+        }
+        self.file = open(&self.path, false)?;
+        self.len = self.file.metadata()?.len();
+        Ok(())
+    }
+
+    /// Delete the oldest rotated files beyond the retention limits
+    fn prune(&self) -> io::Result<()> {
+        if self.policy.max_files.is_none() && self.policy.max_total_size.is_none() {
+            return Ok(());
+        }
+
+        // rotated files are named after the current one, followed by a
+        // number or a date
+        let name = match self.path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned() + ".",
+            None => return Ok(()),
+        };
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut rotated = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let is_rotated = entry
+                .file_name()
+                .to_string_lossy()
+                .strip_prefix(&name)
+                .is_some_and(|suffix| suffix.starts_with(|c: char| c.is_ascii_digit()));
+            let metadata = entry.metadata()?;
+            if is_rotated && metadata.is_file() {
+                rotated.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+
+        // newest first
+        rotated.sort_by_key(|(modified, _, _)| std::cmp::Reverse(*modified));
+        let mut total = self.len;
+        for (idx, (_, len, path)) in rotated.iter().enumerate() {
+            total += len;
+            let too_many = self.policy.max_files.is_some_and(|max| idx >= max);
+            let too_large = self.policy.max_total_size.is_some_and(|max| total > max);
+            if too_many || too_large {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Account for bytes written to the current file
+    pub(crate) fn wrote(&mut self, written: usize) {
+        self.len += written as u64;
+    }
+
+    /// Move on to a new path if the placeholders now resolve to one
+    ///
+    /// Returns whether the file was switched.
+    fn switch(&mut self) -> io::Result<bool> {
+        let path = resolve(&self.template);
+        if path == self.path {
+            return Ok(false);
+        }
+        self.file.flush()?;
+        if self.create_dirs {
+            create_parent(&path)?;
+        }
+        self.file = open(&path, false)?;
+        self.len = self.file.metadata()?.len();
+        self.path = path;
+        Ok(true)
+    }
+
+    /// Shift the rotated files up by one and start a fresh current file
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.switch()? {
+            return self.prune();
+        }
+        self.file.flush()?;
+
+        let mut last = 0;
+        while rotated(&self.path, last + 1).exists() {
+            last += 1;
+        }
+        for index in (1..=last).rev() {
+            fs::rename(rotated(&self.path, index), rotated(&self.path, index + 1))?;
+        }
+        fs::rename(&self.path, rotated(&self.path, 1))?;
+
+        self.file = open(&self.path, false)?;
+        self.len = 0;
+        self.prune()
+    }
+
+    /// Name the current file after the period it covers and start a fresh one
+    /// for the current period
+    fn rotate_period(&mut self, interval: Interval) -> io::Result<()> {
+        let period = time::now() / interval.millis();
+        if self.len == 0 {
+            self.period = period;
+            return Ok(());
+        }
+        if self.switch()? {
+            self.period = period;
+            return self.prune();
+        }
+        self.file.flush()?;
+
+        // a name already taken, for instance by a restart within the same
+        // period, gets a number appended
+        let stamped = suffixed(&self.path, &interval.stamp(self.period));
+        let mut target = stamped.clone();
+        let mut index = 0;
+        while target.exists() {
+            index += 1;
+            target = suffixed(&stamped, &index.to_string());
+        }
+        fs::rename(&self.path, target)?;
+
+        self.file = open(&self.path, false)?;
+        self.len = 0;
+        self.period = period;
+        self.prune()
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.wrote(written);
+        Ok(written)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let written = self.file.write_vectored(bufs)?;
+        self.wrote(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Open a log file for writing, creating it if it doesn't exist
+fn open(path: &Path, truncate: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    match truncate {
+        true => options.write(true).truncate(true),
+        false => options.append(true),
+    };
+    options.create(true).open(path)
+}
+
+/// Whether two lookups found the same file
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+/// Whether two lookups found the same file
+///
+/// Without inode numbers to compare, a file is only known to be replaced
+/// once its path is gone.
+#[cfg(not(unix))]
+fn same_file(_: &fs::Metadata, _: &fs::Metadata) -> bool {
+    true
+}
+
+/// Create the directory a file goes in, and its parents, if they're missing
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::create_dir_all(dir),
+        _ => Ok(()),
+    }
+}
+
+/// Resolve the placeholders in a path
+///
+/// - `{name}`: file name of the running program, without its extension
+/// - `{pid}`: id of the process
+/// - `{date}`: the current date in UTC, such as `2019-08-21`
+///
+/// Anything else between braces is left as is.
+pub(crate) fn resolve(template: &Path) -> PathBuf {
+    let text = template.to_string_lossy();
+    if !text.contains('{') {
+        return template.to_path_buf();
+    }
+
+    let name = || {
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.file_stem()?.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "app".to_string())
+    };
+    let date = || {
+        let civil = Civil::from_millis(time::now());
+        format!("{:04}-{:02}-{:02}", civil.year, civil.month, civil.day)
+    };
+    let text = text
+        .replace("{name}", &name())
+        .replace("{pid}", &std::process::id().to_string())
+        .replace("{date}", &date());
+    PathBuf::from(text)
+}
+
+/// Path of the `index`th most recent rotated file, such as `app.log.1`
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    suffixed(path, &index.to_string())
+}
+
+/// `path` with `.suffix` appended to its file name
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
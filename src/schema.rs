@@ -0,0 +1,172 @@
+//! A JSON Schema of the ndjson records a configuration writes.
+//!
+//! The schema follows JSON Schema 2020-12, and names the members femme
+//! writes itself, their types as configured, and the keys `default_field`
+//! fills in at the levels it does. Key/Value pairs are any other members.
+
+use crate::{escape, json, Femme, KeyCollision, LevelValues, Multiline};
+use std::io::{self, Write};
+
+/// The schema of the ndjson records of a configuration, as a line of JSON
+pub(crate) fn write(femme: &Femme) -> String {
+    let mut out = Vec::new();
+    // writing to a `Vec` can't fail
+    let _ = write_schema(&mut out, femme);
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn write_schema<W: Write>(out: &mut W, femme: &Femme) -> io::Result<()> {
+    #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+    let bunyan = femme.bunyan;
+    #[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
+    let bunyan = false;
+
+    let level = match femme.level_values {
+        LevelValues::Numbers(numbers) => format!(
+            "{{\"type\":\"integer\",\"enum\":[{}]}}",
+            join(numbers.iter().map(|number| number.to_string()))
+        ),
+        LevelValues::Names(names) => format!(
+            "{{\"type\":\"string\",\"enum\":[{}]}}",
+            join(names.iter().map(|name| quoted(name)))
+        ),
+    };
+    let time = match bunyan {
+        true => "{\"type\":\"string\",\"format\":\"date-time\"}",
+        false => "{\"type\":\"integer\",\"description\":\"Milliseconds since the Unix epoch\"}",
+    };
+    let mut members = vec![
+        ("level", level.as_str()),
+        ("time", time),
+        ("msg", "{\"type\":\"string\"}"),
+    ];
+    if bunyan {
+        members.splice(
+            0..1,
+            [
+                ("v", "{\"const\":0}"),
+                ("level", level.as_str()),
+                ("name", "{\"type\":\"string\"}"),
+                ("hostname", "{\"type\":\"string\"}"),
+                ("pid", "{\"type\":\"integer\",\"minimum\":0}"),
+            ],
+        );
+    }
+    let version = format!("{{\"const\":{}}}", crate::FMT_VERSION);
+    if femme.fmt_version {
+        members.insert(0, ("fmt_version", version.as_str()));
+    }
+    let collision = femme.key_collision.unwrap_or_default();
+
+    write!(
+        out,
+        "{{\"$schema\":\"https://json-schema.org/draft/2020-12/schema\",\"title\":\"femme ndjson record\",\"type\":\"object\",\"properties\":{{"
+    )?;
+    for (i, (name, schema)) in members.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        // a record may log a value of any type in place of femme's, but for
+        // the versions
+        match collision {
+            KeyCollision::Override if !["fmt_version", "v"].contains(name) => write!(
+                out,
+                "\"{}\":{{\"description\":\"Written by femme, unless the record logs its own\"}}",
+                name
+            )?,
+            _ => write!(out, "\"{}\":{}", name, schema)?,
+        }
+    }
+    // members only records of messages spanning lines have
+    let multiline = match femme.multiline {
+        Multiline::Escape => None,
+        Multiline::Array => Some((
+            "msg_lines",
+            "{\"type\":\"array\",\"items\":{\"type\":\"string\"},\"description\":\"Every line of a message that spans lines\"}",
+        )),
+        Multiline::Split => Some((
+            "continuation",
+            "{\"type\":\"integer\",\"minimum\":1,\"description\":\"Which line of a message that spans lines the record has, after the first\"}",
+        )),
+    };
+    if let Some((name, schema)) = multiline {
+        write!(out, ",\"{}\":{}", name, schema)?;
+    }
+    let mut defaults: Vec<&str> = Vec::new();
+    for (level, key, value) in &femme.defaults {
+        if members.iter().any(|(name, _)| name == key) || defaults.contains(&key.as_ref()) {
+            continue;
+        }
+        defaults.push(key);
+        write!(out, ",")?;
+        escape::json_str(out, key)?;
+        write!(out, ":{{\"description\":")?;
+        let description = format!(
+            "Logged by the record, or {:?} at {} and more severe levels",
+            value,
+            level.to_string().to_ascii_lowercase()
+        );
+        escape::json_str(out, description)?;
+        write!(out, "}}")?;
+    }
+    write!(
+        out,
+        "}},\"required\":[{}]",
+        join(members.iter().map(|(name, _)| quoted(name)))
+    )?;
+
+    let pairs = match collision {
+        KeyCollision::Prefix => {
+            "Key/Value pairs; keys femme writes itself get an underscore before them"
+        }
+        KeyCollision::Drop => "Key/Value pairs; those with keys femme writes itself are left out",
+        KeyCollision::Override => {
+            "Key/Value pairs; those with keys femme writes itself replace its values"
+        }
+    };
+    write!(out, ",\"additionalProperties\":{{\"description\":")?;
+    match femme.nest_keys {
+        true => escape::json_str(
+            out,
+            format_args!("{}, with dotted keys expanded into nested objects", pairs),
+        )?,
+        false => escape::json_str(out, pairs)?,
+    }
+    write!(out, "}}")?;
+
+    // defaults are only required at the levels they apply to
+    let conditions: Vec<String> = femme
+        .defaults
+        .iter()
+        .filter(|(_, key, _)| defaults.contains(&key.as_ref()))
+        .map(|(level, key, _)| {
+            let levels = (0..*level as usize).map(|idx| match femme.level_values {
+                LevelValues::Numbers(numbers) => numbers[idx].to_string(),
+                LevelValues::Names(names) => quoted(names[idx]),
+            });
+            let mut required = Vec::new();
+            let _ = escape::json_str(&mut required, key);
+            format!(
+                "{{\"if\":{{\"properties\":{{\"level\":{{\"enum\":[{}]}}}}}},\"then\":{{\"required\":[{}]}}}}",
+                join(levels),
+                String::from_utf8_lossy(&required)
+            )
+        })
+        .collect();
+    if !conditions.is_empty() && collision != KeyCollision::Override {
+        write!(out, ",\"allOf\":[{}]", conditions.join(","))?;
+    }
+    out.write_all(json::TERMINATOR)
+}
+
+/// A name as a JSON string
+fn quoted(name: &str) -> String {
+    let mut out = Vec::new();
+    let _ = escape::json_str(&mut out, name);
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Values separated by commas
+fn join(values: impl Iterator<Item = String>) -> String {
+    values.collect::<Vec<_>>().join(",")
+}
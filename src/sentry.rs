@@ -0,0 +1,252 @@
+//! Sentry events, sent for records as they're logged.
+//!
+//! Each record becomes an event:
+//!
+//! ```text
+//! {"event_id":"5f0ab3a4c1...","timestamp":1566390896.789,"platform":"other","level":"error","logger":"app::db","message":{"formatted":"Connection lost"},"extra":{"retries":3}}
+//! ```
+//!
+//! The event is sent on its own, in an envelope, to the project of the DSN,
+//! with the backtrace of the logging call as its thread's stack trace when
+//! `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` asks for backtraces. The DSN may
+//! be `https://`, as those of Sentry's own ingest are, or `http://`, such as
+//! that of a self-hosted Sentry or a local relay.
+
+use crate::batch::{self, Batcher, Envelope, Limits};
+use crate::escape;
+use crate::http::{self, Url};
+use log::{kv, Level, Record};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+
+/// Format the opening of an event, up to the formatted message
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+pub(crate) fn write_header<W: Write>(out: &mut W, record: &Record, time: u64) -> io::Result<()> {
+    let level = match record.level() {
+        Level::Error => "error",
+        Level::Warn => "warning",
+        Level::Info => "info",
+        Level::Debug | Level::Trace => "debug",
+    };
+    write!(
+        out,
+        "{{\"event_id\":\"{}\",\"timestamp\":{}.{:03},\"platform\":\"other\",\"level\":\"{}\",\"logger\":",
+        event_id(),
+        time / 1000,
+        time % 1000,
+        level
+    )?;
+    escape::json_str(out, record.target())?;
+    write!(out, ",\"message\":{{\"formatted\":")
+}
+
+/// Format Key/Value pairs as the event's `extra` data, then the backtrace
+/// and the SDK
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+pub(crate) fn write_extra<W: Write>(out: &mut W, record: &Record) -> io::Result<()> {
+    struct Visitor<'a, W> {
+        out: &'a mut W,
+        empty: bool,
+    }
+
+    impl<'kvs, 'a, W: Write> kv::Visitor<'kvs> for Visitor<'a, W> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            match self.empty {
+                true => write!(self.out, ",\"extra\":{{")?,
+                false => write!(self.out, ",")?,
+            }
+            self.empty = false;
+            escape::json_str(self.out, key.as_str())?;
+            write!(self.out, ":")?;
+            escape::json_value(self.out, &val)?;
+            Ok(())
+        }
+    }
+
+    write!(out, "}}")?;
+    let mut visitor = Visitor { out, empty: true };
+    record
+        .key_values()
+        .visit(&mut visitor)
+        .map_err(crate::error::kv_to_io)?;
+    let out = visitor.out;
+    if !visitor.empty {
+        write!(out, "}}")?;
+    }
+
+    let backtrace = Backtrace::capture();
+    if backtrace.status() == BacktraceStatus::Captured {
+        write!(
+            out,
+            ",\"threads\":{{\"values\":[{{\"current\":true,\"stacktrace\":{{\"frames\":["
+        )?;
+        let backtrace = backtrace.to_string();
+        let frames = frames(&backtrace);
+        let caller = frames
+            .iter()
+            .rposition(|frame| is_logging(frame.function))
+            .map_or(0, |idx| idx + 1);
+        for (i, frame) in frames[caller..].iter().rev().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write!(out, "{{\"function\":")?;
+            escape::json_str(out, frame.function)?;
+            if let Some((file, line)) = frame.location {
+                write!(out, ",\"filename\":")?;
+                escape::json_str(out, file)?;
+                write!(out, ",\"lineno\":{}", line)?;
+            }
+            write!(out, "}}")?;
+        }
+        write!(out, "]}}}}]}}")?;
+    }
+    write!(
+        out,
+        ",\"sdk\":{{\"name\":\"femme\",\"version\":\"{}\"}}",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Ends an event
+pub(crate) const TERMINATOR: &[u8] = b"}\n";
+
+/// Send each event as soon as it's logged
+pub(crate) fn limits(limits: Limits) -> Limits {
+    Limits {
+        max_records: 1,
+        ..limits
+    }
+}
+
+/// A frame of a backtrace
+struct Frame<'a> {
+    function: &'a str,
+
+    /// File and line, when known
+    location: Option<(&'a str, u32)>,
+}
+
+/// The frames of a formatted backtrace, innermost first
+///
+/// Each frame is a numbered line naming its function, followed by an `at`
+/// line with its file, line and column when they're known.
+fn frames(backtrace: &str) -> Vec<Frame<'_>> {
+    let mut frames: Vec<Frame> = Vec::new();
+    for line in backtrace.lines().map(str::trim) {
+        if let Some(location) = line.strip_prefix("at ") {
+            let mut parts = location.rsplitn(3, ':');
+            let (_, line, file) = (parts.next(), parts.next(), parts.next());
+            let line = line.and_then(|line| line.parse().ok());
+            if let (Some(frame), Some(file), Some(line)) = (frames.last_mut(), file, line) {
+                frame.location = Some((file, line));
+            }
+        } else if let Some((number, function)) = line.split_once(": ") {
+            if number.chars().all(|c| c.is_ascii_digit()) {
+                frames.push(Frame {
+                    function,
+                    location: None,
+                });
+            }
+        }
+    }
+    frames
+}
+
+/// Whether a frame is one of logging the record, which come before the
+/// frames of the logging call
+fn is_logging(function: &str) -> bool {
+    ["log::", "<log::", "kv_log_macro::", "femme::", "<femme::"]
+        .iter()
+        .any(|prefix| function.starts_with(prefix))
+}
+
+/// 32 random hex digits, the id of an event
+fn event_id() -> String {
+    let half = || {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(crate::time::now());
+        hasher.finish()
+    };
+    format!("{:016x}{:016x}", half(), half())
+}
+
+/// Where events of a DSN go, and the key they're sent with
+struct Dsn {
+    url: Url,
+    key: String,
+}
+
+impl Dsn {
+    /// Parse a DSN, such as `https://<key>@o1.ingest.sentry.io/42` or
+    /// `http://<key>@sentry.internal:9000/42`
+    fn parse(dsn: &str) -> io::Result<Dsn> {
+        let invalid = |reason: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid DSN {}: {}", dsn, reason),
+            )
+        };
+        let (scheme, rest) = dsn
+            .split_once("://")
+            .ok_or_else(|| invalid("it has no scheme"))?;
+        let (userinfo, rest) = rest
+            .split_once('@')
+            .ok_or_else(|| invalid("it has no public key"))?;
+        let key = userinfo.split(':').next().unwrap_or(userinfo);
+        let mut url = Url::parse(&format!("{}://{}", scheme, rest))?;
+        let path = url.path.trim_end_matches('/');
+        let (prefix, project) = path
+            .rsplit_once('/')
+            .filter(|(_, project)| !project.is_empty())
+            .ok_or_else(|| invalid("it has no project id"))?;
+        url.path = format!("{}/api/{}/envelope/", prefix, project);
+        Ok(Dsn {
+            url,
+            key: key.to_string(),
+        })
+    }
+}
+
+/// Start sending events to the project of a DSN
+///
+/// # Arguments
+/// * `dsn` - The DSN of the project
+/// * `name` - Name of the destination, for reports
+pub(crate) fn start(dsn: &str, name: String) -> io::Result<Batcher> {
+    let Dsn { url, key } = Dsn::parse(dsn)?;
+    let auth = format!(
+        "Sentry sentry_version=7, sentry_key={}, sentry_client=femme/{}",
+        key,
+        env!("CARGO_PKG_VERSION")
+    );
+
+    // each batch is a single event, the item of an envelope without headers
+    // of its own; the item ends at its newline, as events have no other
+    let envelope = Envelope {
+        prefix: b"{}\n{\"type\":\"event\"}\n".to_vec(),
+        suffix: b"\n".to_vec(),
+    };
+    let send: batch::Send = Box::new(move |body, encoding, credentials, proxy| {
+        let mut headers = vec![
+            ("Content-Type", "application/x-sentry-envelope"),
+            ("X-Sentry-Auth", auth.as_str()),
+        ];
+        headers.extend(encoding.map(|encoding| ("Content-Encoding", encoding)));
+        http::post(&url, &headers, body, credentials, proxy)?.check(&url)
+    });
+    Batcher::start(name, envelope, send)
+}
@@ -0,0 +1,141 @@
+//! Flushing the outputs when the process is asked to terminate.
+//!
+//! The handler of `SIGTERM` only wakes a thread, by writing a byte to a
+//! socket, since so little is safe in a signal handler, and leaves errno as
+//! it found it. The thread logs a last record regardless of the level
+//! filters, flushes every output within a deadline, then delivers the
+//! signal again with its default handler, which terminates the process.
+
+use crate::diagnostics;
+use libc::{c_int, c_void, SA_RESTART, SIGTERM, SIG_DFL};
+use log::{Level, Record};
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// The end of the socket the handler writes to, or -1 before it's installed
+static WAKE: AtomicI32 = AtomicI32::new(-1);
+
+cfg_if::cfg_if! {
+    if #[cfg(any(target_os = "linux", target_os = "emscripten", target_os = "redox"))] {
+        use libc::__errno_location as errno;
+    } else if #[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))] {
+        use libc::__errno as errno;
+    } else if #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "dragonfly"
+    ))] {
+        use libc::__error as errno;
+    } else if #[cfg(target_os = "solaris")] {
+        use libc::___errno as errno;
+    } else {
+        /// Where errno isn't known to live, it isn't kept
+        unsafe fn errno() -> *mut c_int {
+            std::ptr::null_mut()
+        }
+    }
+}
+
+extern "C" fn on_sigterm(_: c_int) {
+    // the handler may run between a call failing and the code reading errno,
+    // which `write` mustn't change under it
+    // SAFETY: errno's location, where it's known, is valid while the thread
+    // runs
+    let errno = unsafe { errno() };
+    let saved = unsafe { errno.as_ref() }.copied();
+
+    let fd: RawFd = WAKE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        // SAFETY: `write` is async-signal-safe and the byte outlives the call
+        unsafe { libc::write(fd, b"\0".as_ptr() as *const c_void, 1) };
+    }
+
+    if let Some(saved) = saved {
+        // SAFETY: as above
+        unsafe { *errno = saved };
+    }
+}
+
+/// Set the disposition of `SIGTERM`, restarting the system calls it
+/// interrupts instead of failing them with `EINTR`
+fn set_handler(handler: libc::sighandler_t) -> io::Result<()> {
+    // SAFETY: an all-zero `sigaction` is a valid one with an empty mask
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = handler;
+    action.sa_flags = SA_RESTART;
+    // SAFETY: `action` is initialized and outlives the calls
+    match unsafe {
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(SIGTERM, &action, std::ptr::null_mut())
+    } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Flush the outputs on `SIGTERM`, giving up after `deadline`
+///
+/// Only the first call installs the handler.
+pub(crate) fn install(deadline: Duration) -> io::Result<()> {
+    let (sender, mut receiver) = UnixStream::pair()?;
+    // a full socket can't block the handler; the thread is awake by then
+    sender.set_nonblocking(true)?;
+    if WAKE
+        .compare_exchange(-1, sender.as_raw_fd(), Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        return Ok(());
+    }
+    // the handler writes to the socket for as long as the process runs
+    std::mem::forget(sender);
+
+    thread::Builder::new()
+        .name("femme-sigterm".into())
+        .spawn(move || {
+            if receiver.read(&mut [0]).is_ok() {
+                shut_down(deadline);
+            }
+        })?;
+
+    // the handler only loads an atomic and calls `write`
+    set_handler(on_sigterm as extern "C" fn(c_int) as libc::sighandler_t)
+}
+
+/// Log a last record, flush the outputs and terminate the process
+fn shut_down(deadline: Duration) {
+    // written to every output whatever the level filters let through, so
+    // the shutdown shows up in the logs that are kept
+    if let Some(femme) = crate::started() {
+        femme.log_unfiltered(
+            &Record::builder()
+                .level(Level::Info)
+                .target("femme")
+                .args(format_args!("Shutting down on SIGTERM"))
+                .build(),
+        );
+    }
+
+    let (done, flushed) = mpsc::channel();
+    let flushing = thread::Builder::new()
+        .name("femme-flush".into())
+        .spawn(move || {
+            log::logger().flush();
+            let _ = done.send(());
+        });
+    if flushing.is_ok() && flushed.recv_timeout(deadline).is_err() {
+        diagnostics::report(format_args!(
+            "outputs weren't flushed within {:?} of SIGTERM",
+            deadline
+        ));
+    }
+
+    let _ = set_handler(SIG_DFL);
+    // SAFETY: raising a signal is always sound
+    unsafe { libc::raise(SIGTERM) };
+}
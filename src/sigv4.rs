@@ -0,0 +1,134 @@
+//! AWS Signature Version 4, for requests to AWS services.
+//!
+//! A request is signed with a key derived from the secret access key, the
+//! date, the region and the service, over a canonical form of its method,
+//! path, headers and the digest of its body. Credentials come from the
+//! standard environment variables.
+
+use crate::sha256::{self, hex, hmac};
+use crate::time::Civil;
+use std::io;
+
+/// AWS credentials
+#[derive(Clone)]
+pub(crate) struct Credentials {
+    pub(crate) access_key: String,
+    pub(crate) secret_key: String,
+
+    /// Token of temporary credentials, such as those of a role
+    pub(crate) session_token: Option<String>,
+}
+
+impl Credentials {
+    /// Read `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and
+    /// `AWS_SESSION_TOKEN`
+    pub(crate) fn from_env() -> io::Result<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        match (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY")) {
+            (Some(access_key), Some(secret_key)) => Ok(Credentials {
+                access_key,
+                secret_key,
+                session_token: var("AWS_SESSION_TOKEN"),
+            }),
+            _ => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY must be set",
+            )),
+        }
+    }
+}
+
+/// The region set by `AWS_REGION` or `AWS_DEFAULT_REGION`
+pub(crate) fn region_from_env() -> io::Result<String> {
+    ["AWS_REGION", "AWS_DEFAULT_REGION"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|region| !region.is_empty())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "AWS_REGION must be set"))
+}
+
+/// What a signature covers besides the request
+pub(crate) struct Scope<'a> {
+    pub(crate) region: &'a str,
+    pub(crate) service: &'a str,
+
+    /// Milliseconds since the Unix epoch
+    pub(crate) time: u64,
+}
+
+/// Sign a request
+///
+/// Returns the headers to add to it: `X-Amz-Date`, `X-Amz-Security-Token`
+/// for temporary credentials, and `Authorization`.
+///
+/// # Arguments
+/// * `credentials` - Who signs the request
+/// * `scope` - Region, service and time of the signature
+/// * `method` - Method of the request, such as `POST`
+/// * `host` - Value of the `Host` header
+/// * `path` - Path and query of the request, already encoded
+/// * `headers` - Other headers to sign, such as `Content-Type`
+/// * `body` - The body
+pub(crate) fn sign(
+    credentials: &Credentials,
+    scope: &Scope,
+    method: &str,
+    host: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Vec<(String, String)> {
+    let civil = Civil::from_millis(scope.time);
+    let date = format!("{:04}{:02}{:02}", civil.year, civil.month, civil.day);
+    let amz_date = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date, civil.hour, civil.minute, civil.second
+    );
+
+    let mut added = vec![("X-Amz-Date".to_string(), amz_date.clone())];
+    if let Some(token) = &credentials.session_token {
+        added.push(("X-Amz-Security-Token".to_string(), token.clone()));
+    }
+    let mut signed: Vec<(String, String)> = std::iter::once(("host", host))
+        .chain(headers.iter().copied())
+        .chain(
+            added
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        )
+        .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    signed.sort();
+    let names: Vec<&str> = signed.iter().map(|(name, _)| name.as_str()).collect();
+    let names = names.join(";");
+
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let mut canonical = format!("{}\n{}\n{}\n", method, path, query);
+    for (name, value) in &signed {
+        canonical.push_str(&format!("{}:{}\n", name, value));
+    }
+    canonical.push_str(&format!("\n{}\n{}", names, hex(&sha256::digest(body))));
+
+    let credential_scope = format!("{}/{}/{}/aws4_request", date, scope.region, scope.service);
+    let to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        credential_scope,
+        hex(&sha256::digest(canonical.as_bytes()))
+    );
+    let key = format!("AWS4{}", credentials.secret_key);
+    let key = hmac(key.as_bytes(), date.as_bytes());
+    let key = hmac(&key, scope.region.as_bytes());
+    let key = hmac(&key, scope.service.as_bytes());
+    let key = hmac(&key, b"aws4_request");
+    let signature = hex(&hmac(&key, to_sign.as_bytes()));
+
+    added.push((
+        "Authorization".to_string(),
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key, credential_scope, names, signature
+        ),
+    ));
+    added
+}
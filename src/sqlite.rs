@@ -0,0 +1,360 @@
+//! Rows of a SQLite database, inserted in batches through libsqlite3.
+//!
+//! Each record becomes a row of the `logs` table, created if it's missing,
+//! with its fields as a JSON object that SQLite's JSON functions can query:
+//!
+//! ```text
+//! sqlite> SELECT time, level, message, json_extract(fields, '$.port') FROM logs;
+//! 1566390896789|info|Listening on port|8080
+//! ```
+//!
+//! The rows of a batch are inserted in one transaction, so a batch is
+//! stored whole or not at all, and the database is opened in WAL mode, so
+//! it can be queried while records are being inserted.
+
+use crate::batch::{self, Batcher, Envelope};
+use crate::{json, Femme};
+use log::Record;
+use std::ffi::{CStr, CString};
+use std::io::{self, Write};
+use std::os::raw::{c_char, c_int, c_void};
+use std::path::Path;
+use std::ptr;
+
+/// Opaque handles of libsqlite3
+#[repr(C)]
+struct Sqlite3 {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct Stmt {
+    _private: [u8; 0],
+}
+
+#[link(name = "sqlite3")]
+extern "C" {
+    fn sqlite3_open_v2(
+        filename: *const c_char,
+        db: *mut *mut Sqlite3,
+        flags: c_int,
+        vfs: *const c_char,
+    ) -> c_int;
+    fn sqlite3_close(db: *mut Sqlite3) -> c_int;
+    fn sqlite3_busy_timeout(db: *mut Sqlite3, ms: c_int) -> c_int;
+    fn sqlite3_errmsg(db: *mut Sqlite3) -> *const c_char;
+    fn sqlite3_exec(
+        db: *mut Sqlite3,
+        sql: *const c_char,
+        callback: *const c_void,
+        arg: *mut c_void,
+        errmsg: *mut *mut c_char,
+    ) -> c_int;
+    fn sqlite3_prepare_v2(
+        db: *mut Sqlite3,
+        sql: *const c_char,
+        len: c_int,
+        stmt: *mut *mut Stmt,
+        tail: *mut *const c_char,
+    ) -> c_int;
+    fn sqlite3_bind_int64(stmt: *mut Stmt, idx: c_int, value: i64) -> c_int;
+    fn sqlite3_bind_text(
+        stmt: *mut Stmt,
+        idx: c_int,
+        text: *const c_char,
+        len: c_int,
+        destructor: *const c_void,
+    ) -> c_int;
+    fn sqlite3_step(stmt: *mut Stmt) -> c_int;
+    fn sqlite3_reset(stmt: *mut Stmt) -> c_int;
+    fn sqlite3_finalize(stmt: *mut Stmt) -> c_int;
+}
+
+/// Result codes, from sqlite3.h
+const SQLITE_OK: c_int = 0;
+const SQLITE_TOOBIG: c_int = 18;
+const SQLITE_CONSTRAINT: c_int = 19;
+const SQLITE_MISMATCH: c_int = 20;
+const SQLITE_DONE: c_int = 101;
+
+/// Flags of `sqlite3_open_v2`, from sqlite3.h
+const SQLITE_OPEN_READWRITE: c_int = 0x0000_0002;
+const SQLITE_OPEN_CREATE: c_int = 0x0000_0004;
+const SQLITE_OPEN_FULLMUTEX: c_int = 0x0001_0000;
+
+/// How long an insert waits for another connection's lock, in milliseconds
+const BUSY_TIMEOUT: c_int = 5000;
+
+/// Statements run once the database is open
+const SCHEMA: &str = "PRAGMA journal_mode = WAL;
+CREATE TABLE IF NOT EXISTS logs (
+    id INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    level TEXT NOT NULL,
+    target TEXT NOT NULL,
+    message TEXT NOT NULL,
+    fields TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS logs_time ON logs (time);";
+
+/// The statement each row is inserted with
+const INSERT: &str =
+    "INSERT INTO logs (time, level, target, message, fields) VALUES (?1, ?2, ?3, ?4, ?5)";
+
+/// Format the opening of a row: its time, level and target
+///
+/// The message and fields follow, each behind its length, see
+/// `write_message` and `write_fields`; the rows of a batch are taken apart
+/// again by these lengths.
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+pub(crate) fn write_header<W: Write>(out: &mut W, record: &Record, time: u64) -> io::Result<()> {
+    out.write_all(&(time as i64).to_be_bytes())?;
+    out.write_all(&[record.level() as u8])?;
+    write_text(out, record.target().as_bytes())
+}
+
+/// Format the message of a row
+pub(crate) fn write_message<W: Write>(out: &mut W, record: &Record) -> io::Result<()> {
+    write_text(out, record.args().to_string().as_bytes())
+}
+
+/// Format the fields of a row, the Key/Value pairs as a JSON object
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `femme` - Logger configuration, which says whether keys are nested
+pub(crate) fn write_fields<W: Write>(
+    out: &mut W,
+    record: &Record,
+    femme: &Femme,
+) -> io::Result<()> {
+    let mut members = Vec::new();
+    json::write_pairs(&mut members, record, femme, &[])?;
+    let mut fields = Vec::with_capacity(members.len() + 2);
+    fields.push(b'{');
+    // the members are written each behind a comma
+    fields.extend_from_slice(members.get(1..).unwrap_or_default());
+    fields.push(b'}');
+    write_text(out, &fields)
+}
+
+/// Ends a row
+pub(crate) const TERMINATOR: &[u8] = b"\n";
+
+/// Append text behind its length
+fn write_text<W: Write>(out: &mut W, text: &[u8]) -> io::Result<()> {
+    out.write_all(&(text.len() as u32).to_be_bytes())?;
+    out.write_all(text)
+}
+
+/// A row of a batch, as `write_header`, `write_message` and `write_fields`
+/// formatted it
+struct Row<'a> {
+    time: i64,
+    level: &'static str,
+    target: &'a [u8],
+    message: &'a [u8],
+    fields: &'a [u8],
+}
+
+/// Take the rows of a batch apart
+fn rows(body: &[u8]) -> io::Result<Vec<Row<'_>>> {
+    let mut rows = Vec::new();
+    let mut rest = body;
+    while !rest.is_empty() {
+        if !rows.is_empty() {
+            // rows are joined with commas
+            rest = &rest[1..];
+        }
+        let (row, after) = next_row(rest).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "the SQLite row ended early")
+        })?;
+        rows.push(row);
+        rest = after;
+    }
+    Ok(rows)
+}
+
+/// The first row of some bytes, and the bytes after it
+fn next_row(bytes: &[u8]) -> Option<(Row<'_>, &[u8])> {
+    fn text(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+        let (len, rest) = bytes.split_at_checked(4)?;
+        let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]);
+        rest.split_at_checked(len as usize)
+    }
+
+    let (time, rest) = bytes.split_at_checked(8)?;
+    let mut be = [0; 8];
+    be.copy_from_slice(time);
+    let (&level, rest) = rest.split_first()?;
+    let level = match level {
+        1 => "error",
+        2 => "warn",
+        3 => "info",
+        4 => "debug",
+        _ => "trace",
+    };
+    let (target, rest) = text(rest)?;
+    let (message, rest) = text(rest)?;
+    let (fields, rest) = text(rest)?;
+    let row = Row {
+        time: i64::from_be_bytes(be),
+        level,
+        target,
+        message,
+        fields,
+    };
+    Some((row, rest))
+}
+
+/// An open database, and the statement rows are inserted with
+struct Database {
+    db: *mut Sqlite3,
+    insert: *mut Stmt,
+}
+
+// opened with `SQLITE_OPEN_FULLMUTEX`, the connection may be used from any
+// thread, and it's only used from the one sending batches
+unsafe impl Send for Database {}
+
+impl Database {
+    /// Open the database, creating it and its table if they're missing
+    fn open(path: &Path) -> io::Result<Self> {
+        let filename = CString::new(path.to_string_lossy().into_owned()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid SQLite path {}: it has a NUL", path.display()),
+            )
+        })?;
+        let mut db = ptr::null_mut();
+        let flags = SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE | SQLITE_OPEN_FULLMUTEX;
+        let code = unsafe { sqlite3_open_v2(filename.as_ptr(), &mut db, flags, ptr::null()) };
+        // the handle is allocated even when opening fails, to carry the error
+        let mut database = Database {
+            db,
+            insert: ptr::null_mut(),
+        };
+        database.check(code)?;
+        database.check(unsafe { sqlite3_busy_timeout(db, BUSY_TIMEOUT) })?;
+        database.exec(SCHEMA)?;
+
+        let sql = CString::new(INSERT).unwrap();
+        let code = unsafe {
+            sqlite3_prepare_v2(db, sql.as_ptr(), -1, &mut database.insert, ptr::null_mut())
+        };
+        database.check(code)?;
+        Ok(database)
+    }
+
+    /// Run statements without results
+    fn exec(&self, sql: &str) -> io::Result<()> {
+        let sql = CString::new(sql).unwrap();
+        let code = unsafe {
+            sqlite3_exec(
+                self.db,
+                sql.as_ptr(),
+                ptr::null(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        self.check(code)
+    }
+
+    /// The error of a result code other than `SQLITE_OK`
+    ///
+    /// Rows SQLite refuses fail with `InvalidInput`, so their batch isn't
+    /// retried; others, such as a full disk or a lock held too long, may
+    /// pass.
+    fn check(&self, code: c_int) -> io::Result<()> {
+        if code == SQLITE_OK {
+            return Ok(());
+        }
+        let message = match self.db.is_null() {
+            true => "out of memory".into(),
+            false => unsafe { CStr::from_ptr(sqlite3_errmsg(self.db)) }.to_string_lossy(),
+        };
+        let kind = match code & 0xff {
+            SQLITE_TOOBIG | SQLITE_CONSTRAINT | SQLITE_MISMATCH => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::Other,
+        };
+        Err(io::Error::new(
+            kind,
+            format!("SQLite error {}: {}", code, message),
+        ))
+    }
+
+    /// Insert the rows of a batch in one transaction
+    fn insert(&mut self, body: &[u8]) -> io::Result<()> {
+        let rows = rows(body)?;
+        self.exec("BEGIN IMMEDIATE")?;
+        let res = rows.iter().try_for_each(|row| self.insert_row(row));
+        match res {
+            Ok(()) => self.exec("COMMIT").inspect_err(|_| {
+                let _ = self.exec("ROLLBACK");
+            }),
+            Err(err) => {
+                let _ = self.exec("ROLLBACK");
+                Err(err)
+            }
+        }
+    }
+
+    /// Insert a row with the prepared statement
+    fn insert_row(&self, row: &Row) -> io::Result<()> {
+        let stmt = self.insert;
+        let texts = [row.level.as_bytes(), row.target, row.message, row.fields];
+        let res = (|| {
+            self.check(unsafe { sqlite3_bind_int64(stmt, 1, row.time) })?;
+            for (idx, text) in (2..).zip(texts.iter()) {
+                // bound without a destructor, as `SQLITE_STATIC`: the text
+                // outlives the step below
+                let code = unsafe {
+                    sqlite3_bind_text(
+                        stmt,
+                        idx,
+                        text.as_ptr() as *const c_char,
+                        text.len() as c_int,
+                        ptr::null(),
+                    )
+                };
+                self.check(code)?;
+            }
+            match unsafe { sqlite3_step(stmt) } {
+                SQLITE_DONE => Ok(()),
+                code => self.check(code),
+            }
+        })();
+        unsafe { sqlite3_reset(stmt) };
+        res
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        unsafe {
+            sqlite3_finalize(self.insert);
+            sqlite3_close(self.db);
+        }
+    }
+}
+
+/// Start inserting batches of rows into a database
+///
+/// # Arguments
+/// * `path` - The database file, created if it doesn't exist
+/// * `name` - Name of the destination, for reports
+pub(crate) fn start(path: &Path, name: String) -> io::Result<Batcher> {
+    let mut database = Database::open(path)?;
+    let envelope = Envelope {
+        prefix: Vec::new(),
+        suffix: Vec::new(),
+    };
+    let send: batch::Send = Box::new(move |body, _, _, _| database.insert(body));
+    Batcher::start(name, envelope, send)
+}
@@ -0,0 +1,134 @@
+//! JSON lines in the structured logging layout of Google Cloud Logging.
+//!
+//! The logging agents of GKE, Cloud Run and App Engine read each line of a
+//! container's output as a log entry:
+//!
+//! ```text
+//! {"severity":"INFO","time":"2019-08-21T12:34:56.789Z","logging.googleapis.com/sourceLocation":{"file":"src/main.rs","line":"12","function":"app::server"},"message":"Listening on port 8080","logging.googleapis.com/labels":{"port":"8080"}}
+//! ```
+//!
+//! `severity`, `time` and the source location fill fields of the entry, and
+//! Key/Value pairs become its labels, which are always strings.
+
+use crate::escape::{self, Scalar};
+use crate::time;
+use log::{kv, Level, Record};
+use std::io::{self, Write};
+
+/// Format the opening of an entry, up to the value of `message`
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+pub(crate) fn write_header<W: Write>(out: &mut W, record: &Record, time: u64) -> io::Result<()> {
+    write!(
+        out,
+        "{{\"severity\":\"{}\",\"time\":\"",
+        severity(record.level())
+    )?;
+    time::write_rfc3339(out, time)?;
+    write!(out, "\"")?;
+
+    let location = [
+        ("file", record.file().map(String::from)),
+        ("line", record.line().map(|line| line.to_string())),
+        ("function", record.module_path().map(String::from)),
+    ];
+    let mut location = location
+        .iter()
+        .filter_map(|(name, value)| value.as_ref().map(|value| (name, value)))
+        .peekable();
+    if location.peek().is_some() {
+        write!(out, ",\"logging.googleapis.com/sourceLocation\":{{")?;
+        for (i, (name, value)) in location.enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write!(out, "\"{}\":", name)?;
+            escape::json_str(out, value)?;
+        }
+        write!(out, "}}")?;
+    }
+    write!(out, ",\"message\":")
+}
+
+/// Format Key/Value pairs as the labels of the entry, or nothing without any
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+pub(crate) fn write_labels<W: Write>(out: &mut W, record: &Record) -> io::Result<()> {
+    struct Visitor<'a, W> {
+        out: &'a mut W,
+        empty: bool,
+    }
+
+    impl<'kvs, 'a, W: Write> kv::Visitor<'kvs> for Visitor<'a, W> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            match self.empty {
+                true => write!(self.out, ",\"logging.googleapis.com/labels\":{{")?,
+                false => write!(self.out, ",")?,
+            }
+            self.empty = false;
+            escape::json_str(self.out, key.as_str())?;
+            write!(self.out, ":")?;
+
+            let text = val.to_string();
+            let mut unescaped = String::new();
+            let text = match Scalar::parse(&text, &mut unescaped) {
+                Scalar::Str(text) | Scalar::Raw(text) | Scalar::Text(text) => text,
+            };
+            escape::json_str(self.out, text)?;
+            Ok(())
+        }
+    }
+
+    let mut visitor = Visitor { out, empty: true };
+    record
+        .key_values()
+        .visit(&mut visitor)
+        .map_err(crate::error::kv_to_io)?;
+    if !visitor.empty {
+        write!(visitor.out, "}}")?;
+    }
+    Ok(())
+}
+
+/// The `LogSeverity` of a level; Cloud Logging has nothing finer than
+/// `DEBUG`
+fn severity(level: Level) -> &'static str {
+    match level {
+        Level::Error => "ERROR",
+        Level::Warn => "WARNING",
+        Level::Info => "INFO",
+        Level::Debug | Level::Trace => "DEBUG",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use log::Level;
+
+    #[test]
+    fn layout() {
+        if testing::compiled_out() {
+            return;
+        }
+        let output = testing::log(
+            crate::stackdriver(),
+            Level::Warn,
+            "Disk almost full",
+            &[("free", "2%".into())],
+        );
+        assert!(output.starts_with("{\"severity\":\"WARNING\",\"time\":\""));
+        assert!(output.ends_with(
+            ",\"message\":\"Disk almost full\",\"logging.googleapis.com/labels\":{\"free\":\"2%\"}}\n"
+        ));
+    }
+}
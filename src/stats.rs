@@ -0,0 +1,529 @@
+//! Internal counters exposed through `femme::stats()`.
+
+use log::Level;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Number of records, broken down by level
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LevelCounts {
+    pub error: u64,
+    pub warn: u64,
+    pub info: u64,
+    pub debug: u64,
+    pub trace: u64,
+}
+
+impl LevelCounts {
+    /// Sum over all levels
+    pub fn total(&self) -> u64 {
+        self.error + self.warn + self.info + self.debug + self.trace
+    }
+
+    /// Count for a single level
+    pub fn get(&self, level: Level) -> u64 {
+        match level {
+            Level::Error => self.error,
+            Level::Warn => self.warn,
+            Level::Info => self.info,
+            Level::Debug => self.debug,
+            Level::Trace => self.trace,
+        }
+    }
+}
+
+/// Health and throughput of a single sink
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SinkStats {
+    /// Name of the sink, such as `stdout`
+    pub name: Cow<'static, str>,
+
+    /// Records written successfully
+    pub written: u64,
+
+    /// Records discarded because the sink's queue was full
+    pub dropped: u64,
+
+    /// Records that failed to write
+    pub errors: u64,
+
+    /// Whether the most recent write succeeded
+    pub healthy: bool,
+
+    /// The most recent write error
+    pub last_error: Option<String>,
+}
+
+/// A snapshot of femme's internal counters
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Records that passed the level filters
+    pub emitted: LevelCounts,
+
+    /// Per-sink health, in the order the sinks were configured
+    pub sinks: Vec<SinkStats>,
+}
+
+impl Stats {
+    /// Render the counters in the Prometheus text exposition format
+    ///
+    /// The output can be served as is, or appended to the application's own
+    /// metrics, from a `/metrics` endpoint.
+    ///
+    /// # Examples
+    /// ```
+    /// # if ::log::STATIC_MAX_LEVEL == ::log::LevelFilter::Off { return; }
+    /// femme::start();
+    /// log::error!("Connection refused");
+    ///
+    /// let text = femme::stats().to_prometheus();
+    /// assert!(text.contains("femme_records_total{level=\"error\"} 1\n"));
+    /// assert!(text.contains("femme_sink_healthy{sink=\"stdout\"} 1\n"));
+    /// ```
+    pub fn to_prometheus(&self) -> String {
+        fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+            out.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} {}\n",
+                name, help, name, kind
+            ));
+        }
+
+        let mut out = String::new();
+        family(
+            &mut out,
+            "femme_records_total",
+            "counter",
+            "Records that passed the level filters.",
+        );
+        for level in &[
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ] {
+            out.push_str(&format!(
+                "femme_records_total{{level=\"{}\"}} {}\n",
+                level.to_string().to_lowercase(),
+                self.emitted.get(*level)
+            ));
+        }
+
+        type Value = fn(&SinkStats) -> u64;
+        let per_sink: [(&str, &str, &str, Value); 4] = [
+            (
+                "femme_sink_written_total",
+                "counter",
+                "Records written successfully.",
+                |sink| sink.written,
+            ),
+            (
+                "femme_sink_dropped_total",
+                "counter",
+                "Records discarded because the sink's queue was full.",
+                |sink| sink.dropped,
+            ),
+            (
+                "femme_sink_errors_total",
+                "counter",
+                "Records that failed to write.",
+                |sink| sink.errors,
+            ),
+            (
+                "femme_sink_healthy",
+                "gauge",
+                "Whether the most recent write succeeded.",
+                |sink| sink.healthy as u64,
+            ),
+        ];
+        for (name, kind, help, value) in &per_sink {
+            family(&mut out, name, kind, help);
+            for sink in &self.sinks {
+                out.push_str(&format!(
+                    "{}{{sink=\"{}\"}} {}\n",
+                    name,
+                    label_value(&sink.name),
+                    value(sink)
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// Escape a label value for the Prometheus text format
+fn label_value(value: &str) -> Cow<'_, str> {
+    if !value.contains(&['\\', '"', '\n'][..]) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() + 2);
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Counters for one level each, indexed by `level as usize - 1`
+struct Levels([AtomicU64; 5]);
+
+impl Levels {
+    const fn new() -> Self {
+        Levels([
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+        ])
+    }
+
+    fn incr(&self, level: Level) {
+        self.0[level as usize - 1].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> LevelCounts {
+        let get = |level: Level| self.0[level as usize - 1].load(Ordering::Relaxed);
+        LevelCounts {
+            error: get(Level::Error),
+            warn: get(Level::Warn),
+            info: get(Level::Info),
+            debug: get(Level::Debug),
+            trace: get(Level::Trace),
+        }
+    }
+}
+
+static EMITTED: Levels = Levels::new();
+static SINKS: Mutex<Vec<Arc<SinkCounters>>> = Mutex::new(Vec::new());
+static METRICS: OnceLock<Arc<dyn Metrics>> = OnceLock::new();
+static ALERT: OnceLock<Alert> = OnceLock::new();
+
+/// Receives femme's internal counters as they change
+///
+/// Implement it to publish the health of the logging pipeline through a
+/// metrics library, so it shows up on the same dashboards as the
+/// application's own metrics. Every method does nothing by default.
+///
+/// femme doesn't depend on the `metrics` crate, and has no feature that
+/// does; a bridge to it is a few lines of the application's own:
+///
+/// ```ignore
+/// struct Bridge;
+///
+/// impl femme::Metrics for Bridge {
+///     fn emitted(&self, level: log::Level) {
+///         metrics::counter!("femme_records_emitted", "level" => level.as_str()).increment(1);
+///     }
+///
+///     fn dropped(&self, sink: &str, level: log::Level) {
+///         let sink = sink.to_string();
+///         metrics::counter!("femme_records_dropped", "sink" => sink, "level" => level.as_str())
+///             .increment(1);
+///     }
+///
+///     fn flushed(&self, sink: &str, duration: std::time::Duration) {
+///         let sink = sink.to_string();
+///         metrics::histogram!("femme_flush_seconds", "sink" => sink).record(duration.as_secs_f64());
+///     }
+/// }
+///
+/// femme::ndjson().metrics(Bridge).finish().unwrap();
+/// ```
+///
+/// Methods are called from the logging threads and from background writer
+/// threads, while a record is being written. They must be cheap and must not
+/// log.
+pub trait Metrics: Send + Sync {
+    /// A record passed the level filters
+    fn emitted(&self, level: Level) {
+        let _ = level;
+    }
+
+    /// A record was written to a sink
+    fn written(&self, sink: &str) {
+        let _ = sink;
+    }
+
+    /// A record was discarded because the sink's queue was full
+    fn dropped(&self, sink: &str, level: Level) {
+        let _ = (sink, level);
+    }
+
+    /// A record failed to write to a sink
+    fn failed(&self, sink: &str) {
+        let _ = sink;
+    }
+
+    /// A sink was flushed, including any records still queued for it
+    fn flushed(&self, sink: &str, duration: Duration) {
+        let _ = (sink, duration);
+    }
+}
+
+/// The error rate that triggered an alert
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorRate {
+    /// Error records logged within the window, including the one that
+    /// triggered the alert
+    pub errors: u64,
+
+    /// Length of the sliding window
+    pub window: Duration,
+}
+
+/// Callback receiving error rate alerts
+pub(crate) type AlertHandler = Arc<dyn Fn(&ErrorRate) + Send + Sync>;
+
+/// Watches the rate of error records over a sliding window
+pub(crate) struct Alert {
+    max: u64,
+    window: Duration,
+    handler: AlertHandler,
+    state: Mutex<AlertState>,
+}
+
+struct AlertState {
+    /// When the most recent errors within the window were logged, oldest
+    /// first; never more than one above the threshold
+    times: VecDeque<Instant>,
+
+    /// Whether the next error above the threshold fires the alert
+    armed: bool,
+}
+
+impl Alert {
+    /// Create an alert
+    ///
+    /// # Arguments
+    /// * `max` - Errors allowed within the window before the alert fires
+    /// * `window` - Length of the sliding window
+    /// * `handler` - Callback receiving the alert
+    pub(crate) fn new(max: u64, window: Duration, handler: AlertHandler) -> Self {
+        Alert {
+            max,
+            window,
+            handler,
+            state: Mutex::new(AlertState {
+                times: VecDeque::new(),
+                armed: true,
+            }),
+        }
+    }
+
+    /// Account for an error record, firing the alert when there were too many
+    ///
+    /// The alert fires once when the rate crosses the threshold, and again
+    /// only after an error is logged with the rate back under it.
+    fn error(&self) {
+        let errors = {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            let now = Instant::now();
+            while state
+                .times
+                .front()
+                .is_some_and(|time| now.duration_since(*time) > self.window)
+            {
+                state.times.pop_front();
+            }
+            state.times.push_back(now);
+
+            let errors = state.times.len() as u64;
+            if errors <= self.max {
+                state.armed = true;
+                return;
+            }
+            while state.times.len() as u64 > self.max + 1 {
+                state.times.pop_front();
+            }
+            if !std::mem::replace(&mut state.armed, false) {
+                return;
+            }
+            errors
+        };
+
+        (self.handler)(&ErrorRate {
+            errors,
+            window: self.window,
+        });
+    }
+}
+
+/// Install the error rate alert
+///
+/// Only the first alert is kept, like only the first logger is.
+pub(crate) fn install_alert(alert: Alert) {
+    let _ = ALERT.set(alert);
+}
+
+/// Install the receiver of internal counters
+///
+/// Only the first receiver is kept, like only the first logger is.
+pub(crate) fn install(metrics: Arc<dyn Metrics>) {
+    let _ = METRICS.set(metrics);
+}
+
+fn metrics() -> Option<&'static dyn Metrics> {
+    METRICS.get().map(|metrics| &**metrics)
+}
+
+/// A change in a sink's health
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Transition {
+    /// The first write failed after a successful one
+    Failed,
+
+    /// A write succeeded after `lost` records failed to write
+    Recovered { lost: u64 },
+}
+
+/// Live counters of a sink
+pub(crate) struct SinkCounters {
+    name: Cow<'static, str>,
+    written: AtomicU64,
+    dropped: AtomicU64,
+    errors: AtomicU64,
+    healthy: AtomicBool,
+    last_error: Mutex<Option<String>>,
+
+    /// Errors since the sink last became unhealthy
+    outage: AtomicU64,
+}
+
+impl SinkCounters {
+    /// Create the counters for a sink and make them visible through `stats()`
+    pub(crate) fn register(name: impl Into<Cow<'static, str>>) -> Arc<Self> {
+        let counters = Arc::new(SinkCounters {
+            name: name.into(),
+            written: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            healthy: AtomicBool::new(true),
+            last_error: Mutex::new(None),
+            outage: AtomicU64::new(0),
+        });
+        SINKS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(counters.clone());
+        counters
+    }
+
+    /// Name of the sink
+    #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Account for the outcome of writing a record
+    ///
+    /// Returns the change in health the write caused, if any.
+    pub(crate) fn record(&self, result: &io::Result<()>) -> Option<Transition> {
+        match result {
+            Ok(()) => {
+                self.written.fetch_add(1, Ordering::Relaxed);
+                if let Some(metrics) = metrics() {
+                    metrics.written(&self.name);
+                }
+                match self.healthy.swap(true, Ordering::Relaxed) {
+                    true => None,
+                    false => Some(Transition::Recovered {
+                        lost: self.outage.swap(0, Ordering::Relaxed),
+                    }),
+                }
+            }
+            Err(err) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                self.outage.fetch_add(1, Ordering::Relaxed);
+                if let Some(metrics) = metrics() {
+                    metrics.failed(&self.name);
+                }
+                *self
+                    .last_error
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner) = Some(err.to_string());
+                match self.healthy.swap(false, Ordering::Relaxed) {
+                    true => Some(Transition::Failed),
+                    false => None,
+                }
+            }
+        }
+    }
+
+    /// Account for a record discarded before reaching the sink
+    #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+    pub(crate) fn dropped(&self, level: Level) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        if let Some(metrics) = metrics() {
+            metrics.dropped(&self.name, level);
+        }
+    }
+
+    /// Account for a flush of the sink
+    #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+    pub(crate) fn flushed(&self, duration: Duration) {
+        if let Some(metrics) = metrics() {
+            metrics.flushed(&self.name, duration);
+        }
+    }
+
+    fn snapshot(&self) -> SinkStats {
+        SinkStats {
+            name: self.name.clone(),
+            written: self.written.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            healthy: self.healthy.load(Ordering::Relaxed),
+            last_error: self
+                .last_error
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        }
+    }
+}
+
+/// Account for a record that passed the level filters
+pub(crate) fn emitted(level: Level) {
+    EMITTED.incr(level);
+    if let Some(metrics) = metrics() {
+        metrics.emitted(level);
+    }
+    if level == Level::Error {
+        if let Some(alert) = ALERT.get() {
+            alert.error();
+        }
+    }
+}
+
+/// Take a snapshot of femme's internal counters
+///
+/// # Examples
+/// ```
+/// # if ::log::STATIC_MAX_LEVEL == ::log::LevelFilter::Off { return; }
+/// femme::start();
+/// log::info!("Listening on port 8080");
+///
+/// let stats = femme::stats();
+/// assert_eq!(stats.emitted.info, 1);
+/// assert!(stats.sinks.iter().all(|sink| sink.healthy));
+/// ```
+pub fn stats() -> Stats {
+    Stats {
+        emitted: EMITTED.snapshot(),
+        sinks: SINKS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|sink| sink.snapshot())
+            .collect(),
+    }
+}
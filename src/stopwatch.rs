@@ -0,0 +1,85 @@
+//! Lap timings, for finding where a program spends its time.
+
+use log::{kv, Level, Record};
+use std::time::{Duration, Instant};
+
+/// Times the phases of a run, logging each lap as it ends
+///
+/// Every lap is a record at the info level, with the time since the
+/// stopwatch started as `elapsed_ms` and the time since the previous lap as
+/// `lap_ms`, both in milliseconds. Pretty-printed with `humanize`, they read
+/// like `1.24s`.
+///
+/// # Examples
+/// ```
+/// use femme::Stopwatch;
+///
+/// femme::start();
+///
+/// let mut sw = Stopwatch::new();
+/// // ...
+/// sw.lap("parsed input");
+/// // ...
+/// sw.lap("wrote output");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Stopwatch {
+    start: Instant,
+    last: Instant,
+}
+
+impl Default for Stopwatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stopwatch {
+    /// Start a stopwatch
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Stopwatch {
+            start: now,
+            last: now,
+        }
+    }
+
+    /// Time since the stopwatch started
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// End a lap and log it, with `label` as the message
+    ///
+    /// Returns how long the lap took.
+    #[track_caller]
+    pub fn lap(&mut self, label: &str) -> Duration {
+        let now = Instant::now();
+        let lap = now - self.last;
+        self.last = now;
+
+        if Level::Info <= log::STATIC_MAX_LEVEL && Level::Info <= log::max_level() {
+            let location = std::panic::Location::caller();
+            let fields: &[(&str, kv::Value)] = &[
+                ("elapsed_ms", millis(now - self.start).into()),
+                ("lap_ms", millis(lap).into()),
+            ];
+            log::logger().log(
+                &Record::builder()
+                    .level(Level::Info)
+                    .target("femme")
+                    .file_static(Some(location.file()))
+                    .line(Some(location.line()))
+                    .args(format_args!("{}", label))
+                    .key_values(&fields)
+                    .build(),
+            );
+        }
+        lap
+    }
+}
+
+/// Milliseconds in a duration, to the microsecond
+fn millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
@@ -0,0 +1,311 @@
+//! Stream connections that survive outages of the other end, over TCP, a
+//! Unix socket or a stream of a `Connector`.
+//!
+//! When a write fails the connection is dropped, and records are held in a
+//! buffer until a new one is made. Reconnecting is attempted lazily, on
+//! write, after a delay that doubles with every failed attempt up to a
+//! limit, so a listener that is down isn't flooded with connection attempts.
+//! Once connected, the buffered records are written first, in order.
+//!
+//! A TCP destination is named by `host:port`, looked up again on every
+//! attempt to connect. It can also be looked up every so often while
+//! connected, to follow a collector that moved behind DNS-based load
+//! balancing or failover.
+
+use crate::diagnostics;
+use crate::output::Connector;
+use std::collections::VecDeque;
+use std::io::{self, IoSlice, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Delay before the first attempt to reconnect
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Longest delay between attempts to reconnect
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How long an attempt to connect over TCP may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Bytes held while disconnected; the oldest records are dropped beyond it
+const BUFFER_BYTES: usize = 1024 * 1024;
+
+/// A kind of stream a `Connection` is made over
+pub(crate) trait Stream: Write + Sized {
+    /// Where the other end is reached
+    type Addr;
+
+    /// Connect to the other end
+    fn connect(addr: &Self::Addr) -> io::Result<Self>;
+
+    /// Fail if the other end closed the connection
+    ///
+    /// Writing to a connection closed by the other end succeeds until it
+    /// answers with a reset, so the first record written after it went away
+    /// would be lost without this.
+    fn check_open(&self) -> io::Result<()>;
+
+    /// Whether the other end is now reached elsewhere, so the stream should
+    /// be made again
+    fn moved(&self, _addr: &Self::Addr) -> bool {
+        false
+    }
+}
+
+impl Stream for TcpStream {
+    /// The configured `host:port`, resolved again on each attempt, in case it
+    /// moved
+    type Addr = String;
+
+    fn connect(addr: &String) -> io::Result<Self> {
+        let mut last = None;
+        for addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last = Some((addr, err)),
+            }
+        }
+        Err(match last {
+            Some((addr, err)) => io::Error::new(err.kind(), format!("{}: {}", addr, err)),
+            None => io::Error::new(io::ErrorKind::NotFound, "no address to connect to"),
+        })
+    }
+
+    fn check_open(&self) -> io::Result<()> {
+        self.set_nonblocking(true)?;
+        let res = self.peek(&mut [0]);
+        self.set_nonblocking(false)?;
+        closed(res)
+    }
+
+    /// A lookup that fails keeps the connection
+    fn moved(&self, addr: &String) -> bool {
+        match (self.peer_addr(), addr.to_socket_addrs()) {
+            (Ok(peer), Ok(mut addrs)) => !addrs.any(|addr| addr == peer),
+            _ => false,
+        }
+    }
+}
+
+impl Stream for Box<dyn Write + Send> {
+    type Addr = Connector;
+
+    fn connect(connector: &Connector) -> io::Result<Self> {
+        (connector.connect)()
+    }
+
+    /// A broken connection is only noticed when writing to it fails, since
+    /// the stream can't be peeked at
+    fn check_open(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    type Addr = PathBuf;
+
+    fn connect(path: &PathBuf) -> io::Result<Self> {
+        UnixStream::connect(path)
+    }
+
+    fn check_open(&self) -> io::Result<()> {
+        use std::io::Read;
+
+        // collectors don't answer, so there's nothing to lose by reading
+        self.set_nonblocking(true)?;
+        let res = (&*self).read(&mut [0]);
+        self.set_nonblocking(false)?;
+        closed(res)
+    }
+}
+
+/// Fail if a non-blocking read found the connection closed
+fn closed(res: io::Result<usize>) -> io::Result<()> {
+    match res {
+        Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+        Err(err) if err.kind() != io::ErrorKind::WouldBlock => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// A connection that is made again when it breaks
+pub(crate) struct Connection<S: Stream> {
+    addr: S::Addr,
+
+    /// Name of the destination, for reports
+    name: String,
+    stream: Option<S>,
+
+    /// Writes waiting for a connection, oldest first
+    buffer: VecDeque<Vec<u8>>,
+
+    /// Bytes in `buffer`
+    buffered: usize,
+
+    /// Delay after the next failed attempt
+    backoff: Duration,
+
+    /// When to attempt to reconnect next
+    retry_at: Instant,
+
+    /// How often to look up the other end again, see `Femme::dns_refresh`
+    refresh: Option<Duration>,
+
+    /// When to look it up next
+    refresh_at: Instant,
+}
+
+impl<S: Stream> Connection<S> {
+    /// Connect to the other end, which must be reachable when the logger
+    /// starts
+    ///
+    /// # Arguments
+    /// * `addr` - Where the other end is
+    /// * `name` - Name of the destination, for reports
+    pub(crate) fn open(addr: S::Addr, name: String) -> io::Result<Self> {
+        let stream = S::connect(&addr)?;
+        Ok(Connection {
+            addr,
+            name,
+            stream: Some(stream),
+            buffer: VecDeque::new(),
+            buffered: 0,
+            backoff: MIN_BACKOFF,
+            retry_at: Instant::now(),
+            refresh: None,
+            refresh_at: Instant::now(),
+        })
+    }
+
+    /// Look up the other end again every `interval`, and reconnect if it
+    /// moved
+    pub(crate) fn refresh_every(&mut self, interval: Duration) {
+        self.refresh = Some(interval);
+        self.refresh_at = Instant::now() + interval;
+    }
+
+    /// Write all of `slices`, or hold them until the connection is back
+    ///
+    /// Fails only when buffered records had to be dropped to make room.
+    pub(crate) fn write_all_vectored(&mut self, slices: &mut [IoSlice<'_>]) -> io::Result<()> {
+        self.follow();
+        if self.buffer.is_empty() {
+            if let Some(stream) = &mut self.stream {
+                match stream
+                    .check_open()
+                    .and_then(|_| crate::output::write_all_vectored(stream, slices))
+                {
+                    Ok(()) => return Ok(()),
+                    Err(err) => self.disconnect(err),
+                }
+            }
+        }
+
+        let mut buf = Vec::with_capacity(slices.iter().map(|slice| slice.len()).sum());
+        for slice in slices.iter() {
+            buf.extend_from_slice(slice);
+        }
+        self.buffered += buf.len();
+        self.buffer.push_back(buf);
+        let dropped = self.trim();
+
+        self.drain();
+        match dropped {
+            0 => Ok(()),
+            dropped => Err(io::Error::other(format!(
+                "dropped {} bytes of records while disconnected from {}",
+                dropped, self.name
+            ))),
+        }
+    }
+
+    /// Whether the connection is up, with nothing waiting to be written
+    pub(crate) fn is_connected(&self) -> bool {
+        self.stream.is_some() && self.buffer.is_empty()
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.drain();
+        match &mut self.stream {
+            Some(stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the buffered records, reconnecting first if it's time to
+    fn drain(&mut self) {
+        if self.stream.is_none() {
+            if Instant::now() < self.retry_at {
+                return;
+            }
+            match S::connect(&self.addr) {
+                Ok(stream) => {
+                    self.stream = Some(stream);
+                    self.backoff = MIN_BACKOFF;
+                }
+                Err(err) => return self.disconnect(err),
+            }
+        }
+
+        while let Some(buf) = self.buffer.front() {
+            let stream = match &mut self.stream {
+                Some(stream) => stream,
+                None => return,
+            };
+            if let Err(err) = stream.write_all(buf) {
+                return self.disconnect(err);
+            }
+            self.buffered -= buf.len();
+            self.buffer.pop_front();
+        }
+    }
+
+    /// Drop the connection if the other end moved, when it's time to look,
+    /// so the next write reconnects right away
+    fn follow(&mut self) {
+        let interval = match self.refresh {
+            Some(interval) if Instant::now() >= self.refresh_at => interval,
+            _ => return,
+        };
+        self.refresh_at = Instant::now() + interval;
+        if let Some(stream) = &mut self.stream {
+            if stream.moved(&self.addr) {
+                let _ = stream.flush();
+                self.stream = None;
+                self.retry_at = Instant::now();
+            }
+        }
+    }
+
+    /// Drop the connection and schedule the next attempt to reconnect
+    fn disconnect(&mut self, err: io::Error) {
+        diagnostics::report(format_args!(
+            "connection to {} failed, retrying in {:?}: {}",
+            self.name, self.backoff, err
+        ));
+        self.stream = None;
+        self.retry_at = Instant::now() + self.backoff;
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+    }
+
+    /// Drop the oldest buffered records beyond the limit, but never the
+    /// newest one
+    ///
+    /// Returns the number of bytes dropped.
+    fn trim(&mut self) -> usize {
+        let mut dropped = 0;
+        while self.buffered > BUFFER_BYTES && self.buffer.len() > 1 {
+            if let Some(buf) = self.buffer.pop_front() {
+                self.buffered -= buf.len();
+                dropped += buf.len();
+            }
+        }
+        dropped
+    }
+}
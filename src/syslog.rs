@@ -0,0 +1,254 @@
+//! Syslog messages, as specified by RFC 5424.
+//!
+//! A record becomes a single message:
+//!
+//! ```text
+//! <30>1 2019-08-21T12:34:56.789Z host app 1234 - [kv@32473 user="alice"] Listening on port 8080
+//! ```
+//!
+//! The priority combines the facility with the record's level, Key/Value
+//! pairs become the parameters of a structured data element, and the message
+//! follows on the same line.
+
+use crate::escape::{self, Scalar};
+use crate::time;
+use log::{kv, Level, Record};
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+/// Identifies the structured data element holding Key/Value pairs; 32473 is
+/// the private enterprise number reserved for documentation
+const SD_ID: &str = "kv@32473";
+
+/// Longest parameter name RFC 5424 allows
+const MAX_PARAM_NAME: usize = 32;
+
+/// The kind of program a syslog message comes from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Facility {
+    /// Kernel messages
+    Kern,
+    /// User-level messages
+    #[default]
+    User,
+    /// The mail system
+    Mail,
+    /// System daemons
+    Daemon,
+    /// Security and authorization messages
+    Auth,
+    /// Messages generated by the syslog daemon itself
+    Syslog,
+    /// The line printer subsystem
+    Lpr,
+    /// The network news subsystem
+    News,
+    /// The UUCP subsystem
+    Uucp,
+    /// The clock daemon
+    Cron,
+    /// Private security and authorization messages
+    Authpriv,
+    /// The FTP daemon
+    Ftp,
+    /// Reserved for local use
+    Local0,
+    /// Reserved for local use
+    Local1,
+    /// Reserved for local use
+    Local2,
+    /// Reserved for local use
+    Local3,
+    /// Reserved for local use
+    Local4,
+    /// Reserved for local use
+    Local5,
+    /// Reserved for local use
+    Local6,
+    /// Reserved for local use
+    Local7,
+}
+
+impl Facility {
+    pub(crate) fn code(self) -> u8 {
+        match self {
+            Facility::Kern => 0,
+            Facility::User => 1,
+            Facility::Mail => 2,
+            Facility::Daemon => 3,
+            Facility::Auth => 4,
+            Facility::Syslog => 5,
+            Facility::Lpr => 6,
+            Facility::News => 7,
+            Facility::Uucp => 8,
+            Facility::Cron => 9,
+            Facility::Authpriv => 10,
+            Facility::Ftp => 11,
+            Facility::Local0 => 16,
+            Facility::Local1 => 17,
+            Facility::Local2 => 18,
+            Facility::Local3 => 19,
+            Facility::Local4 => 20,
+            Facility::Local5 => 21,
+            Facility::Local6 => 22,
+            Facility::Local7 => 23,
+        }
+    }
+}
+
+/// Fields of the syslog header that don't change from record to record
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Config {
+    pub(crate) facility: Facility,
+
+    /// Name of the application, if set
+    pub(crate) app_name: Option<String>,
+}
+
+/// The program's file name and the machine's name, looked up once
+static DEFAULTS: OnceLock<(Option<String>, Option<String>)> = OnceLock::new();
+
+fn defaults() -> &'static (Option<String>, Option<String>) {
+    DEFAULTS.get_or_init(|| {
+        let app_name = std::env::current_exe()
+            .ok()
+            .and_then(|exe| header_field(&exe.file_stem()?.to_string_lossy()));
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .ok()
+            .or_else(|| std::env::var("HOSTNAME").ok())
+            .or_else(|| std::env::var("COMPUTERNAME").ok())
+            .and_then(|hostname| header_field(&hostname));
+        (app_name, hostname)
+    })
+}
+
+impl Config {
+    /// Name of the application, by default the program's file name
+    pub(crate) fn app_name(&self) -> Option<&str> {
+        match &self.app_name {
+            Some(app_name) => Some(app_name.as_str()).filter(|app_name| !app_name.is_empty()),
+            None => defaults().0.as_deref(),
+        }
+    }
+
+    /// Name of the machine
+    pub(crate) fn hostname(&self) -> Option<&str> {
+        defaults().1.as_deref()
+    }
+}
+
+/// Keep the characters a header field may hold, printable ASCII
+pub(crate) fn header_field(text: &str) -> Option<String> {
+    let text: String = text.trim().chars().filter(char::is_ascii_graphic).collect();
+    Some(text).filter(|text| !text.is_empty())
+}
+
+/// Format the header of a message, up to the structured data
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `time` - Milliseconds since the Unix epoch
+/// * `config` - Syslog header fields
+pub(crate) fn write_header<W: Write>(
+    out: &mut W,
+    record: &Record,
+    time: u64,
+    config: &Config,
+) -> io::Result<()> {
+    let severity = match record.level() {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    };
+    write!(out, "<{}>1 ", config.facility.code() * 8 + severity)?;
+    time::write_rfc3339(out, time)?;
+    write!(
+        out,
+        " {} {} {} - ",
+        config.hostname().unwrap_or("-"),
+        config.app_name().unwrap_or("-"),
+        std::process::id()
+    )
+}
+
+/// Format Key/Value pairs as a structured data element, or `-` without any
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+pub(crate) fn write_structured_data<W: Write>(out: &mut W, record: &Record) -> io::Result<()> {
+    struct Visitor<'a, W> {
+        out: &'a mut W,
+        empty: bool,
+    }
+
+    impl<'kvs, 'a, W: Write> kv::Visitor<'kvs> for Visitor<'a, W> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            if self.empty {
+                write!(self.out, "[{}", SD_ID)?;
+                self.empty = false;
+            }
+
+            // names are printable ASCII, without `=`, ` `, `]` or `"`
+            let name: String = key
+                .as_str()
+                .chars()
+                .map(|c| match c {
+                    '=' | ' ' | ']' | '"' => '_',
+                    c if c.is_ascii_graphic() => c,
+                    _ => '_',
+                })
+                .take(MAX_PARAM_NAME)
+                .collect();
+            write!(self.out, " {}=\"", name)?;
+
+            // strings are written without the quotes of their `Debug` output
+            let text = val.to_string();
+            let mut unescaped = String::new();
+            let text = match Scalar::parse(&text, &mut unescaped) {
+                Scalar::Str(text) | Scalar::Raw(text) | Scalar::Text(text) => text,
+            };
+            escape::single_line(&mut ParamValue(&mut *self.out), text)?;
+            write!(self.out, "\"")?;
+            Ok(())
+        }
+    }
+
+    let mut visitor = Visitor { out, empty: true };
+    record
+        .key_values()
+        .visit(&mut visitor)
+        .map_err(crate::error::kv_to_io)?;
+    match visitor.empty {
+        true => write!(visitor.out, "-"),
+        false => write!(visitor.out, "]"),
+    }
+}
+
+/// Escapes `"`, `\` and `]` in a parameter value with a backslash
+struct ParamValue<'a, W>(&'a mut W);
+
+impl<'a, W: Write> Write for ParamValue<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut start = 0;
+        for (idx, byte) in buf.iter().enumerate() {
+            if matches!(byte, b'"' | b'\\' | b']') {
+                self.0.write_all(&buf[start..idx])?;
+                self.0.write_all(&[b'\\', *byte])?;
+                start = idx + 1;
+            }
+        }
+        self.0.write_all(&buf[start..])?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
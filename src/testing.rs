@@ -0,0 +1,102 @@
+//! What the unit tests share: a writer to read records back from, a way to
+//! log through a configuration without installing it, and a count of the
+//! allocations each thread makes.
+
+use crate::{x86, Femme};
+use log::{kv, Level, Log, Record};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+thread_local! {
+    /// Allocations made by this thread so far
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The system allocator, counting the allocations of each thread
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Allocations made by this thread so far
+pub(crate) fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+/// A writer whose bytes tests read back
+#[derive(Clone, Default)]
+pub(crate) struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Buffer {
+    /// What was written, as text
+    pub(crate) fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Whether a `max_level` feature drops records before they reach the logger
+///
+/// Tests that read records back have nothing to check then.
+pub(crate) fn compiled_out() -> bool {
+    log::STATIC_MAX_LEVEL < log::LevelFilter::Trace
+}
+
+/// Log a record through a configuration, started but not installed, and
+/// return what it wrote
+///
+/// The record's target is `app`.
+///
+/// # Arguments
+/// * `femme` - Logger configuration, whose destination is replaced
+/// * `level` - Level of the record
+/// * `message` - Message of the record
+/// * `pairs` - Key/Value pairs of the record
+pub(crate) fn log(
+    femme: Femme,
+    level: Level,
+    message: &str,
+    pairs: &[(&str, kv::Value)],
+) -> String {
+    let buffer = Buffer::default();
+    let mut femme = femme.writer(buffer.clone());
+    femme.validate().unwrap();
+    x86::start(&mut femme).unwrap();
+    femme.log(
+        &Record::builder()
+            .level(level)
+            .target("app")
+            .args(format_args!("{}", message))
+            .key_values(&pairs)
+            .build(),
+    );
+    femme.flush();
+    buffer.text()
+}
+
+/// Bytes as lowercase hex, for comparing them with test vectors
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
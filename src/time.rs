@@ -0,0 +1,162 @@
+//! Timestamps, formatted at most once per millisecond and thread.
+//!
+//! Loops that log many records per millisecond would otherwise format the
+//! same timestamp over and over. Each thread keeps the last millisecond it
+//! formatted together with its text, and copies the text while the clock
+//! hasn't moved on.
+
+use std::cell::Cell;
+use std::io::{self, Write};
+
+/// Milliseconds since the Unix epoch, or zero if the clock is set before it
+#[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+pub(crate) fn now() -> u64 {
+    std::time::UNIX_EPOCH
+        .elapsed()
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A UTC calendar date and time of day
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Civil {
+    pub(crate) year: i64,
+    pub(crate) month: u32,
+    pub(crate) day: u32,
+    pub(crate) hour: u32,
+    pub(crate) minute: u32,
+    pub(crate) second: u32,
+    pub(crate) millis: u32,
+}
+
+impl Civil {
+    /// The date and time `millis` after the Unix epoch
+    ///
+    /// Uses Howard Hinnant's `civil_from_days` algorithm.
+    pub(crate) fn from_millis(millis: u64) -> Self {
+        let secs = millis / 1000;
+        let hour = (secs / 3600 % 24) as u32;
+        let minute = (secs / 60 % 60) as u32;
+        let second = (secs % 60) as u32;
+        let days = (secs / 86_400) as i64 + 719_468;
+        let era = days.div_euclid(146_097);
+        let doe = days.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + (month <= 2) as i64;
+        Civil {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            millis: (millis % 1000) as u32,
+        }
+    }
+}
+
+/// Text of a timestamp
+#[derive(Clone, Copy)]
+struct Text {
+    bytes: [u8; 24],
+    len: usize,
+}
+
+impl Text {
+    const EMPTY: Text = Text {
+        bytes: [0; 24],
+        len: 0,
+    };
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// The last timestamp a thread formatted
+#[derive(Clone, Copy)]
+struct Cached {
+    millis: u64,
+    text: Text,
+}
+
+thread_local! {
+    static MILLIS: Cell<Option<Cached>> = const { Cell::new(None) };
+    static RFC3339: Cell<Option<Cached>> = const { Cell::new(None) };
+}
+
+/// Write `millis` as a decimal number
+///
+/// # Arguments
+/// * `out` - Writer to write to
+/// * `millis` - Milliseconds since the Unix epoch
+pub(crate) fn write_millis<W: Write>(out: &mut W, millis: u64) -> io::Result<()> {
+    cached(&MILLIS, millis, format_millis, out)
+}
+
+/// Write `millis` as an RFC 3339 timestamp in UTC, such as
+/// `2019-08-21T12:34:56.789Z`
+///
+/// # Arguments
+/// * `out` - Writer to write to
+/// * `millis` - Milliseconds since the Unix epoch
+pub(crate) fn write_rfc3339<W: Write>(out: &mut W, millis: u64) -> io::Result<()> {
+    cached(&RFC3339, millis, format_rfc3339, out)
+}
+
+fn cached<W: Write>(
+    cache: &'static std::thread::LocalKey<Cell<Option<Cached>>>,
+    millis: u64,
+    format: fn(u64) -> Text,
+    out: &mut W,
+) -> io::Result<()> {
+    let text = cache
+        .try_with(|cache| match cache.get() {
+            Some(cached) if cached.millis == millis => cached.text,
+            _ => {
+                let text = format(millis);
+                cache.set(Some(Cached { millis, text }));
+                text
+            }
+        })
+        .unwrap_or_else(|_| format(millis));
+    out.write_all(text.as_bytes())
+}
+
+fn format_rfc3339(millis: u64) -> Text {
+    let civil = Civil::from_millis(millis);
+    let mut text = Text::EMPTY;
+    let mut cursor = io::Cursor::new(&mut text.bytes[..]);
+    // at most 24 bytes until the year 10000
+    let _ = write!(
+        cursor,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        civil.year, civil.month, civil.day, civil.hour, civil.minute, civil.second, civil.millis
+    );
+    text.len = cursor.position() as usize;
+    text
+}
+
+fn format_millis(millis: u64) -> Text {
+    let mut text = Text::EMPTY;
+    let mut digits = [0; 20];
+    let mut rest = millis;
+    let mut len = 0;
+    loop {
+        digits[len] = b'0' + (rest % 10) as u8;
+        len += 1;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    for (dst, src) in text.bytes.iter_mut().zip(digits[..len].iter().rev()) {
+        *dst = *src;
+    }
+    text.len = len;
+    text
+}
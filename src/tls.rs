@@ -0,0 +1,467 @@
+//! TLS client connections, made through the system's OpenSSL.
+//!
+//! The `tls` feature links libssl and libcrypto of OpenSSL 3, like the
+//! `sqlite` feature links libsqlite3, rather than building a TLS library of
+//! its own. Connections speak TLS 1.2 or 1.3, and the server's certificate
+//! is verified against the system's trusted roots, or against the CAs of
+//! `Tls::ca_file`, and must be issued for the host connected to.
+
+use std::ffi::{CStr, CString};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::os::raw::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::{Arc, OnceLock};
+
+/// Opaque handles of libssl
+#[repr(C)]
+struct SslMethod {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct SslCtx {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct Ssl {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct VerifyParam {
+    _private: [u8; 0],
+}
+
+#[link(name = "ssl")]
+extern "C" {
+    fn TLS_client_method() -> *const SslMethod;
+    fn SSL_CTX_new(method: *const SslMethod) -> *mut SslCtx;
+    fn SSL_CTX_free(ctx: *mut SslCtx);
+    fn SSL_CTX_ctrl(ctx: *mut SslCtx, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    fn SSL_CTX_set_options(ctx: *mut SslCtx, options: u64) -> u64;
+    fn SSL_CTX_set_verify(ctx: *mut SslCtx, mode: c_int, callback: *const c_void);
+    fn SSL_CTX_set_default_verify_paths(ctx: *mut SslCtx) -> c_int;
+    fn SSL_CTX_load_verify_locations(
+        ctx: *mut SslCtx,
+        file: *const c_char,
+        dir: *const c_char,
+    ) -> c_int;
+    fn SSL_CTX_use_certificate_chain_file(ctx: *mut SslCtx, file: *const c_char) -> c_int;
+    fn SSL_CTX_use_PrivateKey_file(ctx: *mut SslCtx, file: *const c_char, kind: c_int) -> c_int;
+    fn SSL_CTX_check_private_key(ctx: *const SslCtx) -> c_int;
+    fn SSL_new(ctx: *mut SslCtx) -> *mut Ssl;
+    fn SSL_free(ssl: *mut Ssl);
+    fn SSL_ctrl(ssl: *mut Ssl, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    fn SSL_set1_host(ssl: *mut Ssl, hostname: *const c_char) -> c_int;
+    fn SSL_get0_param(ssl: *mut Ssl) -> *mut VerifyParam;
+    fn SSL_set_fd(ssl: *mut Ssl, fd: c_int) -> c_int;
+    fn SSL_connect(ssl: *mut Ssl) -> c_int;
+    fn SSL_read(ssl: *mut Ssl, buf: *mut c_void, num: c_int) -> c_int;
+    fn SSL_write(ssl: *mut Ssl, buf: *const c_void, num: c_int) -> c_int;
+    fn SSL_shutdown(ssl: *mut Ssl) -> c_int;
+    fn SSL_get_error(ssl: *const Ssl, ret: c_int) -> c_int;
+    fn SSL_get_verify_result(ssl: *const Ssl) -> c_long;
+    fn SSL_set_alpn_protos(ssl: *mut Ssl, protos: *const u8, len: c_uint) -> c_int;
+    fn SSL_get0_alpn_selected(ssl: *const Ssl, data: *mut *const u8, len: *mut c_uint);
+}
+
+#[link(name = "crypto")]
+extern "C" {
+    fn X509_VERIFY_PARAM_set1_ip_asc(param: *mut VerifyParam, ip: *const c_char) -> c_int;
+    fn X509_verify_cert_error_string(n: c_long) -> *const c_char;
+    fn ERR_get_error() -> c_ulong;
+    fn ERR_error_string_n(e: c_ulong, buf: *mut c_char, len: usize);
+    fn ERR_clear_error();
+}
+
+/// Constants, from ssl.h, tls1.h and x509_vfy.h
+const SSL_VERIFY_PEER: c_int = 0x01;
+const SSL_FILETYPE_PEM: c_int = 1;
+const SSL_CTRL_SET_TLSEXT_HOSTNAME: c_int = 55;
+const SSL_CTRL_SET_MIN_PROTO_VERSION: c_int = 123;
+const TLSEXT_NAMETYPE_HOST_NAME: c_long = 0;
+const TLS1_2_VERSION: c_long = 0x0303;
+const SSL_OP_IGNORE_UNEXPECTED_EOF: u64 = 1 << 7;
+const SSL_ERROR_SSL: c_int = 1;
+const SSL_ERROR_WANT_READ: c_int = 2;
+const SSL_ERROR_WANT_WRITE: c_int = 3;
+const SSL_ERROR_SYSCALL: c_int = 5;
+const SSL_ERROR_ZERO_RETURN: c_int = 6;
+const X509_V_OK: c_long = 0;
+
+/// How connections made with `Destination::tls` verify the server, and
+/// whether they present a certificate of their own
+///
+/// By default the server's certificate must be issued, by a root the system
+/// trusts, for the host connected to.
+///
+/// # Examples
+/// ```no_run
+/// use femme::{Destination, Tls};
+///
+/// let tls = Tls::new()
+///     .ca_file("/etc/collector/ca.pem")
+///     .client_cert("/etc/app/client.pem", "/etc/app/client.key");
+/// femme::ndjson()
+///     .destination(Destination::tls("logs.example.com:6514", tls))
+///     .finish()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tls {
+    ca_file: Option<PathBuf>,
+    client_cert: Option<(PathBuf, PathBuf)>,
+    server_name: Option<String>,
+}
+
+impl Tls {
+    /// Trust the roots of the system, and present no certificate
+    pub fn new() -> Self {
+        Tls::default()
+    }
+
+    /// Trust the CAs in a PEM file instead of the roots of the system, such
+    /// as those of a private CA
+    pub fn ca_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ca_file = Some(path.into());
+        self
+    }
+
+    /// Present a client certificate to servers asking for one
+    ///
+    /// # Arguments
+    /// * `cert` - PEM file with the certificate, followed by the
+    ///   intermediates it was issued by
+    /// * `key` - PEM file with its private key, unencrypted
+    pub fn client_cert(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.client_cert = Some((cert.into(), key.into()));
+        self
+    }
+
+    /// Expect the server's certificate to be issued for a name other than
+    /// the host connected to, such as when connecting to an IP address
+    pub fn server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+}
+
+/// Settings of the connections made to servers, shared by them
+pub(crate) struct Context {
+    ctx: *mut SslCtx,
+    server_name: Option<String>,
+}
+
+// SAFETY: an `SSL_CTX` may be used from any thread once it's set up, which
+// `Context::new` finishes before handing it out
+unsafe impl Send for Context {}
+unsafe impl Sync for Context {}
+
+impl Context {
+    /// Set up connections as `tls` says, loading the certificates it names
+    pub(crate) fn new(tls: &Tls) -> io::Result<Arc<Context>> {
+        // SAFETY: the method is static, and a context that fails to be set
+        // up is freed when it's dropped
+        let context = unsafe {
+            let ctx = SSL_CTX_new(TLS_client_method());
+            if ctx.is_null() {
+                return Err(error("failed to create a TLS context"));
+            }
+            Context {
+                ctx,
+                server_name: tls.server_name.clone(),
+            }
+        };
+        let ctx = context.ctx;
+
+        // SAFETY: `ctx` is valid, and the paths outlive the calls
+        unsafe {
+            SSL_CTX_ctrl(
+                ctx,
+                SSL_CTRL_SET_MIN_PROTO_VERSION,
+                TLS1_2_VERSION,
+                ptr::null_mut(),
+            );
+            // servers often close HTTP connections without a close_notify
+            SSL_CTX_set_options(ctx, SSL_OP_IGNORE_UNEXPECTED_EOF);
+            SSL_CTX_set_verify(ctx, SSL_VERIFY_PEER, ptr::null());
+
+            let trusted = match &tls.ca_file {
+                Some(path) => {
+                    let file = c_path(path)?;
+                    SSL_CTX_load_verify_locations(ctx, file.as_ptr(), ptr::null())
+                }
+                None => SSL_CTX_set_default_verify_paths(ctx),
+            };
+            if trusted != 1 {
+                return Err(error(&match &tls.ca_file {
+                    Some(path) => format!("failed to load the CAs in {}", path.display()),
+                    None => "failed to load the system's trusted roots".to_string(),
+                }));
+            }
+
+            if let Some((cert, key)) = &tls.client_cert {
+                let cert_file = c_path(cert)?;
+                let key_file = c_path(key)?;
+                if SSL_CTX_use_certificate_chain_file(ctx, cert_file.as_ptr()) != 1 {
+                    let reason = format!("failed to load the certificate in {}", cert.display());
+                    return Err(error(&reason));
+                }
+                if SSL_CTX_use_PrivateKey_file(ctx, key_file.as_ptr(), SSL_FILETYPE_PEM) != 1
+                    || SSL_CTX_check_private_key(ctx) != 1
+                {
+                    let reason = format!(
+                        "failed to load the key of {} in {}",
+                        cert.display(),
+                        key.display()
+                    );
+                    return Err(error(&reason));
+                }
+            }
+        }
+        Ok(Arc::new(context))
+    }
+
+    /// Settings trusting the roots of the system, set up once for the
+    /// `https://` requests of HTTP sinks
+    pub(crate) fn system() -> io::Result<Arc<Context>> {
+        static SYSTEM: OnceLock<Arc<Context>> = OnceLock::new();
+        if let Some(context) = SYSTEM.get() {
+            return Ok(context.clone());
+        }
+        let context = Context::new(&Tls::new())?;
+        Ok(SYSTEM.get_or_init(|| context).clone())
+    }
+
+    /// Make a TLS connection over a stream, and verify the server
+    ///
+    /// # Arguments
+    /// * `stream` - The connection to the server, or to a proxy tunnelling
+    ///   to it
+    /// * `host` - Name or address of the server, which its certificate must
+    ///   be issued for unless `Tls::server_name` gives another
+    /// * `alpn` - The protocol the server must agree to speak, such as `h2`,
+    ///   if any
+    pub(crate) fn connect(
+        &self,
+        stream: TcpStream,
+        host: &str,
+        alpn: Option<&str>,
+    ) -> io::Result<TlsStream> {
+        let host = self
+            .server_name
+            .as_deref()
+            .unwrap_or(host)
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let name = CString::new(host)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid host name"))?;
+
+        // SAFETY: `ctx` is valid, and the stream owning the `SSL` frees it
+        unsafe {
+            ERR_clear_error();
+            let ssl = SSL_new(self.ctx);
+            if ssl.is_null() {
+                return Err(error("failed to create a TLS connection"));
+            }
+            let tls = TlsStream { ssl, stream };
+
+            // an address has no name to send, and is matched as an address
+            let verified = match host.parse::<IpAddr>() {
+                Ok(_) => X509_VERIFY_PARAM_set1_ip_asc(SSL_get0_param(ssl), name.as_ptr()),
+                Err(_) => {
+                    SSL_ctrl(
+                        ssl,
+                        SSL_CTRL_SET_TLSEXT_HOSTNAME,
+                        TLSEXT_NAMETYPE_HOST_NAME,
+                        name.as_ptr() as *mut c_void,
+                    );
+                    SSL_set1_host(ssl, name.as_ptr())
+                }
+            };
+            if verified != 1 || SSL_set_fd(ssl, raw_socket(&tls.stream)) != 1 {
+                return Err(error("failed to set up a TLS connection"));
+            }
+            if let Some(protocol) = alpn {
+                let mut protos = vec![protocol.len() as u8];
+                protos.extend_from_slice(protocol.as_bytes());
+                // unlike most of OpenSSL, this returns 0 on success
+                if SSL_set_alpn_protos(ssl, protos.as_ptr(), protos.len() as c_uint) != 0 {
+                    return Err(error("failed to set up a TLS connection"));
+                }
+            }
+
+            let ret = SSL_connect(ssl);
+            if ret != 1 {
+                let result = SSL_get_verify_result(ssl);
+                if result != X509_V_OK {
+                    let reason = CStr::from_ptr(X509_verify_cert_error_string(result));
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "the certificate of {} isn't trusted: {}",
+                            host,
+                            reason.to_string_lossy()
+                        ),
+                    ));
+                }
+                return Err(tls.failure(ret, "TLS handshake failed"));
+            }
+            if let Some(protocol) = alpn {
+                let mut data = ptr::null();
+                let mut len = 0;
+                SSL_get0_alpn_selected(ssl, &mut data, &mut len);
+                let selected = match data.is_null() {
+                    true => &[][..],
+                    false => std::slice::from_raw_parts(data, len as usize),
+                };
+                if selected != protocol.as_bytes() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} doesn't speak {}", host, protocol),
+                    ));
+                }
+            }
+            Ok(tls)
+        }
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        // SAFETY: the context is no longer used; connections hold their own
+        // reference to it
+        unsafe { SSL_CTX_free(self.ctx) };
+    }
+}
+
+/// A TLS connection over a TCP stream
+pub(crate) struct TlsStream {
+    ssl: *mut Ssl,
+    stream: TcpStream,
+}
+
+// SAFETY: the `SSL` is only used through `&mut self`, from one thread at a
+// time
+unsafe impl Send for TlsStream {}
+
+impl TlsStream {
+    /// The error of a call that returned `ret`
+    fn failure(&self, ret: c_int, what: &str) -> io::Error {
+        // SAFETY: `ssl` is valid while the stream lives
+        match unsafe { SSL_get_error(self.ssl, ret) } {
+            SSL_ERROR_WANT_READ | SSL_ERROR_WANT_WRITE => {
+                io::Error::new(io::ErrorKind::WouldBlock, format!("{}: timed out", what))
+            }
+            SSL_ERROR_SYSCALL => match io::Error::last_os_error() {
+                err if err.raw_os_error().unwrap_or(0) != 0 => err,
+                _ => error(what),
+            },
+            SSL_ERROR_SSL => error(what),
+            code => io::Error::other(format!("{}: error {}", what, code)),
+        }
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        // SAFETY: `buf` is valid for `len` bytes
+        let ret = unsafe {
+            ERR_clear_error();
+            SSL_read(self.ssl, buf.as_mut_ptr() as *mut c_void, len)
+        };
+        if ret > 0 {
+            return Ok(ret as usize);
+        }
+        // SAFETY: as above
+        match unsafe { SSL_get_error(self.ssl, ret) } {
+            SSL_ERROR_ZERO_RETURN => Ok(0),
+            _ => Err(self.failure(ret, "TLS read failed")),
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        // SAFETY: `buf` is valid for `len` bytes
+        let ret = unsafe {
+            ERR_clear_error();
+            SSL_write(self.ssl, buf.as_ptr() as *const c_void, len)
+        };
+        match ret > 0 {
+            true => Ok(ret as usize),
+            false => Err(self.failure(ret, "TLS write failed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // records are handed to the socket as they're written
+        Ok(())
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        // SAFETY: the `SSL` isn't used past this; the close_notify is best
+        // effort
+        unsafe {
+            SSL_shutdown(self.ssl);
+            SSL_free(self.ssl);
+        }
+    }
+}
+
+/// The socket of a stream, as OpenSSL takes it
+#[cfg(unix)]
+fn raw_socket(stream: &TcpStream) -> c_int {
+    use std::os::unix::io::AsRawFd;
+    stream.as_raw_fd()
+}
+
+#[cfg(windows)]
+fn raw_socket(stream: &TcpStream) -> c_int {
+    use std::os::windows::io::AsRawSocket;
+    stream.as_raw_socket() as c_int
+}
+
+/// A path as OpenSSL takes it
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.to_string_lossy().into_owned()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid path {}", path.display()),
+        )
+    })
+}
+
+/// An error, with the reason OpenSSL gives for it, if any
+///
+/// # Arguments
+/// * `what` - What failed
+fn error(what: &str) -> io::Error {
+    // SAFETY: the buffer is as long as it's said to be, and the string
+    // written to it is nul-terminated
+    let reason = unsafe {
+        match ERR_get_error() {
+            0 => None,
+            code => {
+                let mut buf = [0 as c_char; 256];
+                ERR_error_string_n(code, buf.as_mut_ptr(), buf.len());
+                ERR_clear_error();
+                Some(CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned())
+            }
+        }
+    };
+    match reason {
+        Some(reason) => io::Error::other(format!("{}: {}", what, reason)),
+        None => io::Error::other(what.to_string()),
+    }
+}
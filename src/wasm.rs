@@ -1,11 +1,279 @@
-//! Print logs as ndjson.
+//! Print logs to the JavaScript console.
 
-use crate::Femme;
-use js_sys::Object;
+use crate::stats::{self, SinkCounters};
+use crate::{diagnostics, json, Femme};
+use js_sys::{Array, Date, Function, Object, Promise, Reflect, Uint8Array};
 use log::{kv, Level, Log, Metadata, Record};
 use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::console;
 
 use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// JavaScript environment the module is running in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Runtime {
+    /// A browser or any other host whose console isn't backed by streams
+    Browser,
+
+    /// Node.js, where the console writes to stdout and stderr
+    Node,
+
+    /// Deno, which has no `window` but exposes stdout and stderr through the
+    /// `Deno` global
+    Deno,
+}
+
+impl Runtime {
+    /// Inspect the global object to find out which runtime is hosting us
+    fn detect() -> Self {
+        let is_string = |path: &[&str]| global_path(path).is_some_and(|v| v.is_string());
+
+        if is_string(&["Deno", "version", "deno"]) {
+            Runtime::Deno
+        } else if is_string(&["process", "versions", "node"]) {
+            Runtime::Node
+        } else {
+            Runtime::Browser
+        }
+    }
+}
+
+/// Look up a property, treating `undefined` and `null` as missing
+fn property(target: &JsValue, key: &str) -> Option<JsValue> {
+    Reflect::get(target, &JsValue::from_str(key))
+        .ok()
+        .filter(|value| !value.is_undefined() && !value.is_null())
+}
+
+/// Follow a path of properties starting at the global object
+fn global_path(path: &[&str]) -> Option<JsValue> {
+    path.iter()
+        .try_fold(js_sys::global().into(), |value, key| property(&value, key))
+}
+
+/// Detect the runtime and set up the outputs before the logger is installed
+pub(crate) fn start(femme: &mut Femme) {
+    femme.runtime = Runtime::detect();
+    femme.sampler = femme.limit.map(Sampler::new);
+    femme.batch = femme
+        .fetch
+        .clone()
+        .map(|(endpoint, capacity)| Batch::start(endpoint, capacity));
+}
+
+/// Caps the number of low-severity lines reaching the console
+///
+/// Browsers slow to a crawl when thousands of lines are logged, so at most
+/// `limit` info, debug and trace lines are printed per second. Warnings and
+/// errors always get through. Once a second passes, the number of lines held
+/// back is printed as a warning of its own.
+pub(crate) struct Sampler {
+    limit: usize,
+    window: Mutex<Window>,
+}
+
+/// The second currently being sampled
+#[derive(Default)]
+struct Window {
+    start: f64,
+    printed: usize,
+    suppressed: u64,
+}
+
+impl Sampler {
+    fn new(limit: usize) -> Self {
+        Sampler {
+            limit,
+            window: Mutex::new(Window::default()),
+        }
+    }
+
+    /// Whether a record may be printed
+    fn admit(&self, level: Level) -> bool {
+        let now = Date::now();
+        let mut window = self.window.lock().unwrap_or_else(PoisonError::into_inner);
+        if now - window.start >= 1000.0 {
+            if window.suppressed > 0 {
+                console::warn_1(
+                    &format!(
+                        "femme: {} lines suppressed to keep the console responsive",
+                        window.suppressed
+                    )
+                    .into(),
+                );
+            }
+            *window = Window {
+                start: now,
+                ..Window::default()
+            };
+        }
+
+        if level <= Level::Warn {
+            return true;
+        }
+        match window.printed < self.limit {
+            true => {
+                window.printed += 1;
+                true
+            }
+            false => {
+                window.suppressed += 1;
+                false
+            }
+        }
+    }
+}
+
+/// How a batch of records is sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Transport {
+    /// `fetch`, used while the page is alive
+    Fetch,
+
+    /// `navigator.sendBeacon`, which still delivers while the page unloads
+    Beacon,
+}
+
+/// Records waiting to be POSTed to an endpoint
+pub(crate) struct Batch {
+    endpoint: String,
+    capacity: usize,
+    counters: Arc<SinkCounters>,
+    pending: Mutex<Pending>,
+}
+
+/// The ndjson body of the next request
+#[derive(Default)]
+struct Pending {
+    body: Vec<u8>,
+    records: usize,
+}
+
+impl Batch {
+    /// Create the batch and send what is left of it when the page goes away
+    ///
+    /// # Arguments
+    /// * `endpoint` - URL to POST the records to
+    /// * `capacity` - Number of records that trigger a request
+    fn start(endpoint: String, capacity: usize) -> Arc<Self> {
+        let batch = Arc::new(Batch {
+            counters: SinkCounters::register(format!("fetch {}", endpoint)),
+            endpoint,
+            capacity,
+            pending: Mutex::new(Pending::default()),
+        });
+
+        // `pagehide` fires reliably on mobile where `unload` doesn't. Node has
+        // no `addEventListener` and no pages to leave.
+        let add_listener = property(&js_sys::global(), "addEventListener")
+            .and_then(|add| add.dyn_into::<Function>().ok());
+        if let Some(add_listener) = add_listener {
+            let unloading = batch.clone();
+            let on_hide = Closure::wrap(
+                Box::new(move || unloading.send(Transport::Beacon)) as Box<dyn FnMut()>
+            );
+            let _ = add_listener.call2(&js_sys::global(), &"pagehide".into(), on_hide.as_ref());
+            on_hide.forget();
+        }
+
+        batch
+    }
+
+    /// Queue a record, sending the batch once it is full
+    fn push(&self, record: &Record<'_>, femme: &Femme) {
+        let full = {
+            let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+            let time = Date::now() as u64;
+            if let Err(err) = json::write_ndjson(&mut pending.body, record, time, femme) {
+                diagnostics::report(format_args!("failed to format record: {}", err));
+                return;
+            }
+            pending.records += 1;
+            pending.records >= self.capacity
+        };
+
+        if full {
+            self.send(Transport::Fetch);
+        }
+    }
+
+    /// Send the waiting records, if there are any
+    fn send(&self, transport: Transport) {
+        let pending =
+            std::mem::take(&mut *self.pending.lock().unwrap_or_else(PoisonError::into_inner));
+        if pending.records == 0 {
+            return;
+        }
+
+        let body = JsValue::from_str(&String::from_utf8_lossy(&pending.body));
+        let res = match transport {
+            Transport::Beacon => {
+                send_beacon(&self.endpoint, &body).or_else(|_| send_fetch(&self.endpoint, &body))
+            }
+            Transport::Fetch => send_fetch(&self.endpoint, &body),
+        };
+
+        if let Err(err) = &res {
+            diagnostics::report(format_args!(
+                "failed to send {} records to {}: {}",
+                pending.records, self.endpoint, err
+            ));
+        }
+        for _ in 0..pending.records {
+            self.counters.record(&res);
+        }
+    }
+}
+
+/// POST a body with `fetch`
+///
+/// `keepalive` lets the request outlive the page. A request that is rejected
+/// later, once the promise settles, is reported as an internal error.
+fn send_fetch(endpoint: &str, body: &JsValue) -> io::Result<()> {
+    thread_local! {
+        static ON_REJECT: Closure<dyn FnMut(JsValue)> = Closure::wrap(Box::new(|err: JsValue| {
+            diagnostics::report(format_args!("failed to send records: {:?}", err));
+        }) as Box<dyn FnMut(JsValue)>);
+    }
+
+    let fetch: Function = property(&js_sys::global(), "fetch")
+        .and_then(|fetch| fetch.dyn_into().ok())
+        .ok_or_else(|| io::Error::other("`fetch` is missing"))?;
+
+    let headers = Object::new();
+    set(&headers, "Content-Type", &"application/x-ndjson".into());
+    let init = Object::new();
+    set(&init, "method", &"POST".into());
+    set(&init, "body", body);
+    set(&init, "headers", &headers);
+    set(&init, "keepalive", &JsValue::from_bool(true));
+
+    let promise: Promise = fetch
+        .call2(&js_sys::global(), &endpoint.into(), &init)
+        .map_err(|err| io::Error::other(format!("{:?}", err)))?
+        .unchecked_into();
+    ON_REJECT.with(|on_reject| promise.catch(on_reject));
+    Ok(())
+}
+
+/// POST a body with `navigator.sendBeacon`
+fn send_beacon(endpoint: &str, body: &JsValue) -> io::Result<()> {
+    let navigator =
+        global_path(&["navigator"]).ok_or_else(|| io::Error::other("`navigator` is missing"))?;
+    let send_beacon: Function = property(&navigator, "sendBeacon")
+        .and_then(|send_beacon| send_beacon.dyn_into().ok())
+        .ok_or_else(|| io::Error::other("`navigator.sendBeacon` is missing"))?;
+
+    // `sendBeacon` returns false when the browser refuses to queue the data
+    match send_beacon.call2(&navigator, &endpoint.into(), body) {
+        Ok(queued) if queued.as_bool() == Some(true) => Ok(()),
+        Ok(_) => Err(io::Error::other("the browser refused to queue the records")),
+        Err(err) => Err(io::Error::other(format!("{:?}", err))),
+    }
+}
 
 impl Log for Femme {
     fn enabled(&self, metadata: &Metadata<'_>) -> bool {
@@ -13,36 +281,171 @@ impl Log for Femme {
     }
 
     fn log(&self, record: &Record<'_>) {
+        // a constant comparison, letting the compiler drop everything below
+        // when a `max_level_*` feature rules the level out
+        if record.level() > log::STATIC_MAX_LEVEL {
+            return;
+        }
+
         let level = self.module_level(record);
 
-        if record.level() <= *level {
-            let args = format!("{}", record.args()).into();
-            let line = format_line(&record).into();
-
-            match format_kv_pairs(&record) {
-                Some(obj) => match record.level() {
-                    Level::Error => web_sys::console::error_3(&args, &obj, &line),
-                    Level::Warn => web_sys::console::warn_3(&args, &obj, &line),
-                    Level::Info => web_sys::console::info_3(&args, &obj, &line),
-                    _ => web_sys::console::debug_3(&args, &obj, &line),
-                },
-                None => match record.level() {
-                    Level::Error => web_sys::console::error_2(&args, &line),
-                    Level::Warn => web_sys::console::warn_2(&args, &line),
-                    Level::Info => web_sys::console::info_2(&args, &line),
-                    _ => web_sys::console::debug_2(&args, &line),
-                },
+        if record.level() <= level {
+            stats::emitted(record.level());
+            let sampled = self
+                .sampler
+                .as_ref()
+                .is_none_or(|s| s.admit(record.level()));
+            self.with_fields(record, |record| match self.runtime {
+                // like `femme::start`, pretty-print in debug builds and
+                // write ndjson in release builds
+                Runtime::Deno if !cfg!(debug_assertions) => write_deno(record, self),
+                _ if sampled => write_console(record, self),
+                _ => {}
+            });
+            if let Some(batch) = &self.batch {
+                self.with_fields(record, |record| batch.push(record, self));
+            }
+        }
+    }
+    fn flush(&self) {
+        if let Some(batch) = &self.batch {
+            batch.send(Transport::Fetch);
+        }
+    }
+}
+
+/// Write a record to the console
+///
+/// Under Node, errors and warnings go to `console.error` (stderr) and
+/// everything else to `console.log` (stdout), so redirecting the output of a
+/// wasm-powered command line tool behaves like it does for a native one.
+///
+/// The message is passed as an argument of its own rather than as the format
+/// string, so a `%` in it is printed as is.
+///
+/// Records with at least `console_table` key/value pairs get their pairs
+/// rendered as a table after the message.
+fn write_console(record: &Record<'_>, femme: &Femme) {
+    let args = Array::new();
+    match (&femme.prefix, &femme.style) {
+        (None, None) => {}
+        (Some(prefix), None) => {
+            args.push(&prefix.into());
+        }
+        (prefix, Some(style)) => {
+            let label = prefix.as_deref().unwrap_or_else(|| record.target());
+            args.push(&format!("%c{}", label.replace('%', "%%")).into());
+            args.push(&style.into());
+        }
+    }
+    args.push(&format!("{}", record.args()).into());
+
+    let busy = |obj: &Object| {
+        femme
+            .table
+            .is_some_and(|min_pairs| Object::keys(obj).length() as usize >= min_pairs)
+    };
+    let (kvs, table) = match format_kv_pairs(record) {
+        Some(obj) if busy(&obj) => (None, Some(obj)),
+        kvs => (kvs, None),
+    };
+    if let Some(obj) = &kvs {
+        args.push(obj);
+    }
+    args.push(&metadata(record));
+
+    match (femme.runtime, record.level()) {
+        (Runtime::Node, Level::Error)
+        | (Runtime::Node, Level::Warn)
+        | (Runtime::Deno, Level::Error)
+        | (Runtime::Deno, Level::Warn) => console::error(&args),
+        (Runtime::Node, _) | (Runtime::Deno, _) => console::log(&args),
+        (Runtime::Browser, Level::Error) => console::error(&args),
+        (Runtime::Browser, Level::Warn) => console::warn(&args),
+        (Runtime::Browser, Level::Info) => console::info(&args),
+        (Runtime::Browser, _) => console::debug(&args),
+    }
+
+    if let Some(table) = table {
+        console_table(&table);
+    }
+}
+
+/// Render an object with `console.table`
+fn console_table(data: &Object) {
+    let console = match global_path(&["console"]) {
+        Some(console) => console,
+        None => return,
+    };
+    if let Some(Ok(table)) = property(&console, "table").map(JsCast::dyn_into::<Function>) {
+        let _ = table.call1(&console, data);
+    }
+}
+
+/// Write a record to `Deno.stdout` as a line of ndjson
+fn write_deno(record: &Record<'_>, femme: &Femme) {
+    let mut line = Vec::new();
+    if let Err(err) = json::write_ndjson(&mut line, record, Date::now() as u64, femme) {
+        diagnostics::report(format_args!("failed to format record: {}", err));
+        return;
+    }
+
+    let stdout = match global_path(&["Deno", "stdout"]) {
+        Some(stdout) => stdout,
+        None => return diagnostics::report("`Deno.stdout` is missing"),
+    };
+    let write_sync: Function = match property(&stdout, "writeSync").map(JsCast::dyn_into) {
+        Some(Ok(write_sync)) => write_sync,
+        _ => return diagnostics::report("`Deno.stdout.writeSync` is missing"),
+    };
+
+    // `writeSync` may write only part of the buffer
+    let mut bytes = Uint8Array::from(&line[..]);
+    while bytes.length() > 0 {
+        let written = match write_sync.call1(&stdout, &bytes) {
+            Ok(written) => written.as_f64().unwrap_or(0.0) as u32,
+            Err(err) => {
+                return diagnostics::report(format_args!("failed to write to stdout: {:?}", err))
             }
+        };
+        if written == 0 {
+            return diagnostics::report("failed to write to stdout: nothing was written");
         }
+        bytes = bytes.subarray(written, bytes.length());
     }
-    fn flush(&self) {}
 }
 
-fn format_line(record: &Record<'_>) -> String {
-    match (record.file(), record.line()) {
-        (Some(file), Some(line)) => format!("({}:{})", file, line),
-        _ => String::new(),
+/// Fields describing where and when a record was logged
+///
+/// `file` and `line` let tooling with source maps or DWARF support jump to the
+/// Rust source; `time` is the wall clock time and `elapsed_ms` the high
+/// resolution time since the page or process started, for timing analysis in
+/// the devtools.
+fn metadata(record: &Record<'_>) -> Object {
+    let metadata = Object::new();
+    if let Some(file) = record.file() {
+        set(&metadata, "file", &JsValue::from_str(file));
+    }
+    if let Some(line) = record.line() {
+        set(&metadata, "line", &JsValue::from_f64(f64::from(line)));
     }
+    set(&metadata, "time", &Date::new_0().to_iso_string().into());
+    if let Some(elapsed) = performance_now() {
+        set(&metadata, "elapsed_ms", &JsValue::from_f64(elapsed));
+    }
+    metadata
+}
+
+/// Milliseconds since the time origin, if the runtime has `performance`
+fn performance_now() -> Option<f64> {
+    let performance = property(&js_sys::global(), "performance")?;
+    let now: Function = property(&performance, "now")?.dyn_into().ok()?;
+    now.call0(&performance).ok()?.as_f64()
+}
+
+/// Set a property, ignoring objects that refuse it
+fn set(target: &Object, key: &str, value: &JsValue) {
+    let _ = Reflect::set(target, &JsValue::from_str(key), value);
 }
 
 fn format_kv_pairs(record: &Record) -> Option<Object> {
@@ -72,13 +475,15 @@ fn format_kv_pairs(record: &Record) -> Option<Object> {
     }
 
     let mut visitor = Visitor::new();
-    record.key_values().visit(&mut visitor).unwrap();
+    if let Err(err) = record.key_values().visit(&mut visitor) {
+        diagnostics::report(format_args!("failed to read key/value pairs: {}", err));
+    }
 
-    match visitor.hashmap.as_ref() {
-        Some(hashmap) => {
-            let val = JsValue::from_serde(hashmap).unwrap();
-            Some(Object::from(val))
+    match JsValue::from_serde(visitor.hashmap.as_ref()?) {
+        Ok(val) => Some(Object::from(val)),
+        Err(err) => {
+            diagnostics::report(format_args!("failed to serialize key/value pairs: {}", err));
+            None
         }
-        None => None,
     }
 }
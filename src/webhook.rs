@@ -0,0 +1,147 @@
+//! Alerts POSTed to a chat webhook, such as Slack's, Discord's or Teams'.
+//!
+//! Each record becomes a payload with its text under both `text`, which
+//! Slack, Mattermost and Teams read, and `content`, which Discord reads:
+//!
+//! ```text
+//! {"text":"[WARN] app::disk: Disk almost full free=2%","content":"[WARN] app::disk: Disk almost full free=2%"}
+//! ```
+//!
+//! At most a number of alerts are sent each minute; those beyond it are
+//! counted, and announced by an alert of their own before the first one
+//! sent after the minute. Chats don't take compressed payloads, so alerts
+//! never are.
+
+use crate::batch::{self, Batcher, Envelope, Limits};
+use crate::{escape, http, logfmt, Femme};
+use log::Record;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Alerts sent each minute, by default
+pub(crate) const DEFAULT_PER_MINUTE: u32 = 10;
+
+/// Most characters of the text of an alert, as Discord allows
+const MAX_TEXT: usize = 2000;
+
+/// How long the rate of alerts is counted over
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Format a record as the payload of an alert
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `femme` - Logger configuration
+pub(crate) fn write_payload<W: Write>(
+    out: &mut W,
+    record: &Record,
+    femme: &Femme,
+) -> io::Result<()> {
+    let mut text = Vec::new();
+    write!(
+        text,
+        "[{}] {}: {}",
+        record.level(),
+        record.target(),
+        record.args()
+    )?;
+    logfmt::write_pairs(&mut text, record, femme, &[])?;
+    write_text(out, &String::from_utf8_lossy(&text))
+}
+
+/// Ends an alert
+pub(crate) const TERMINATOR: &[u8] = b"\n";
+
+/// Send each alert as soon as it's logged
+pub(crate) fn limits(limits: Limits) -> Limits {
+    Limits {
+        max_records: 1,
+        ..limits
+    }
+}
+
+/// Format the payload of an alert with some text, cut down to what chats
+/// take
+fn write_text<W: Write>(out: &mut W, text: &str) -> io::Result<()> {
+    let text = match text.char_indices().nth(MAX_TEXT - 1) {
+        Some((idx, _)) => format!("{}…", &text[..idx]),
+        None => text.to_string(),
+    };
+    write!(out, "{{\"text\":")?;
+    escape::json_str(out, &text)?;
+    write!(out, ",\"content\":")?;
+    escape::json_str(out, &text)?;
+    write!(out, "}}")
+}
+
+/// How many alerts were sent and held back in the current minute
+struct Rate {
+    per_minute: u32,
+    started: Instant,
+    sent: u32,
+    suppressed: u64,
+}
+
+impl Rate {
+    /// Whether to send an alert now, starting a new minute if it's time
+    ///
+    /// Returns the number of alerts held back in the minute before, when a
+    /// new one started.
+    fn admit(&mut self) -> (bool, u64) {
+        let mut suppressed = 0;
+        if self.started.elapsed() >= WINDOW {
+            self.started = Instant::now();
+            self.sent = 0;
+            suppressed = std::mem::take(&mut self.suppressed);
+        }
+        match self.sent < self.per_minute {
+            true => {
+                self.sent += 1;
+                (true, suppressed)
+            }
+            false => {
+                self.suppressed += 1;
+                (false, suppressed)
+            }
+        }
+    }
+}
+
+/// Start POSTing alerts to a webhook
+///
+/// # Arguments
+/// * `url` - The webhook
+/// * `per_minute` - Most alerts sent each minute
+/// * `name` - Name of the destination, for reports
+pub(crate) fn start(url: &str, per_minute: u32, name: String) -> io::Result<Batcher> {
+    let url = http::Url::parse(url)?;
+    let envelope = Envelope {
+        prefix: Vec::new(),
+        suffix: Vec::new(),
+    };
+    let mut rate = Rate {
+        per_minute,
+        started: Instant::now(),
+        sent: 0,
+        suppressed: 0,
+    };
+    let send: batch::Send = Box::new(move |body, _, auth, proxy| {
+        let headers = [("Content-Type", "application/json")];
+        let (admitted, suppressed) = rate.admit();
+        if suppressed > 0 {
+            let mut notice = Vec::new();
+            let text = format!(
+                "{} more alerts were held back in the last minute",
+                suppressed
+            );
+            write_text(&mut notice, &text)?;
+            http::post(&url, &headers, &notice, auth, proxy)?.check(&url)?;
+        }
+        match admitted {
+            true => http::post(&url, &headers, body, auth, proxy)?.check(&url),
+            false => Ok(()),
+        }
+    });
+    Batcher::start(name, envelope, send)
+}
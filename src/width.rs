@@ -0,0 +1,188 @@
+//! Display width of terminal text.
+//!
+//! Terminals render CJK ideographs and most emoji two columns wide, and
+//! combining marks, variation selectors and joiners take no space at all. To
+//! align columns and truncate values without splitting a character we measure
+//! text in grapheme clusters: a base character plus everything rendered on top
+//! of it.
+
+/// Ranges of characters that occupy no columns of their own.
+const ZERO_WIDTH: &[(u32, u32)] = &[
+    (0x0300, 0x036F),
+    (0x0483, 0x0489),
+    (0x0591, 0x05BD),
+    (0x0610, 0x061A),
+    (0x064B, 0x065F),
+    (0x0E31, 0x0E31),
+    (0x0E34, 0x0E3A),
+    (0x0E47, 0x0E4E),
+    (0x1AB0, 0x1AFF),
+    (0x1DC0, 0x1DFF),
+    (0x200B, 0x200F),
+    (0x20D0, 0x20FF),
+    (0x302A, 0x302F),
+    (0x3099, 0x309A),
+    (0xFE00, 0xFE0F),
+    (0xFE20, 0xFE2F),
+    (0x1F3FB, 0x1F3FF),
+    (0xE0020, 0xE007F),
+    (0xE0100, 0xE01EF),
+];
+
+/// Ranges of characters rendered two columns wide.
+const WIDE: &[(u32, u32)] = &[
+    (0x1100, 0x115F),
+    (0x231A, 0x231B),
+    (0x23E9, 0x23EC),
+    (0x25FD, 0x25FE),
+    (0x2614, 0x2615),
+    (0x2648, 0x2653),
+    (0x26A1, 0x26A1),
+    (0x26AA, 0x26AB),
+    (0x26BD, 0x26BE),
+    (0x26C4, 0x26C5),
+    (0x26D4, 0x26D4),
+    (0x26EA, 0x26EA),
+    (0x26F2, 0x26F5),
+    (0x26FA, 0x26FD),
+    (0x2705, 0x2705),
+    (0x270A, 0x270B),
+    (0x2728, 0x2728),
+    (0x274C, 0x274C),
+    (0x2753, 0x2757),
+    (0x2795, 0x2797),
+    (0x27B0, 0x27B0),
+    (0x2B1B, 0x2B1C),
+    (0x2B50, 0x2B55),
+    (0x2E80, 0x303E),
+    (0x3041, 0x33FF),
+    (0x3400, 0x4DBF),
+    (0x4E00, 0x9FFF),
+    (0xA000, 0xA4CF),
+    (0xA960, 0xA97F),
+    (0xAC00, 0xD7A3),
+    (0xF900, 0xFAFF),
+    (0xFE10, 0xFE19),
+    (0xFE30, 0xFE6F),
+    (0xFF00, 0xFF60),
+    (0xFFE0, 0xFFE6),
+    (0x1F004, 0x1F004),
+    (0x1F0CF, 0x1F0CF),
+    (0x1F18E, 0x1F18E),
+    (0x1F191, 0x1F19A),
+    (0x1F200, 0x1F251),
+    (0x1F300, 0x1F64F),
+    (0x1F680, 0x1F6FF),
+    (0x1F7E0, 0x1F7EB),
+    (0x1F90C, 0x1F9FF),
+    (0x1FA70, 0x1FAFF),
+    (0x20000, 0x3FFFD),
+];
+
+const ZWJ: char = '\u{200D}';
+const VS16: char = '\u{FE0F}';
+
+fn in_table(table: &[(u32, u32)], c: char) -> bool {
+    let c = c as u32;
+    table
+        .binary_search_by(|&(lo, hi)| {
+            if hi < c {
+                std::cmp::Ordering::Less
+            } else if lo > c {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+/// Number of columns a single character occupies
+fn char_width(c: char) -> usize {
+    if c.is_control() || in_table(ZERO_WIDTH, c) {
+        0
+    } else if in_table(WIDE, c) || is_regional_indicator(c) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Iterator over `(grapheme, width)` pairs of a string
+struct Graphemes<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Graphemes<'a> {
+    type Item = (&'a str, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chars = self.rest.char_indices();
+        let (_, base) = chars.next()?;
+        let mut width = char_width(base);
+        let mut end = base.len_utf8();
+        let mut joined = false;
+        let mut flag = is_regional_indicator(base);
+
+        for (idx, c) in chars {
+            if joined {
+                // the character after a zero width joiner is drawn on top of
+                // the cluster so far
+                joined = false;
+            } else if c == ZWJ {
+                joined = true;
+            } else if c == VS16 {
+                // emoji presentation selector widens the base character
+                width = width.max(2);
+            } else if flag && is_regional_indicator(c) {
+                flag = false;
+            } else if char_width(c) != 0 || c.is_control() {
+                break;
+            }
+            end = idx + c.len_utf8();
+        }
+
+        let (grapheme, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some((grapheme, width))
+    }
+}
+
+/// Number of terminal columns `text` occupies
+pub(crate) fn display_width(text: &str) -> usize {
+    Graphemes { rest: text }.map(|(_, width)| width).sum()
+}
+
+/// Shorten `text` to at most `max` columns without splitting a grapheme
+///
+/// If the text has to be cut, the last column is used for an ellipsis.
+///
+/// # Arguments
+/// * `text` - Text to shorten
+/// * `max` - Maximum number of columns of the result
+pub(crate) fn truncate(text: &str, max: usize) -> std::borrow::Cow<'_, str> {
+    if display_width(text) <= max {
+        return text.into();
+    }
+
+    let mut used = 0;
+    let mut end = 0;
+    for (grapheme, width) in (Graphemes { rest: text }) {
+        if used + width + 1 > max {
+            break;
+        }
+        used += width;
+        end += grapheme.len();
+    }
+
+    let mut out = String::with_capacity(end + '…'.len_utf8());
+    out.push_str(&text[..end]);
+    if max > 0 {
+        out.push('…');
+    }
+    out.into()
+}
@@ -1,93 +1,1319 @@
 //! Pretty print logs.
 
-use crate::{Femme, Logger};
+use crate::capture::{self, Captured};
+use crate::macros::MARKER_KEY;
+use crate::output::{Destination, Framing, Output};
+use crate::progress::Progress;
+use crate::recorder;
+use crate::stats::{self, SinkCounters, Transition};
+use crate::{
+    bytes, cef, datadog, diagnostics, ecs, escape, gelf, journald, json, logfmt, logstash, loki,
+    stackdriver, syslog, time, webhook,
+};
+use crate::{human, indent, limit, width, Compression, Error, Femme, HttpAuth, Logger, Proxy};
 use log::{kv, Level, Log, Metadata, Record};
-use std::io::{self, StdoutLock, Write};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, IoSlice, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
 
 // ANSI term codes.
-const RESET: &'static str = "\x1b[0m";
-const BOLD: &'static str = "\x1b[1m";
-const RED: &'static str = "\x1b[31m";
-const GREEN: &'static str = "\x1b[32m";
-const YELLOW: &'static str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
 
-/// Format Key/Value pairs that have been passed to a `Log` macro (such as `info!`)
+/// Returns to the start of the line and erases it
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+/// Key whose value colors a pretty-printed record, instead of its level
+const STYLE_KEY: &str = "femme.style";
+
+/// The color a record asks for with `femme.style`, if it names one
+fn style(record: &Record) -> Option<&'static str> {
+    let text = record
+        .key_values()
+        .get(kv::Key::from_str(STYLE_KEY))?
+        .to_string();
+    let mut unescaped = String::new();
+    let name = match escape::Scalar::parse(&text, &mut unescaped) {
+        escape::Scalar::Str(name) | escape::Scalar::Raw(name) | escape::Scalar::Text(name) => name,
+    };
+    let color = match name.to_ascii_lowercase().as_str() {
+        "black" => "\x1b[30m",
+        "red" => RED,
+        "green" => GREEN,
+        "yellow" => YELLOW,
+        "blue" => "\x1b[34m",
+        "magenta" => "\x1b[35m",
+        "cyan" => "\x1b[36m",
+        "white" => "\x1b[37m",
+        "bright_black" => "\x1b[90m",
+        "bright_red" => "\x1b[91m",
+        "bright_green" => "\x1b[92m",
+        "bright_yellow" => "\x1b[93m",
+        "bright_blue" => "\x1b[94m",
+        "bright_magenta" => "\x1b[95m",
+        "bright_cyan" => "\x1b[96m",
+        "bright_white" => "\x1b[97m",
+        _ => return None,
+    };
+    Some(color)
+}
+
+/// How an output sending batches sends them
+struct Sending<'a> {
+    compression: &'a Compression,
+    auth: &'a HttpAuth,
+    proxy: &'a Proxy,
+}
+
+impl<'a> Sending<'a> {
+    /// The logger's settings, or those a sink replaces
+    fn of(femme: &'a Femme, sink: Option<&'a crate::Sink>) -> Self {
+        Sending {
+            compression: sink
+                .and_then(|sink| sink.compression.as_ref())
+                .unwrap_or(&femme.compression),
+            auth: sink
+                .and_then(|sink| sink.auth.as_ref())
+                .unwrap_or(&femme.auth),
+            proxy: &femme.proxy,
+        }
+    }
+}
+
+/// The outputs records are written to: where each one goes, in which format
+/// and for which levels, indexed by `level as usize - 1`, and how batches
+/// are sent
+///
+/// # Arguments
+/// * `femme` - Logger configuration
+fn plan(femme: &Femme) -> Vec<(&Destination, Logger, [bool; 5], Sending<'_>)> {
+    #[allow(unused_mut)]
+    let mut plan = outputs(femme);
+
+    // alerts go to the webhook, whatever the other outputs are
+    if let Some((webhook, level)) = &femme.webhook {
+        let mut alerts = [false; 5];
+        for (idx, alert) in alerts.iter_mut().enumerate() {
+            *alert = idx < *level as usize;
+        }
+        plan.push((webhook, Logger::Webhook, alerts, Sending::of(femme, None)));
+    }
+
+    // error records also go to Sentry, whatever the other outputs are
+    #[cfg(feature = "sentry")]
+    if let Some(sentry) = &femme.sentry {
+        let errors = [true, false, false, false, false];
+        plan.push((sentry, Logger::Sentry, errors, Sending::of(femme, None)));
+    }
+    plan
+}
+
+/// The outputs of the plan besides the webhook and Sentry
+fn outputs(femme: &Femme) -> Vec<(&Destination, Logger, [bool; 5], Sending<'_>)> {
+    if !femme.outputs.is_empty() {
+        return femme
+            .outputs
+            .iter()
+            .map(|output| {
+                let levels = std::array::from_fn(|idx| idx < output.level as usize);
+                let sending = Sending::of(femme, Some(output));
+                (&output.destination, output.logger, levels, sending)
+            })
+            .collect();
+    }
+
+    // logcat and os_log are the destination as well as the format
+    #[cfg(all(target_os = "android", feature = "android"))]
+    if femme.logger == Logger::Logcat {
+        let sending = Sending::of(femme, None);
+        return vec![(&femme.destination, femme.logger, [true; 5], sending)];
+    }
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    if femme.logger == Logger::OsLog {
+        let sending = Sending::of(femme, None);
+        return vec![(&femme.destination, femme.logger, [true; 5], sending)];
+    }
+
+    // levels sharing a destination share a sink, the default one first
+    let mut routes: Vec<(&Destination, [bool; 5])> = vec![(&femme.destination, [false; 5])];
+    for (idx, route) in femme.routes.iter().enumerate() {
+        let destination = route.as_ref().unwrap_or(&femme.destination);
+        match routes.iter_mut().find(|(d, _)| *d == destination) {
+            Some((_, levels)) => levels[idx] = true,
+            None => {
+                let mut levels = [false; 5];
+                levels[idx] = true;
+                routes.push((destination, levels));
+            }
+        }
+    }
+    routes
+        .into_iter()
+        .filter(|(_, levels)| levels.contains(&true))
+        .map(|(destination, levels)| (destination, femme.logger, levels, Sending::of(femme, None)))
+        .collect()
+}
+
+/// Open an output of the plan
+///
+/// # Arguments
+/// * `femme` - Logger configuration
+/// * `destination` - Where the records go
+/// * `logger` - Format of the records
+/// * `sending` - How batches are sent
+/// * `truncate` - Whether to empty a file instead of appending to it
+fn open(
+    femme: &Femme,
+    destination: &Destination,
+    logger: Logger,
+    sending: &Sending,
+    truncate: bool,
+) -> Result<(String, Output), Error> {
+    match logger {
+        #[cfg(all(target_os = "android", feature = "android"))]
+        Logger::Logcat => Ok(("logcat".to_string(), Output::Logcat)),
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        Logger::OsLog => {
+            let default = crate::syslog::Config::default();
+            let subsystem = femme.subsystem.as_deref().or(default.app_name());
+            let logs = crate::oslog::Logs::new(subsystem.unwrap_or("femme"));
+            Ok(("os_log".to_string(), Output::OsLog(logs)))
+        }
+        _ => {
+            let output = destination
+                .open(truncate, femme.create_dirs, femme.rotation)
+                .map_err(|source| Error::Output {
+                    name: destination.name(),
+                    source,
+                })?;
+            let output = match (logger, output) {
+                (Logger::Gelf, Output::Udp(socket, _)) => Output::GelfUdp(socket),
+                (_, Output::Udp(socket, _)) => Output::Udp(socket, femme.datagram),
+                (_, Output::Tcp(mut connection)) => {
+                    if let Some(interval) = femme.dns_refresh {
+                        connection.get_mut().unwrap().refresh_every(interval);
+                    }
+                    Output::Tcp(connection)
+                }
+                #[cfg(feature = "aws")]
+                (Logger::CloudWatch, Output::Batch(batcher)) => {
+                    let limits = crate::cloudwatch::clamp(femme.batching);
+                    let proxy = sending.proxy.clone();
+                    batcher.configure(limits, Compression::None, HttpAuth::None, proxy);
+                    Output::Batch(batcher)
+                }
+                (Logger::Webhook, Output::Batch(batcher)) => {
+                    let limits = webhook::limits(femme.batching);
+                    let auth = sending.auth.clone();
+                    let proxy = sending.proxy.clone();
+                    batcher.configure(limits, Compression::None, auth, proxy);
+                    Output::Batch(batcher)
+                }
+                #[cfg(feature = "kafka")]
+                (Logger::Kafka, Output::Batch(batcher)) => {
+                    let limits = crate::kafka::clamp(femme.batching);
+                    let proxy = sending.proxy.clone();
+                    batcher.configure(limits, Compression::None, HttpAuth::None, proxy);
+                    Output::Batch(batcher)
+                }
+                #[cfg(feature = "mqtt")]
+                (Logger::Mqtt, Output::Batch(batcher)) => {
+                    let proxy = sending.proxy.clone();
+                    batcher.configure(femme.batching, Compression::None, HttpAuth::None, proxy);
+                    Output::Batch(batcher)
+                }
+                #[cfg(feature = "sqlite")]
+                (Logger::Sqlite, Output::Batch(batcher)) => {
+                    let proxy = sending.proxy.clone();
+                    batcher.configure(femme.batching, Compression::None, HttpAuth::None, proxy);
+                    Output::Batch(batcher)
+                }
+                #[cfg(feature = "otlp")]
+                (_, Output::Batch(batcher)) if matches!(destination, Destination::OtlpGrpc(_)) => {
+                    let auth = sending.auth.clone();
+                    let proxy = sending.proxy.clone();
+                    batcher.configure(femme.batching, Compression::None, auth, proxy);
+                    Output::Batch(batcher)
+                }
+                #[cfg(feature = "sentry")]
+                (Logger::Sentry, Output::Batch(batcher)) => {
+                    let limits = crate::sentry::limits(femme.batching);
+                    let compression = sending.compression.clone();
+                    let auth = sending.auth.clone();
+                    let proxy = sending.proxy.clone();
+                    batcher.configure(limits, compression, auth, proxy);
+                    Output::Batch(batcher)
+                }
+                (_, Output::Batch(batcher)) => {
+                    let compression = sending.compression.clone();
+                    let auth = sending.auth.clone();
+                    let proxy = sending.proxy.clone();
+                    batcher.configure(femme.batching, compression, auth, proxy);
+                    Output::Batch(batcher)
+                }
+                (_, output) => output,
+            };
+            Ok((destination.name(), output))
+        }
+    }
+}
+
+/// Prepare the logger's outputs before it is installed
+///
+/// # Arguments
+/// * `femme` - Logger configuration
+pub(crate) fn start(femme: &mut Femme) -> Result<(), Error> {
+    let mut sinks = Vec::new();
+    for (destination, logger, levels, sending) in plan(femme) {
+        let (name, output) = open(femme, destination, logger, &sending, femme.truncate)?;
+        sinks.push(Sink::new(name, output, logger, levels, femme));
+    }
+    femme.sinks = sinks;
+    Ok(())
+}
+
+/// Open each output the logger would write to, and close it again
+///
+/// Files are created but never emptied, whatever `truncate` says.
+///
+/// # Arguments
+/// * `femme` - Logger configuration
+pub(crate) fn verify(femme: &Femme) -> Vec<Error> {
+    plan(femme)
+        .into_iter()
+        .filter_map(|(destination, logger, _, sending)| {
+            open(femme, destination, logger, &sending, false).err()
+        })
+        .collect()
+}
+
+/// Render records the way the logger would write them
+///
+/// # Arguments
+/// * `femme` - Logger configuration
+/// * `records` - Records to render, or none for a few representative ones
+pub(crate) fn preview(femme: &Femme, records: &[Record]) -> String {
+    let logger = femme
+        .outputs
+        .first()
+        .map_or(femme.logger, |sink| sink.logger);
+    let colored = femme.color.enabled(false);
+    let mut buf = Vec::new();
+    let mut render = |record: &Record| {
+        femme.with_fields(record, |record| {
+            // formatting into memory only fails if a value's `Display` does
+            let _ = format(&mut buf, record, femme, logger, colored);
+        })
+    };
+    if !records.is_empty() {
+        records.iter().for_each(&mut render);
+        return String::from_utf8_lossy(&buf).into_owned();
+    }
+
+    let request: &[(&str, kv::Value)] = &[
+        ("http.method", "GET".into()),
+        ("http.path", "/users".into()),
+        ("status", 200.into()),
+        ("elapsed_ms", 12.into()),
+        ("response_bytes", 5120.into()),
+    ];
+    let query: &[(&str, kv::Value)] = &[("rows", 42.into())];
+    let login: &[(&str, kv::Value)] = &[("user", "alice".into())];
+    let samples = [
+        (Level::Info, "app::server", "Request handled", request),
+        (Level::Debug, "app::db", "Query finished", query),
+        (
+            Level::Warn,
+            "app::auth",
+            "Unauthorized access attempt",
+            login,
+        ),
+        (Level::Error, "app::db", "Connection refused", &[]),
+    ];
+    for (level, target, message, kvs) in samples {
+        render(
+            &Record::builder()
+                .level(level)
+                .target(target)
+                .args(format_args!("{}", message))
+                .key_values(&kvs)
+                .build(),
+        );
+    }
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+/// Target of the records femme logs about itself
+const INTERNAL_TARGET: &str = "femme";
+
+/// Account for a write and announce changes in the sink's health
+///
+/// When a sink starts failing, a `disconnected` record is logged through the
+/// other sinks. Once it recovers, a `recovered` record says how many records
+/// were lost in between, so gaps in the output can be explained.
+///
+/// # Arguments
+/// * `counters` - Counters of the sink that was written to
+/// * `res` - Outcome of the write
+fn account(counters: &SinkCounters, res: &io::Result<()>) {
+    if let Err(err) = res {
+        diagnostics::report(format_args!(
+            "failed to write to {}: {}",
+            counters.name(),
+            err
+        ));
+    }
+
+    let (level, event, lost) = match counters.record(res) {
+        None => return,
+        Some(Transition::Failed) => (Level::Warn, "disconnected", None),
+        Some(Transition::Recovered { lost }) => (Level::Info, "recovered", Some(lost)),
+    };
+
+    let name = counters.name();
+    let error = res.as_ref().err().map(|err| err.to_string());
+    let fields: &[(&str, kv::Value)] = &[("femme.sink", name.into()), ("event", event.into())];
+    let lost = lost.map(|lost| ("lost", kv::Value::from(lost)));
+    let error = error
+        .as_deref()
+        .map(|error| ("error", kv::Value::from(error)));
+    let sources: &[&dyn kv::Source] = &[&fields, &lost, &error];
+
+    log::logger().log(
+        &Record::builder()
+            .level(level)
+            .target(INTERNAL_TARGET)
+            .args(format_args!("sink {} {}", name, event))
+            .key_values(&sources)
+            .build(),
+    );
+}
+
+/// An output records are written to
+///
+/// Each sink keeps its own counters and, when configured with a queue, its own
+/// writer thread. A sink that fails or stalls only affects its own records.
+pub(crate) struct Sink {
+    counters: Arc<SinkCounters>,
+    output: Arc<Output>,
+    queue: Option<SyncSender<Message>>,
+
+    /// Whether records of each level, indexed by `level as usize - 1`, are
+    /// written to the sink
+    levels: [bool; 5],
+
+    /// Format of the records
+    logger: Logger,
+
+    /// Whether pretty-printed records are colored
+    colored: bool,
+
+    /// Whether progress is drawn as a line updated in place, for pretty
+    /// output to a terminal
+    live: bool,
+
+    /// Whether the last thing written is a progress line still in place,
+    /// which the next write replaces
+    line_open: AtomicBool,
+
+    /// How records are delimited, for stream outputs
+    framing: Framing,
+}
+
+/// Work handed to a sink's writer thread
+enum Message {
+    /// A formatted record
+    Record(Vec<u8>),
+
+    /// Flush the output, then acknowledge
+    Flush(SyncSender<()>),
+}
+
+/// Bytes of queued records coalesced into a single write during a burst
+const BURST_BYTES: usize = 64 * 1024;
+
+/// Records coalesced into a single write during a burst, within the number
+/// of buffers a vectored write accepts on common platforms
+const BURST_RECORDS: usize = 1024;
+
+/// Write queued records until every sender is gone
+///
+/// Flushing adapts to the load: while the queue is empty each record is
+/// written as soon as it arrives, so interactive use feels immediate. During
+/// a burst the records that piled up are written together, in one vectored
+/// write of up to `BURST_BYTES` or `BURST_RECORDS`. Datagram outputs get one
+/// write per record, since a datagram can only hold one.
+///
+/// # Arguments
+/// * `rx` - The sink's queue
+/// * `counters` - The sink's counters
+/// * `output` - Where the records go
+fn run_writer(rx: mpsc::Receiver<Message>, counters: &SinkCounters, output: &Output) {
+    let mut pending: Vec<Vec<u8>> = Vec::new();
+    let mut bytes = 0;
+
+    let write = |pending: &mut Vec<Vec<u8>>| {
+        if pending.is_empty() {
+            return;
+        }
+        if output.is_datagram() {
+            for buf in pending.drain(..) {
+                let res = output.write_all_vectored(&mut [IoSlice::new(&buf)]);
+                account(counters, &res);
+            }
+            return;
+        }
+        let mut slices: Vec<_> = pending.iter().map(|buf| IoSlice::new(buf)).collect();
+        let res = output.write_all_vectored(&mut slices);
+        for _ in pending.drain(..) {
+            account(counters, &res);
+        }
+    };
+
+    while let Ok(message) = rx.recv() {
+        let mut next = Some(message);
+        while let Some(message) = next.take() {
+            match message {
+                Message::Record(buf) => {
+                    bytes += buf.len();
+                    pending.push(buf);
+                    if bytes >= BURST_BYTES || pending.len() >= BURST_RECORDS {
+                        write(&mut pending);
+                        bytes = 0;
+                    }
+                }
+                Message::Flush(ack) => {
+                    let start = Instant::now();
+                    write(&mut pending);
+                    bytes = 0;
+                    let _ = output.flush();
+                    counters.flushed(start.elapsed());
+                    let _ = ack.send(());
+                }
+            }
+            // keep coalescing while records are waiting
+            next = rx.try_recv().ok();
+        }
+
+        // the queue ran dry
+        write(&mut pending);
+        bytes = 0;
+    }
+}
+
+impl Sink {
+    /// Create a sink
+    ///
+    /// # Arguments
+    /// * `name` - Name reported by `femme::stats()`
+    /// * `output` - Where the records go
+    /// * `logger` - Format of the records
+    /// * `levels` - Whether records of each level, indexed by
+    ///   `level as usize - 1`, are written to the sink
+    /// * `femme` - Logger configuration
+    fn new(
+        name: impl Into<Cow<'static, str>>,
+        output: Output,
+        logger: Logger,
+        levels: [bool; 5],
+        femme: &Femme,
+    ) -> Self {
+        let counters = SinkCounters::register(name);
+        let colored = femme.color.enabled(output.is_terminal());
+        let live = logger == Logger::Pretty && output.is_terminal();
+        let framing = match output.is_stream() {
+            true => femme.framing,
+            false => Framing::default(),
+        };
+        let output = Arc::new(output);
+
+        let queue = femme.queue.and_then(|capacity| {
+            let (tx, rx) = mpsc::sync_channel(capacity);
+            let thread_counters = counters.clone();
+            let thread_output = output.clone();
+            let thread_name = match *output {
+                Output::Stdout => "femme-stdout",
+                Output::Stderr => "femme-stderr",
+                Output::File(_) => "femme-file",
+                Output::Writer(_) => "femme-writer",
+                Output::Udp(..) | Output::GelfUdp(_) => "femme-udp",
+                Output::Tcp(_) => "femme-tcp",
+                Output::Connector(_) => "femme-connector",
+                #[cfg(unix)]
+                Output::UnixDatagram(_) => "femme-unix",
+                #[cfg(unix)]
+                Output::UnixStream(_) => "femme-unix",
+                #[cfg(windows)]
+                Output::NamedPipe(_) => "femme-pipe",
+                Output::Failover(_) => "femme-failover",
+                Output::Batch(_) => "femme-batch",
+                #[cfg(any(unix, windows))]
+                Output::Inherited(_) => "femme-fd",
+                #[cfg(all(target_os = "android", feature = "android"))]
+                Output::Logcat => "femme-logcat",
+                #[cfg(any(target_os = "macos", target_os = "ios"))]
+                Output::OsLog(_) => "femme-os-log",
+            };
+            thread::Builder::new()
+                .name(thread_name.into())
+                .spawn(move || run_writer(rx, &thread_counters, &thread_output))
+                .ok()
+                .map(|_| tx)
+        });
+        Sink {
+            counters,
+            output,
+            queue,
+            levels,
+            logger,
+            colored,
+            live,
+            line_open: AtomicBool::new(false),
+            framing,
+        }
+    }
+
+    /// Whether records of a level are written to the sink
+    fn accepts(&self, level: Level) -> bool {
+        self.levels[level as usize - 1]
+    }
+
+    /// Format and write a record
+    ///
+    /// # Arguments
+    /// * `record` - Record to write
+    /// * `femme` - Logger configuration
+    fn write(&self, record: &Record, femme: &Femme) {
+        if self.is_about_own_outage(record) {
+            return;
+        }
+        match self.logger {
+            Logger::NDJson => json::split_lines(record, femme, |line| self.write_one(line, femme)),
+            _ => self.write_one(record, femme),
+        }
+    }
+
+    /// Format and write a record as a single one
+    fn write_one(&self, record: &Record, femme: &Femme) {
+        match &self.queue {
+            None => {
+                let res = with_buffer(|buf| {
+                    let segments = self.format(buf, record, femme)?;
+                    write_segments(&self.output, buf, &segments)
+                });
+                account(&self.counters, &res);
+            }
+            Some(queue) => {
+                let mut buf = Vec::new();
+                match self.format(&mut buf, record, femme) {
+                    Ok(_) => {
+                        if queue.try_send(Message::Record(buf)).is_err() {
+                            self.counters.dropped(record.level());
+                        }
+                    }
+                    Err(err) => account(&self.counters, &Err(err)),
+                }
+            }
+        }
+    }
+
+    /// Format a record for this sink, drawing progress in place if it's live
+    ///
+    /// # Arguments
+    /// * `buf` - Empty buffer to format into
+    /// * `record` - Record to write
+    /// * `femme` - Logger configuration
+    fn format(&self, buf: &mut Vec<u8>, record: &Record, femme: &Femme) -> io::Result<Segments> {
+        let progress = Progress::parse(record).filter(|_| self.live);
+        let open = progress
+            .as_ref()
+            .is_some_and(|progress| !progress.finished());
+        let replace = self.line_open.swap(open, Ordering::Relaxed);
+
+        // the line in place is cleared, then written over
+        if replace {
+            buf.extend_from_slice(CLEAR_LINE.as_bytes());
+        }
+        let mut ends = match progress {
+            Some(progress) => {
+                write_progress_line(buf, &progress, self.colored)?;
+                let end = buf.len();
+                if progress.finished() {
+                    buf.push(b'\n');
+                }
+                [end; 3]
+            }
+            None => {
+                let start = buf.len();
+                let mut segments = format(buf, record, femme, self.logger, self.colored)?;
+                if let Some(max) = femme.max_record_size.filter(|max| buf.len() - start > *max) {
+                    buf.truncate(start);
+                    let mut scratch = Vec::new();
+                    let measure = |record: &Record| {
+                        scratch.clear();
+                        let _ = format(&mut scratch, record, femme, self.logger, self.colored);
+                        scratch.len()
+                    };
+                    segments = limit::fit(record, max, measure, |record| {
+                        format(buf, record, femme, self.logger, self.colored)
+                    })?;
+                }
+                [segments.header, segments.message, segments.pairs].map(|end| start + end)
+            }
+        };
+
+        // a record may lose its newline to the framing, but no more
+        let framed = self.framing.frame(buf);
+        for end in &mut ends {
+            *end = (*end + framed).min(buf.len());
+        }
+        Ok(Segments {
+            header: ends[0],
+            message: ends[1],
+            pairs: ends[2],
+        })
+    }
+
+    /// Whether `record` announces that this very sink is failing
+    ///
+    /// Writing it would only fail again.
+    fn is_about_own_outage(&self, record: &Record) -> bool {
+        if record.target() != INTERNAL_TARGET {
+            return false;
+        }
+        let kvs = record.key_values();
+        let is = |key: &str, value: &str| {
+            kvs.get(key.into())
+                .is_some_and(|v| v.to_string() == format!("{:?}", value))
+        };
+        is("event", "disconnected") && is("femme.sink", self.counters.name())
+    }
+
+    /// Flush the output, waiting for queued records to be written
+    fn flush(&self) {
+        match &self.queue {
+            None => {
+                let start = Instant::now();
+                let res = self.output.flush();
+                self.counters.flushed(start.elapsed());
+                if res.is_err() {
+                    account(&self.counters, &res);
+                }
+            }
+            Some(queue) => {
+                let (ack, done) = mpsc::sync_channel(1);
+                if queue.send(Message::Flush(ack)).is_ok() {
+                    let _ = done.recv();
+                }
+            }
+        }
+    }
+}
+
+/// Where the parts of a formatted record end in its buffer
+///
+/// The terminator runs from the end of the pairs to the end of the buffer.
+struct Segments {
+    header: usize,
+    message: usize,
+    pairs: usize,
+}
+
+/// Format a record in a built-in format, uncolored, see `femme::Pretty`
+///
+/// # Arguments
+/// * `buf` - Buffer to format into
+/// * `record` - Record to write
+/// * `femme` - Logger configuration
+/// * `logger` - Format to write
+pub(crate) fn format_plain(
+    buf: &mut Vec<u8>,
+    record: &Record,
+    femme: &Femme,
+    logger: Logger,
+) -> io::Result<()> {
+    format(buf, record, femme, logger, false).map(|_| ())
+}
+
+/// Format a record in the configured format
 ///
 /// # Arguments
-/// * `handle` - Exclusive handle to `stdout`
+/// * `buf` - Buffer to format into
 /// * `record` - Record to write
-fn format_kv_pairs<'b>(mut handle: &mut StdoutLock<'b>, record: &Record) {
-    struct Visitor<'a, 'b> {
-        stdout: &'a mut StdoutLock<'b>,
+/// * `femme` - Logger configuration
+/// * `logger` - Format to write
+/// * `colored` - Whether to color pretty-printed output
+fn format(
+    buf: &mut Vec<u8>,
+    record: &Record,
+    femme: &Femme,
+    logger: Logger,
+    colored: bool,
+) -> io::Result<Segments> {
+    let start = buf.len();
+    let mut ends = [0; 3];
+    match logger {
+        Logger::Pretty if is_marker(record) => {
+            write_marker(buf, record, colored)?;
+            ends = [buf.len(); 3];
+            buf.push(b'\n');
+        }
+        Logger::Pretty => {
+            write_pretty_header(buf, record, colored)?;
+            ends[0] = buf.len();
+            match style(record).filter(|_| colored) {
+                Some(color) => {
+                    write!(buf, " {}", color)?;
+                    escape::visible(buf, record.args())?;
+                    write!(buf, "{}", RESET)?;
+                }
+                None => escape::visible(buf, format_args!(" {}", record.args()))?,
+            }
+            ends[1] = buf.len();
+            format_kv_pairs(buf, record, femme, colored)?;
+            ends[2] = buf.len();
+            buf.push(b'\n');
+        }
+        Logger::NDJson => {
+            json::write_header(buf, record, time::now(), femme)?;
+            ends[0] = buf.len();
+            json::write_message(buf, record, femme)?;
+            ends[1] = buf.len();
+            json::write_pairs(buf, record, femme, json::reserved(femme))?;
+            ends[2] = buf.len();
+            buf.extend_from_slice(json::TERMINATOR);
+        }
+        Logger::Logstash => {
+            logstash::write_header(buf, record, time::now(), femme)?;
+            ends[0] = buf.len();
+            logstash::write_message(buf, record, femme)?;
+            ends[1] = buf.len();
+            json::write_pairs(buf, record, femme, &logstash::RESERVED)?;
+            ends[2] = buf.len();
+            buf.extend_from_slice(json::TERMINATOR);
+        }
+        Logger::Datadog => {
+            datadog::write_header(buf, record, time::now(), femme)?;
+            ends[0] = buf.len();
+            // `message` is named as by Logstash
+            logstash::write_message(buf, record, femme)?;
+            ends[1] = buf.len();
+            json::write_pairs(buf, record, femme, &datadog::RESERVED)?;
+            ends[2] = buf.len();
+            buf.extend_from_slice(json::TERMINATOR);
+        }
+        #[cfg(feature = "aws")]
+        Logger::CloudWatch => {
+            crate::cloudwatch::write_header(buf, time::now())?;
+            ends[0] = buf.len();
+            let mut line = Vec::new();
+            format(&mut line, record, femme, Logger::NDJson, false)?;
+            crate::cloudwatch::write_message(buf, &line)?;
+            ends[1] = buf.len();
+            ends[2] = buf.len();
+            buf.extend_from_slice(crate::cloudwatch::TERMINATOR);
+        }
+        Logger::Loki => {
+            loki::write_header(buf, record, time::now(), &femme.loki)?;
+            ends[0] = buf.len();
+            loki::write_line(buf, record, femme)?;
+            ends[1] = buf.len();
+            ends[2] = buf.len();
+            buf.extend_from_slice(loki::TERMINATOR);
+        }
+        Logger::Webhook => {
+            webhook::write_payload(buf, record, femme)?;
+            ends[0] = buf.len();
+            ends[1] = buf.len();
+            ends[2] = buf.len();
+            buf.extend_from_slice(webhook::TERMINATOR);
+        }
+        #[cfg(feature = "kafka")]
+        Logger::Kafka => {
+            crate::kafka::write_header(buf, record, time::now(), &femme.kafka_key)?;
+            ends[0] = buf.len();
+            let mut line = Vec::new();
+            format(&mut line, record, femme, Logger::NDJson, false)?;
+            crate::kafka::write_value(buf, &line)?;
+            ends[1] = buf.len();
+            ends[2] = buf.len();
+            buf.extend_from_slice(crate::kafka::TERMINATOR);
+        }
+        #[cfg(feature = "mqtt")]
+        Logger::Mqtt => {
+            crate::mqtt::write_header(buf, record, femme.mqtt)?;
+            ends[0] = buf.len();
+            let mut line = Vec::new();
+            format(&mut line, record, femme, Logger::NDJson, false)?;
+            crate::mqtt::write_payload(buf, &line)?;
+            ends[1] = buf.len();
+            ends[2] = buf.len();
+            buf.extend_from_slice(crate::mqtt::TERMINATOR);
+        }
+        #[cfg(feature = "sqlite")]
+        Logger::Sqlite => {
+            crate::sqlite::write_header(buf, record, time::now())?;
+            ends[0] = buf.len();
+            crate::sqlite::write_message(buf, record)?;
+            ends[1] = buf.len();
+            crate::sqlite::write_fields(buf, record, femme)?;
+            ends[2] = buf.len();
+            buf.extend_from_slice(crate::sqlite::TERMINATOR);
+        }
+        #[cfg(feature = "otlp")]
+        Logger::Otlp => {
+            crate::otlp::write_header(buf, record, time::now())?;
+            ends[0] = buf.len();
+            escape::json_str(buf, record.args())?;
+            ends[1] = buf.len();
+            crate::otlp::write_attributes(buf, record)?;
+            ends[2] = buf.len();
+            buf.extend_from_slice(json::TERMINATOR);
+        }
+        #[cfg(feature = "sentry")]
+        Logger::Sentry => {
+            crate::sentry::write_header(buf, record, time::now())?;
+            ends[0] = buf.len();
+            escape::json_str(buf, record.args())?;
+            ends[1] = buf.len();
+            crate::sentry::write_extra(buf, record)?;
+            ends[2] = buf.len();
+            buf.extend_from_slice(crate::sentry::TERMINATOR);
+        }
+        Logger::Cef => {
+            cef::write_header(buf, record, &femme.cef, femme.syslog.app_name())?;
+            ends[0] = buf.len();
+            cef::write_message(buf, record)?;
+            ends[1] = buf.len();
+            cef::write_extensions(buf, record, time::now())?;
+            ends[2] = buf.len();
+            buf.push(b'\n');
+        }
+        Logger::Logfmt => {
+            logfmt::write_header(buf, record, time::now(), femme)?;
+            ends[0] = buf.len();
+            logfmt::write_message(buf, record, femme)?;
+            ends[1] = buf.len();
+            logfmt::write_pairs(buf, record, femme, &logfmt::RESERVED)?;
+            ends[2] = buf.len();
+            buf.push(b'\n');
+        }
+        Logger::Ecs => {
+            ecs::write_header(buf, record, time::now())?;
+            ends[0] = buf.len();
+            escape::json_str(buf, record.args())?;
+            ends[1] = buf.len();
+            ecs::write_labels(buf, record)?;
+            ends[2] = buf.len();
+            buf.extend_from_slice(json::TERMINATOR);
+        }
+        Logger::Stackdriver => {
+            stackdriver::write_header(buf, record, time::now())?;
+            ends[0] = buf.len();
+            escape::json_str(buf, record.args())?;
+            ends[1] = buf.len();
+            stackdriver::write_labels(buf, record)?;
+            ends[2] = buf.len();
+            buf.extend_from_slice(json::TERMINATOR);
+        }
+        Logger::Syslog => {
+            // the structured data comes before the message
+            syslog::write_header(buf, record, time::now(), &femme.syslog)?;
+            syslog::write_structured_data(buf, record)?;
+            ends[0] = buf.len();
+            escape::single_line(buf, format_args!(" {}", record.args()))?;
+            ends[1] = buf.len();
+            ends[2] = buf.len();
+            buf.push(b'\n');
+        }
+        Logger::Journald => {
+            journald::write_entry(buf, record, &femme.syslog)?;
+            ends = [buf.len(); 3];
+        }
+        Logger::Gelf => {
+            gelf::write_message(buf, record, time::now(), &femme.syslog)?;
+            ends = [buf.len(); 3];
+            buf.extend_from_slice(gelf::TERMINATOR);
+        }
+        #[cfg(all(target_os = "android", feature = "android"))]
+        Logger::Logcat => {
+            crate::android::write_frame(buf, record)?;
+            ends = [buf.len(); 3];
+        }
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        Logger::OsLog => {
+            crate::oslog::write_frame(buf, record)?;
+            ends = [buf.len(); 3];
+        }
+        Logger::Custom => {
+            if let Some(layout) = &femme.format {
+                layout.write(buf, record)?;
+            }
+            // the newline the record ends in, if any, is its terminator
+            let end = match buf.len() > start && buf.ends_with(b"\n") {
+                true => buf.len() - 1,
+                false => buf.len(),
+            };
+            ends = [end; 3];
+        }
+    }
+    if logger == Logger::Pretty {
+        indent_lines(buf, start, &mut ends, indent::width());
+    }
+
+    Ok(Segments {
+        header: ends[0] - start,
+        message: ends[1] - start,
+        pairs: ends[2] - start,
+    })
+}
+
+/// Indent the lines of a record formatted from `start`, but not empty ones,
+/// moving the ends of its segments along
+fn indent_lines(buf: &mut Vec<u8>, start: usize, ends: &mut [usize; 3], width: usize) {
+    if width == 0 {
+        return;
+    }
+    let record = buf.split_off(start);
+    let mut inserted = [0; 3];
+    for (idx, &byte) in record.iter().enumerate() {
+        let line_start = idx == 0 || record[idx - 1] == b'\n';
+        if line_start && byte != b'\n' {
+            buf.resize(buf.len() + width, b' ');
+            for (end, inserted) in ends.iter().zip(&mut inserted) {
+                if start + idx < *end {
+                    *inserted += width;
+                }
+            }
+        }
+        buf.push(byte);
+    }
+    for (end, inserted) in ends.iter_mut().zip(inserted) {
+        *end += inserted;
     }
+}
+
+/// Run `f` with a formatting buffer reused across records on this thread
+fn with_buffer<R>(f: impl FnOnce(&mut Vec<u8>) -> R) -> R {
+    /// Capacity past which the buffer is shrunk again after a large record
+    const CAPACITY: usize = 16 * 1024;
+
+    thread_local! {
+        static BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    }
+
+    let mut f = Some(f);
+    let res = BUFFER.try_with(|buf| {
+        // taken when a value's `Display` logs; such a record gets a buffer
+        // of its own below
+        let mut buf = buf.try_borrow_mut().ok()?;
+        buf.clear();
+        let res = f.take().map(|f| f(&mut buf));
+        if buf.capacity() > CAPACITY {
+            buf.clear();
+            buf.shrink_to(CAPACITY);
+        }
+        res
+    });
+    match (res, f) {
+        (Ok(Some(res)), _) => res,
+        (_, Some(f)) => f(&mut Vec::new()),
+        (_, None) => unreachable!("`f` ran but its result was lost"),
+    }
+}
+
+/// Write a formatted record's header, message, pairs and terminator with as
+/// few `write_vectored` calls as the output allows, usually a single one
+///
+/// # Arguments
+/// * `output` - Where the record goes
+/// * `buf` - The formatted record
+/// * `segments` - Where its parts end
+fn write_segments(output: &Output, buf: &[u8], segments: &Segments) -> io::Result<()> {
+    let (head, terminator) = buf.split_at(segments.pairs);
+    let (head, pairs) = head.split_at(segments.message);
+    let (header, message) = head.split_at(segments.header);
+
+    output.write_all_vectored(&mut [
+        IoSlice::new(header),
+        IoSlice::new(message),
+        IoSlice::new(pairs),
+        IoSlice::new(terminator),
+    ])
+}
+
+/// Format Key/Value pairs that have been passed to a `Log` macro (such as `info!`)
+///
+/// Keys are padded to the width of the widest key so the values line up. Keys
+/// sharing a dotted prefix with another key, such as `http.method` and
+/// `http.status`, are grouped in an indented `http:` block, the way they would
+/// nest in structured output.
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Record to write
+/// * `femme` - Logger configuration
+/// * `colored` - Whether to color the keys
+fn format_kv_pairs<W: Write>(
+    out: &mut W,
+    record: &Record,
+    femme: &Femme,
+    colored: bool,
+) -> io::Result<()> {
+    struct Pairs<'kvs>(Vec<(kv::Key<'kvs>, kv::Value<'kvs>)>);
 
-    impl<'kvs, 'a, 'b> kv::Visitor<'kvs> for Visitor<'a, 'b> {
+    impl<'kvs> kv::Visitor<'kvs> for Pairs<'kvs> {
         fn visit_pair(
             &mut self,
             key: kv::Key<'kvs>,
             val: kv::Value<'kvs>,
         ) -> Result<(), kv::Error> {
-            write!(self.stdout, "\n    {}{}{} {}", BOLD, key, RESET, val).unwrap();
+            if key.as_str() != STYLE_KEY {
+                self.0.push((key, val));
+            }
             Ok(())
         }
     }
 
-    let mut visitor = Visitor {
-        stdout: &mut handle,
+    let mut pairs = Pairs(Vec::new());
+    record
+        .key_values()
+        .visit(&mut pairs)
+        .map_err(crate::error::kv_to_io)?;
+    let pairs = pairs.0;
+
+    fn prefix(key: &str) -> Option<&str> {
+        key.split_once('.').map(|(prefix, _)| prefix)
+    }
+
+    // the prefix each key is grouped under, if another key shares it
+    let prefixes: Vec<_> = pairs.iter().map(|(k, _)| prefix(k.as_str())).collect();
+    let groups: Vec<_> = prefixes
+        .iter()
+        .map(|&p| p.filter(|_| prefixes.iter().filter(|&&q| q == p).count() > 1))
+        .collect();
+
+    let widest = |labels: &mut dyn Iterator<Item = &str>| {
+        labels.map(width::display_width).max().unwrap_or(0)
     };
-    record.key_values().visit(&mut visitor).unwrap();
+    let key_width = widest(
+        &mut pairs
+            .iter()
+            .zip(&groups)
+            .filter(|(_, group)| group.is_none())
+            .map(|((k, _), _)| k.as_str()),
+    );
+
+    for (idx, ((key, val), group)) in pairs.iter().zip(&groups).enumerate() {
+        let prefix = match group {
+            None => {
+                write_pair(out, femme, colored, None, key_width, key.as_str(), val)?;
+                continue;
+            }
+            Some(_) if groups[..idx].contains(group) => continue,
+            Some(prefix) => *prefix,
+        };
+
+        write!(out, "\n    ")?;
+        if colored {
+            write!(out, "{}", BOLD)?;
+        }
+        escape::visible(out, prefix)?;
+        if colored {
+            write!(out, "{}", RESET)?;
+        }
+        write!(out, ":")?;
+
+        let members: Vec<_> = pairs
+            .iter()
+            .zip(&groups)
+            .filter(|(_, g)| *g == group)
+            .map(|((k, v), _)| (k.as_str(), &k.as_str()[prefix.len() + 1..], v))
+            .collect();
+        let member_width = widest(&mut members.iter().map(|(_, label, _)| *label));
+        for (key, _, val) in members {
+            write_pair(out, femme, colored, Some(prefix), member_width, key, val)?;
+        }
+    }
+    Ok(())
 }
 
-/// Uses a pretty-print format to print to stdout
+/// Format a single pretty-printed Key/Value pair on a line of its own
 ///
 /// # Arguments
-/// * `handle` - Exclusive handle to `stdout`
-/// * `record` - Record to write
-fn write_pretty(handle: &mut StdoutLock, record: &Record) {
-    // Format lines
-    let msg = record.target();
-    match record.level() {
-        Level::Trace | Level::Debug | Level::Info => {
-            write!(handle, "{}{}{}{}", GREEN, BOLD, msg, RESET).unwrap();
+/// * `out` - Writer to format into
+/// * `femme` - Logger configuration
+/// * `colored` - Whether to color the key
+/// * `group` - Prefix of the group the pair is indented under, if any; the
+///   key is displayed without it
+/// * `key_width` - Width to pad the displayed key to
+/// * `key` - The full key, which decides how the value is humanized
+/// * `val` - Value to format
+fn write_pair<W: Write>(
+    out: &mut W,
+    femme: &Femme,
+    colored: bool,
+    group: Option<&str>,
+    key_width: usize,
+    key: &str,
+    val: &kv::Value,
+) -> io::Result<()> {
+    let (indent, label) = match group {
+        None => ("    ", key),
+        Some(prefix) => ("        ", &key[prefix.len() + 1..]),
+    };
+    let pad = key_width - width::display_width(label);
+    write!(out, "\n{}", indent)?;
+    if colored {
+        write!(out, "{}", BOLD)?;
+    }
+    escape::visible(out, label)?;
+    if colored {
+        write!(out, "{}", RESET)?;
+    }
+    write!(out, "{:pad$} ", "")?;
+
+    let mut text = String::new();
+    let captured =
+        capture::capture(&mut text, val).map_err(|_| io::Error::other("failed to format value"))?;
+    let (bytes, chain) = match captured {
+        Some(Captured::Bytes(bytes)) => (Some(bytes), None),
+        Some(Captured::Report { chain, .. }) => (None, Some(chain)),
+        Some(Captured::Names(_)) | None => (None, None),
+    };
+    if let (Some(bytes), Some(max)) = (&bytes, femme.hexdump) {
+        let mut dump = String::new();
+        let rows = format!("{}    ", indent);
+        bytes::write_dump(&mut dump, bytes, max, &rows).map_err(io::Error::other)?;
+        return out.write_all(dump.as_bytes());
+    }
+    let bytes = bytes.filter(|bytes| std::str::from_utf8(bytes).is_err());
+    let preview = bytes.as_deref().map(bytes::Preview);
+    let humanized = match femme.humanize {
+        true => human::humanize(key, val, &text),
+        false => None,
+    };
+    let debug = format_args!("{:#?}", val);
+    let val: &dyn fmt::Display = match (&preview, &humanized) {
+        (Some(preview), _) => preview,
+        (None, Some(humanized)) => humanized,
+        (None, None) if femme.debug_values => &debug,
+        (None, None) => val,
+    };
+
+    match femme.max_value_width {
+        Some(max) => {
+            let mut visible = Vec::new();
+            escape::visible(&mut visible, val)?;
+            let val = String::from_utf8_lossy(&visible);
+            write!(out, "{}", width::truncate(&val, max))?;
         }
-        Level::Warn => write!(handle, "{}{}{}{}", YELLOW, BOLD, msg, RESET).unwrap(),
-        Level::Error => write!(handle, "{}{}{}{}", RED, BOLD, msg, RESET).unwrap(),
+        None => escape::visible(out, val)?,
     }
-    write!(handle, " {}", record.args()).unwrap();
 
-    // Format Key/Value pairs
-    format_kv_pairs(handle, record);
-    writeln!(handle, "").unwrap();
+    // the errors a `Report` was caused by, each on a line of its own
+    for source in chain.iter().flatten() {
+        write!(out, "\n{}    caused by: ", indent)?;
+        escape::visible(out, source)?;
+    }
+    Ok(())
 }
 
-/// Uses a pretty-print format to print to stdout using the
-/// Newline Delimited JSON format
+/// Format the target a pretty-printed record starts with, colored by level
 ///
 /// # Arguments
-/// * `handle` - Exclusive handle to `stdout`
+/// * `out` - Writer to format into
 /// * `record` - Record to write
-fn write_ndjson(handle: &mut StdoutLock, record: &Record) {
-    fn get_level(level: log::Level) -> u8 {
-        use log::Level::*;
-        match level {
-            Trace => 10,
-            Debug => 20,
-            Info => 30,
-            Warn => 40,
-            Error => 50,
-        }
+/// * `colored` - Whether to color the target
+fn write_pretty_header<W: Write>(out: &mut W, record: &Record, colored: bool) -> io::Result<()> {
+    let target = record.target();
+    if colored {
+        let color = style(record).unwrap_or(match record.level() {
+            Level::Trace | Level::Debug | Level::Info => GREEN,
+            Level::Warn => YELLOW,
+            Level::Error => RED,
+        });
+        write!(out, "{}{}", color, BOLD)?;
+        escape::visible(out, target)?;
+        write!(out, "{}", RESET)
+    } else {
+        escape::visible(out, target)
     }
+}
 
-    write!(handle, "{}", '{').unwrap();
-    write!(handle, "\"level\":{}", get_level(record.level())).unwrap();
+/// Format progress as a bar, drawn over the previous one
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `progress` - Progress to draw
+/// * `colored` - Whether to color the operation
+fn write_progress_line<W: Write>(
+    out: &mut W,
+    progress: &Progress,
+    colored: bool,
+) -> io::Result<()> {
+    /// Columns of the bar
+    const BAR: usize = 24;
 
-    let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_millis();
+    let filled = (progress.pct.min(100) as usize * BAR) / 100;
+    if colored {
+        write!(out, "{}{}", GREEN, BOLD)?;
+    }
+    escape::single_line(out, &progress.operation)?;
+    if colored {
+        write!(out, "{}", RESET)?;
+    }
+    write!(
+        out,
+        " [{}{}] {:>3}% {}/{}",
+        "#".repeat(filled),
+        "-".repeat(BAR - filled),
+        progress.pct,
+        progress.done,
+        progress.total
+    )
+}
+
+/// Whether a record is a marker logged with `femme::marker`
+fn is_marker(record: &Record) -> bool {
+    record
+        .key_values()
+        .get(kv::Key::from_str(MARKER_KEY))
+        .is_some_and(|marker| marker.to_string() == "true")
+}
+
+/// Format a marker as a rule across the line, with its text near the start
+///
+/// # Arguments
+/// * `out` - Writer to format into
+/// * `record` - Marker to write
+/// * `colored` - Whether to color the marker
+fn write_marker<W: Write>(out: &mut W, record: &Record, colored: bool) -> io::Result<()> {
+    /// Columns the rule spans, unless the text is longer
+    const WIDTH: usize = 72;
 
-    write!(handle, ",\"time\":{}", now).unwrap();
-    write!(handle, ",\"msg\":\"{}\"", record.args()).unwrap();
+    let mut text = Vec::new();
+    escape::single_line(&mut text, record.args())?;
+    let text = String::from_utf8_lossy(&text);
+    let rest = WIDTH.saturating_sub(width::display_width(&text) + 6).max(3);
 
-    format_kv_pairs(handle, record);
-    writeln!(handle, "{}", "}").unwrap();
+    if colored {
+        write!(out, "{}{}", style(record).unwrap_or(""), BOLD)?;
+    }
+    write!(out, "\n─── {} {}", text, "─".repeat(rest))?;
+    if colored {
+        write!(out, "{}", RESET)?;
+    }
+    Ok(())
 }
 
 impl Log for Femme {
@@ -96,18 +1322,127 @@ impl Log for Femme {
     }
 
     fn log(&self, record: &Record<'_>) {
+        // a constant comparison, letting the compiler drop everything below
+        // when a `max_level_*` feature rules the level out
+        if record.level() > log::STATIC_MAX_LEVEL {
+            return;
+        }
+
         let level = self.module_level(record);
 
-        if record.level() <= *level {
-            // acquire stdout lock
-            let stdout = io::stdout();
-            let mut handle = stdout.lock();
+        if record.level() <= level {
+            stats::emitted(record.level());
+            self.with_fields(record, |record| {
+                recorder::record(record);
+                for sink in self
+                    .sinks
+                    .iter()
+                    .filter(|sink| sink.accepts(record.level()))
+                {
+                    sink.write(record, self);
+                }
+            });
+        }
+    }
+    fn flush(&self) {
+        for sink in &self.sinks {
+            sink.flush();
+        }
+    }
+}
 
-            match self.logger {
-                Logger::Pretty => write_pretty(&mut handle, &record),
-                Logger::NDJson => write_ndjson(&mut handle, &record),
+impl Femme {
+    /// Write a record to every sink, whatever the level filters let through
+    #[cfg(unix)]
+    pub(crate) fn log_unfiltered(&self, record: &Record<'_>) {
+        stats::emitted(record.level());
+        self.with_fields(record, |record| {
+            recorder::record(record);
+            for sink in &self.sinks {
+                sink.write(record, self);
             }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn ndjson_without_allocating() {
+        let mut femme = crate::ndjson().writer(io::sink());
+        start(&mut femme).unwrap();
+        let pairs: &[(&str, kv::Value)] = &[
+            ("method", "GET".into()),
+            ("path", "/\"quoted\"".into()),
+            ("status", 200.into()),
+            ("cached", true.into()),
+            ("ms", 1.5.into()),
+            ("retries", (-1).into()),
+            ("grade", 'A'.into()),
+            ("user", "alice".into()),
+        ];
+        let request = || {
+            femme.log(
+                &Record::builder()
+                    .level(Level::Info)
+                    .target("app")
+                    .args(format_args!("Request {}", 7))
+                    .key_values(&pairs)
+                    .build(),
+            )
+        };
+
+        // the first record sets up the reused buffers
+        request();
+        let before = testing::allocations();
+        for _ in 0..100 {
+            request();
         }
+        assert_eq!(testing::allocations(), before);
+    }
+
+    /// A writer that panics on its first write
+    struct Panicking(testing::Buffer, bool);
+
+    impl Write for Panicking {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !std::mem::replace(&mut self.1, true) {
+                panic!("first write");
+            }
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    #[test]
+    fn logs_after_a_writer_panics() {
+        if testing::compiled_out() {
+            return;
+        }
+        let buffer = testing::Buffer::default();
+        let mut femme = crate::logfmt().writer(Panicking(buffer.clone(), false));
+        start(&mut femme).unwrap();
+        let log = |message: &str| {
+            femme.log(
+                &Record::builder()
+                    .level(Level::Info)
+                    .target("app")
+                    .args(format_args!("{}", message))
+                    .build(),
+            );
+            femme.flush();
+        };
+
+        // the panic poisons the lock around the writer
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| log("first")));
+        assert!(panicked.is_err());
+        log("second");
+        assert!(buffer.text().ends_with("msg=second\n"), "{}", buffer.text());
     }
-    fn flush(&self) {}
 }
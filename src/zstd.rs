@@ -0,0 +1,348 @@
+//! Zstandard compression, with a small encoder of its own.
+//!
+//! A batch becomes a single frame of blocks of up to 128 KiB. Repeated
+//! strings are found with the hash chains of `lz77` and written as
+//! sequences coded with the predefined FSE tables of the format, while the
+//! literals between them are stored as they are; a block that doesn't get
+//! smaller is stored raw. That gets most of the size of logs without the
+//! cost of Huffman or FSE tables of its own.
+
+use crate::lz77::Matcher;
+
+/// Opens a frame
+const MAGIC: u32 = 0xfd2f_b528;
+
+/// Most bytes of a batch in a block
+const MAX_BLOCK: usize = 128 * 1024;
+
+/// Largest distance a match may reach back
+const WINDOW: usize = 256 * 1024;
+
+/// Shortest match worth a sequence
+const MIN_MATCH: usize = 4;
+
+/// Smallest literal length of each code, and its extra bits
+const LITERALS_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64,
+    128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+const LITERALS_EXTRA: [u32; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16,
+];
+
+/// Smallest match length of each code, and its extra bits
+const MATCH_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    28, 29, 30, 31, 32, 33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027,
+    2051, 4099, 8195, 16387, 32771, 65539,
+];
+const MATCH_EXTRA: [u32; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+
+/// Predefined distributions of literal length, match length and offset
+/// codes, with -1 for codes less likely than the others
+const LITERALS_DISTRIBUTION: [i8; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+];
+const MATCH_DISTRIBUTION: [i8; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OFFSET_DISTRIBUTION: [i8; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+
+/// Compress `data` into a Zstandard frame
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    let tables = [
+        Table::new(&LITERALS_DISTRIBUTION, 6),
+        Table::new(&MATCH_DISTRIBUTION, 6),
+        Table::new(&OFFSET_DISTRIBUTION, 5),
+    ];
+
+    // a single segment, whose size the header gives
+    let mut out = MAGIC.to_le_bytes().to_vec();
+    match data.len() {
+        len @ 0..=255 => out.extend_from_slice(&[0x20, len as u8]),
+        len @ 256..=65_791 => {
+            out.push(0x60);
+            out.extend_from_slice(&((len - 256) as u16).to_le_bytes());
+        }
+        len => {
+            out.push(0xe0);
+            out.extend_from_slice(&(len as u64).to_le_bytes());
+        }
+    }
+
+    let mut matcher = Matcher::new(data, WINDOW);
+    let mut start = 0;
+    loop {
+        let end = data.len().min(start + MAX_BLOCK);
+        let compressed = compress_block(data, start..end, &mut matcher, &tables);
+        let (kind, block) = match compressed.len() < end - start {
+            true => (2, &compressed[..]),
+            false => (0, &data[start..end]),
+        };
+        let last = end == data.len();
+        let header = u32::from(last) | kind << 1 | (block.len() as u32) << 3;
+        out.extend_from_slice(&header.to_le_bytes()[..3]);
+        out.extend_from_slice(block);
+        if last {
+            return out;
+        }
+        start = end;
+    }
+}
+
+/// A match following some literals
+struct Sequence {
+    literals: u32,
+    len: u32,
+
+    /// The distance back, plus 3 as the format wants
+    offset: u32,
+}
+
+/// Compress a block, the literals section followed by the sequences
+fn compress_block(
+    data: &[u8],
+    range: std::ops::Range<usize>,
+    matcher: &mut Matcher,
+    tables: &[Table; 3],
+) -> Vec<u8> {
+    let mut literals = Vec::new();
+    let mut sequences = Vec::new();
+    let mut pending = range.start;
+    let mut at = range.start;
+    while at < range.end {
+        let (len, distance) = matcher.longest(at, range.end - at);
+        if len >= MIN_MATCH {
+            literals.extend_from_slice(&data[pending..at]);
+            sequences.push(Sequence {
+                literals: (at - pending) as u32,
+                len: len as u32,
+                offset: distance as u32 + 3,
+            });
+            for at in at..at + len {
+                matcher.insert(at);
+            }
+            at += len;
+            pending = at;
+        } else {
+            matcher.insert(at);
+            at += 1;
+        }
+    }
+    literals.extend_from_slice(&data[pending..range.end]);
+
+    // raw literals, with a header of 1, 2 or 3 bytes for their size
+    let mut out = Vec::new();
+    match literals.len() {
+        len @ 0..=31 => out.push((len << 3) as u8),
+        len @ 32..=4095 => {
+            out.extend_from_slice(&[(1 << 2 | (len & 15) << 4) as u8, (len >> 4) as u8])
+        }
+        len => out.extend_from_slice(&[
+            (3 << 2 | (len & 15) << 4) as u8,
+            (len >> 4) as u8,
+            (len >> 12) as u8,
+        ]),
+    }
+    out.extend_from_slice(&literals);
+    write_sequences(&mut out, &sequences, tables);
+    out
+}
+
+/// Write the sequences section, with the predefined tables for every code
+fn write_sequences(out: &mut Vec<u8>, sequences: &[Sequence], tables: &[Table; 3]) {
+    match sequences.len() {
+        count @ 0..=127 => out.push(count as u8),
+        count @ 128..=0x7eff => out.extend_from_slice(&[(count >> 8) as u8 + 128, count as u8]),
+        count => {
+            out.push(255);
+            out.extend_from_slice(&((count - 0x7f00) as u16).to_le_bytes());
+        }
+    }
+    if sequences.is_empty() {
+        return;
+    }
+    out.push(0);
+
+    // the decoder reads the bitstream backwards, from the last bit written
+    // to the first, so the sequences go last to first, each with the
+    // transition to the state of the one after it
+    let [literals_table, match_table, offset_table] = tables;
+    let mut bits = Bits {
+        out,
+        acc: 0,
+        len: 0,
+    };
+    let mut next: Option<[usize; 3]> = None;
+    for sequence in sequences.iter().rev() {
+        let (literals_code, literals_extra, literals_bits) =
+            code(&LITERALS_BASE, &LITERALS_EXTRA, sequence.literals);
+        let (match_code, match_extra, match_bits) = code(&MATCH_BASE, &MATCH_EXTRA, sequence.len);
+        let offset_code = 31 - sequence.offset.leading_zeros();
+        let offset_extra = sequence.offset - (1 << offset_code);
+        let codes = [literals_code, match_code, offset_code as usize];
+
+        let states = match next {
+            None => [0, 1, 2].map(|i| tables[i].encode[codes[i]][0]),
+            Some(next) => {
+                let states = [0, 1, 2].map(|i| tables[i].encode[codes[i]][next[i]]);
+                for i in [2, 1, 0] {
+                    let table = &tables[i];
+                    let state = states[i];
+                    bits.put(next[i] as u32 - table.base[state], table.bits[state]);
+                }
+                states
+            }
+        };
+        bits.put(literals_extra, literals_bits);
+        bits.put(match_extra, match_bits);
+        bits.put(offset_extra, offset_code);
+        next = Some(states);
+    }
+
+    // the states of the first sequence, which the decoder starts from
+    if let Some([literals_state, match_state, offset_state]) = next {
+        bits.put(match_state as u32, match_table.log);
+        bits.put(offset_state as u32, offset_table.log);
+        bits.put(literals_state as u32, literals_table.log);
+    }
+    bits.finish();
+}
+
+/// The code of a length, its extra bits, and how many there are
+fn code(base: &[u32], extra: &[u32], value: u32) -> (usize, u32, u32) {
+    let code = base.iter().rposition(|&base| base <= value).unwrap_or(0);
+    (code, value - base[code], extra[code])
+}
+
+/// An FSE table built from a predefined distribution, as decoders build it
+struct Table {
+    /// Bits of a state
+    log: u32,
+
+    /// Bits the decoder reads after each state, and the state they're added
+    /// to
+    bits: Vec<u32>,
+    base: Vec<u32>,
+
+    /// For each code, the state that decodes it and leads to each state
+    encode: Vec<Vec<usize>>,
+}
+
+impl Table {
+    fn new(distribution: &[i8], log: u32) -> Self {
+        let size = 1 << log;
+        let mut symbols = vec![0; size];
+
+        // codes less likely than the others take the last states, and the
+        // others are spread over the rest
+        let mut high = size - 1;
+        for (code, _) in distribution.iter().enumerate().filter(|(_, &p)| p == -1) {
+            symbols[high] = code;
+            high -= 1;
+        }
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut position = 0;
+        for (code, &p) in distribution.iter().enumerate() {
+            for _ in 0..p.max(0) {
+                symbols[position] = code;
+                position = (position + step) & (size - 1);
+                while position > high {
+                    position = (position + step) & (size - 1);
+                }
+            }
+        }
+
+        let mut next: Vec<u32> = distribution.iter().map(|&p| p.max(1) as u32).collect();
+        let mut bits = vec![0; size];
+        let mut base = vec![0; size];
+        let mut encode = vec![vec![0; size]; distribution.len()];
+        for (state, &symbol) in symbols.iter().enumerate() {
+            let n = next[symbol];
+            next[symbol] += 1;
+            bits[state] = log - (31 - n.leading_zeros());
+            base[state] = (n << bits[state]) - size as u32;
+            let from = base[state] as usize;
+            for slot in &mut encode[symbol][from..from + (1 << bits[state])] {
+                *slot = state;
+            }
+        }
+        Table {
+            log,
+            bits,
+            base,
+            encode,
+        }
+    }
+}
+
+/// Bits written least significant first, ended by a single set bit
+struct Bits<'a> {
+    out: &'a mut Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl Bits<'_> {
+    fn put(&mut self, value: u32, len: u32) {
+        self.acc |= u64::from(value) << self.len;
+        self.len += len;
+        while self.len >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    fn finish(mut self) {
+        self.put(1, 1);
+        if self.len > 0 {
+            self.out.push(self.acc as u8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hex;
+
+    // checked with `zstd -d`
+    #[test]
+    fn vectors() {
+        assert_eq!(hex(&compress(b"")), "28b52ffd2000010000");
+        assert_eq!(
+            hex(&compress(
+                b"GET /users 200\nGET /users 200\nGET /users/7 404\n"
+            )),
+            "28b52ffd202fe50000b0474554202f7573657273203230300a2f37203430\
+             340a0100b2cc3a"
+        );
+    }
+
+    #[test]
+    fn frame_sizes() {
+        // the frame content size in each of its widths, and more than a block
+        for (len, header) in [(255, 0x20), (256, 0x60), (65_791, 0x60), (65_792, 0xe0)] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 64) as u8).collect();
+            let frame = compress(&data);
+            assert_eq!(frame[..5], [0x28, 0xb5, 0x2f, 0xfd, header]);
+            assert!(
+                frame.len() < len / 2,
+                "{} bytes compressed to {}",
+                len,
+                frame.len()
+            );
+        }
+        let data: Vec<u8> = (0..3 * MAX_BLOCK).map(|i| (i % 64) as u8).collect();
+        assert!(compress(&data).len() < MAX_BLOCK / 10);
+    }
+}